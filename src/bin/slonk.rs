@@ -28,6 +28,10 @@ use slonk::{server::RaspberryPi, ControllerError};
 /// The second argument to this executable is a path to a directory where log files should be
 /// created.
 /// If the directory does not exist, it will be created.
+///
+/// Alternatively, if the first argument is `dry-run`, the second argument is the path to a
+/// configuration file, and the timelines of the sequences named by any further arguments are
+/// printed without touching any hardware.
 fn main() -> Result<(), ControllerError> {
     slonk::server::run::<RaspberryPi>()
 }
@@ -0,0 +1,389 @@
+/*
+  slonk, a rocket engine controller.
+  Copyright (C) 2022 Rice Eclipse.
+
+  slonk is free software: you can redistribute it and/or modify
+  it under the terms of the GNU General Public License as published by
+  the Free Software Foundation, either version 3 of the License, or
+  (at your option) any later version.
+
+  slonk is distributed in the hope that it will be useful,
+  but WITHOUT ANY WARRANTY; without even the implied warranty of
+  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+  GNU General Public License for more details.
+
+  You should have received a copy of the GNU General Public License
+  along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Calibration functions, which convert raw ADC readings into meaningful sensor values.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(tag = "type")]
+/// A function which converts the raw ADC reading of a sensor into its calibrated value.
+pub enum Calibration {
+    /// A linear calibration, `y = mx + b`.
+    Linear {
+        /// The slope of the calibration, `m`.
+        slope: f64,
+        /// The intercept of the calibration, `b`.
+        intercept: f64,
+    },
+    /// A polynomial calibration, `y = c0 + c1 x + c2 x^2 + ...`.
+    Polynomial {
+        /// The coefficients of the polynomial, starting from the constant term.
+        coefficients: Vec<f64>,
+    },
+    /// A piecewise-linear lookup table.
+    /// Readings between two points are linearly interpolated, and readings outside the table are
+    /// extrapolated from the nearest pair of points.
+    Table {
+        /// The points of the table, each a pair of a raw reading and its calibrated value.
+        /// The raw readings must be strictly increasing.
+        points: Vec<(f64, f64)>,
+    },
+    /// A thermocouple, whose calibrated value is its hot-junction temperature in degrees Celsius.
+    Thermocouple {
+        /// The type of the thermocouple.
+        kind: ThermocoupleKind,
+        /// The label of the sensor measuring the cold-junction temperature, in degrees Celsius.
+        cold_junction: String,
+        /// The slope of the linear conversion from raw reading to thermocouple voltage, in
+        /// millivolts per count.
+        slope: f64,
+        /// The intercept of the linear conversion from raw reading to thermocouple voltage, in
+        /// millivolts.
+        intercept: f64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+/// The types of thermocouple which are supported, each with its NIST ITS-90 reference functions.
+pub enum ThermocoupleKind {
+    /// A type K (chromel-alumel) thermocouple, valid from -200 to 1372 degrees Celsius.
+    K,
+    /// A type T (copper-constantan) thermocouple, valid from -200 to 400 degrees Celsius.
+    T,
+}
+
+impl Calibration {
+    /// Determine whether this calibration is well-formed.
+    /// Polynomials must have at least one coefficient, and tables must have at least two points
+    /// with strictly increasing raw readings.
+    /// Whether the cold junction of a thermocouple exists is left to the configuration.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Calibration::Polynomial { coefficients } => !coefficients.is_empty(),
+            Calibration::Table { points } => {
+                points.len() >= 2 && points.windows(2).all(|pair| pair[0].0 < pair[1].0)
+            }
+            Calibration::Linear { .. } | Calibration::Thermocouple { .. } => true,
+        }
+    }
+
+    /// Get the label of the sensor whose value this calibration depends on, if any.
+    pub fn cold_junction(&self) -> Option<&str> {
+        match self {
+            Calibration::Thermocouple { cold_junction, .. } => Some(cold_junction),
+            _ => None,
+        }
+    }
+
+    /// Convert the raw reading `reading` into a calibrated value.
+    /// `cold_junction` is the most recent value of the sensor named by `cold_junction()`, if this
+    /// calibration has one.
+    ///
+    /// Returns `None` if the value cannot be calibrated because the cold-junction temperature is
+    /// not known.
+    pub fn apply(&self, reading: u16, cold_junction: Option<f64>) -> Option<f64> {
+        let x = f64::from(reading);
+        match self {
+            Calibration::Linear { slope, intercept } => Some(x * slope + intercept),
+            Calibration::Polynomial { coefficients } => Some(horner(coefficients, x)),
+            Calibration::Table { points } => {
+                // the first segment which ends after the reading, or the last one if none does
+                let end = points
+                    .iter()
+                    .position(|&(raw, _)| x <= raw)
+                    .unwrap_or(points.len() - 1)
+                    .max(1);
+                let (x0, y0) = points[end - 1];
+                let (x1, y1) = points[end];
+                Some(y0 + (x - x0) * (y1 - y0) / (x1 - x0))
+            }
+            Calibration::Thermocouple {
+                kind,
+                slope,
+                intercept,
+                ..
+            } => {
+                let emf = x * slope + intercept + kind.emf(cold_junction?);
+                Some(kind.temperature(emf))
+            }
+        }
+    }
+}
+
+impl ThermocoupleKind {
+    /// Compute the voltage, in millivolts, of a thermocouple of this kind whose hot junction is at
+    /// `temperature` degrees Celsius and whose cold junction is at 0 degrees Celsius.
+    fn emf(self, temperature: f64) -> f64 {
+        match self {
+            ThermocoupleKind::K if temperature < 0.0 => horner(&TYPE_K_EMF_NEGATIVE, temperature),
+            ThermocoupleKind::K => {
+                let [a0, a1, a2] = TYPE_K_EMF_EXPONENTIAL;
+                horner(&TYPE_K_EMF_POSITIVE, temperature)
+                    + a0 * (a1 * (temperature - a2).powi(2)).exp()
+            }
+            ThermocoupleKind::T if temperature < 0.0 => horner(&TYPE_T_EMF_NEGATIVE, temperature),
+            ThermocoupleKind::T => horner(&TYPE_T_EMF_POSITIVE, temperature),
+        }
+    }
+
+    /// Compute the hot-junction temperature, in degrees Celsius, of a thermocouple of this kind
+    /// whose voltage is `emf` millivolts with its cold junction at 0 degrees Celsius.
+    /// Voltages outside the valid range are extrapolated from the nearest inverse function.
+    fn temperature(self, emf: f64) -> f64 {
+        let coefficients: &[f64] = match self {
+            ThermocoupleKind::K if emf < 0.0 => &TYPE_K_TEMPERATURE_NEGATIVE,
+            ThermocoupleKind::K if emf < 20.644 => &TYPE_K_TEMPERATURE_LOW,
+            ThermocoupleKind::K => &TYPE_K_TEMPERATURE_HIGH,
+            ThermocoupleKind::T if emf < 0.0 => &TYPE_T_TEMPERATURE_NEGATIVE,
+            ThermocoupleKind::T => &TYPE_T_TEMPERATURE_POSITIVE,
+        };
+        horner(coefficients, emf)
+    }
+}
+
+/// Evaluate the polynomial with `coefficients` (starting from the constant term) at `x`.
+fn horner(coefficients: &[f64], x: f64) -> f64 {
+    coefficients.iter().rev().fold(0.0, |acc, c| acc * x + c)
+}
+
+// The coefficients below are the NIST ITS-90 thermocouple reference functions, copied verbatim from
+// NIST Monograph 175.
+
+#[allow(clippy::unreadable_literal)]
+/// Type K voltage (mV) from temperature (degrees C), from -270 to 0 degrees C.
+const TYPE_K_EMF_NEGATIVE: [f64; 11] = [
+    0.0,
+    0.394501280250E-01,
+    0.236223735980E-04,
+    -0.328589067840E-06,
+    -0.499048287770E-08,
+    -0.675090591730E-10,
+    -0.574103274280E-12,
+    -0.310888728940E-14,
+    -0.104516093650E-16,
+    -0.198892668780E-19,
+    -0.163226974860E-22,
+];
+
+#[allow(clippy::unreadable_literal)]
+/// Type K voltage (mV) from temperature (degrees C), from 0 to 1372 degrees C, excluding the
+/// exponential term.
+const TYPE_K_EMF_POSITIVE: [f64; 10] = [
+    -0.176004136860E-01,
+    0.389212049750E-01,
+    0.185587700320E-04,
+    -0.994575928740E-07,
+    0.318409457190E-09,
+    -0.560728448890E-12,
+    0.560750590590E-15,
+    -0.320207200030E-18,
+    0.971511471520E-22,
+    -0.121047212750E-25,
+];
+
+#[allow(clippy::unreadable_literal)]
+/// The coefficients `a0`, `a1` and `a2` of the exponential term `a0 exp(a1 (t - a2)^2)` of the
+/// type K voltage above 0 degrees C.
+const TYPE_K_EMF_EXPONENTIAL: [f64; 3] =
+    [0.118597600000E+00, -0.118343200000E-03, 0.126968600000E+03];
+
+#[allow(clippy::unreadable_literal)]
+/// Type K temperature (degrees C) from voltage (mV), from -5.891 to 0 mV.
+const TYPE_K_TEMPERATURE_NEGATIVE: [f64; 9] = [
+    0.0,
+    2.5173462E+01,
+    -1.1662878E+00,
+    -1.0833638E+00,
+    -8.9773540E-01,
+    -3.7342377E-01,
+    -8.6632643E-02,
+    -1.0450598E-02,
+    -5.1920577E-04,
+];
+
+#[allow(clippy::unreadable_literal)]
+/// Type K temperature (degrees C) from voltage (mV), from 0 to 20.644 mV.
+const TYPE_K_TEMPERATURE_LOW: [f64; 10] = [
+    0.0,
+    2.508355E+01,
+    7.860106E-02,
+    -2.503131E-01,
+    8.315270E-02,
+    -1.228034E-02,
+    9.804036E-04,
+    -4.413030E-05,
+    1.057734E-06,
+    -1.052755E-08,
+];
+
+#[allow(clippy::unreadable_literal)]
+/// Type K temperature (degrees C) from voltage (mV), from 20.644 to 54.886 mV.
+const TYPE_K_TEMPERATURE_HIGH: [f64; 7] = [
+    -1.318058E+02,
+    4.830222E+01,
+    -1.646031E+00,
+    5.464731E-02,
+    -9.650715E-04,
+    8.802193E-06,
+    -3.110810E-08,
+];
+
+#[allow(clippy::unreadable_literal)]
+/// Type T voltage (mV) from temperature (degrees C), from -270 to 0 degrees C.
+const TYPE_T_EMF_NEGATIVE: [f64; 15] = [
+    0.0,
+    0.387481063640E-01,
+    0.441944343470E-04,
+    0.118443231050E-06,
+    0.200329735540E-07,
+    0.901380195590E-09,
+    0.226511565930E-10,
+    0.360711542050E-12,
+    0.384939398830E-14,
+    0.282135219250E-16,
+    0.142515947790E-18,
+    0.487686622860E-21,
+    0.107955392700E-23,
+    0.139450270620E-26,
+    0.797951539270E-30,
+];
+
+#[allow(clippy::unreadable_literal)]
+/// Type T voltage (mV) from temperature (degrees C), from 0 to 400 degrees C.
+const TYPE_T_EMF_POSITIVE: [f64; 9] = [
+    0.0,
+    0.387481063640E-01,
+    0.332922278800E-04,
+    0.206182434040E-06,
+    -0.218822568460E-08,
+    0.109968809280E-10,
+    -0.308157587720E-13,
+    0.454791352900E-16,
+    -0.275129016730E-19,
+];
+
+#[allow(clippy::unreadable_literal)]
+/// Type T temperature (degrees C) from voltage (mV), from -5.603 to 0 mV.
+const TYPE_T_TEMPERATURE_NEGATIVE: [f64; 8] = [
+    0.0,
+    2.5949192E+01,
+    -2.1316967E-01,
+    7.9018692E-01,
+    4.2527777E-01,
+    1.3304473E-01,
+    2.0241446E-02,
+    1.2668171E-03,
+];
+
+#[allow(clippy::unreadable_literal)]
+/// Type T temperature (degrees C) from voltage (mV), from 0 to 20.872 mV.
+const TYPE_T_TEMPERATURE_POSITIVE: [f64; 7] = [
+    0.0,
+    2.592800E+01,
+    -7.602961E-01,
+    4.637791E-02,
+    -2.165394E-03,
+    6.048144E-05,
+    -7.293422E-07,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Assert that `actual` is within `tolerance` of `expected`.
+    fn assert_near(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "expected {expected} (within {tolerance}), got {actual}"
+        );
+    }
+
+    #[test]
+    /// Test that linear and polynomial calibrations are evaluated correctly.
+    fn polynomial() {
+        let linear = Calibration::Linear {
+            slope: 2.0,
+            intercept: -1.0,
+        };
+        assert_eq!(linear.apply(10, None), Some(19.0));
+
+        let quadratic = Calibration::Polynomial {
+            coefficients: vec![1.0, 0.5, 0.25],
+        };
+        assert_eq!(quadratic.apply(4, None), Some(7.0));
+    }
+
+    #[test]
+    /// Test that tables interpolate between their points and extrapolate beyond them.
+    fn table() {
+        let table = Calibration::Table {
+            points: vec![(100.0, 0.0), (200.0, 10.0), (400.0, 30.0)],
+        };
+        assert!(table.is_valid());
+        assert_eq!(table.apply(150, None), Some(5.0));
+        assert_eq!(table.apply(200, None), Some(10.0));
+        assert_eq!(table.apply(300, None), Some(20.0));
+        assert_eq!(table.apply(0, None), Some(-10.0));
+        assert_eq!(table.apply(500, None), Some(40.0));
+
+        assert!(!Calibration::Table {
+            points: vec![(100.0, 0.0)]
+        }
+        .is_valid());
+        assert!(!Calibration::Table {
+            points: vec![(100.0, 0.0), (100.0, 1.0)]
+        }
+        .is_valid());
+    }
+
+    #[test]
+    /// Test the thermocouple reference functions against values from the NIST tables.
+    fn reference_functions() {
+        for (kind, temperature, emf) in [
+            (ThermocoupleKind::K, -100.0, -3.554),
+            (ThermocoupleKind::K, 20.0, 0.798),
+            (ThermocoupleKind::K, 100.0, 4.096),
+            (ThermocoupleKind::K, 1000.0, 41.276),
+            (ThermocoupleKind::T, -100.0, -3.379),
+            (ThermocoupleKind::T, 100.0, 4.279),
+            (ThermocoupleKind::T, 300.0, 14.862),
+        ] {
+            assert_near(kind.emf(temperature), emf, 0.001);
+            assert_near(kind.temperature(emf), temperature, 0.1);
+        }
+    }
+
+    #[test]
+    /// Test that a thermocouple is compensated by its cold-junction temperature.
+    fn cold_junction() {
+        let thermocouple = Calibration::Thermocouple {
+            kind: ThermocoupleKind::K,
+            cold_junction: "TC_CJ".into(),
+            slope: 0.001,
+            intercept: 0.0,
+        };
+        assert_eq!(thermocouple.cold_junction(), Some("TC_CJ"));
+
+        // 3.298 mV on top of a 20 degree cold junction makes 4.096 mV, which is 100 degrees
+        assert_near(thermocouple.apply(3298, Some(20.0)).unwrap(), 100.0, 0.1);
+        // without a cold-junction temperature, there is nothing to compensate with
+        assert_eq!(thermocouple.apply(3298, None), None);
+    }
+}
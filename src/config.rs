@@ -18,13 +18,23 @@
 
 //! Loading and validating configurations for the engine controller.
 
-use std::{collections::HashSet, io::Read, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Read,
+    time::Duration,
+};
 
 use std::fmt::Display;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::hardware::{ListenerPin, Mcp3208};
+use crate::{
+    calibration::Calibration,
+    console::LogLevel,
+    expression::Expression,
+    hardware::{ListenerPin, Mcp3208},
+    state::State,
+};
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 /// A configuration for the entire engine controller.
@@ -54,10 +64,38 @@ pub struct Configuration {
     /// During pre-ignition, the sensor logging rate is fast, but there
     /// (should) be no more oxidizer flow and ignition should have stopped.
     pub post_ignite_time: u32,
+    /// The policy for arming the controller before an ignition.
+    #[serde(default)]
+    pub arming: ArmingPolicy,
+    /// The T-minus countdown to be run before pre-ignition.
+    /// If `None`, an ignition command moves straight into pre-ignition.
+    #[serde(default)]
+    pub countdown: Option<Countdown>,
+    /// The go/no-go checks which must all pass before the controller enters pre-ignition.
+    #[serde(default)]
+    pub checks: Vec<Check>,
+    /// The rules restricting which combinations of drivers may be actuated.
+    #[serde(default)]
+    pub interlocks: Vec<Interlock>,
+    /// The rules which trigger an emergency stop when the sensors show that something has gone
+    /// wrong.
+    #[serde(default)]
+    pub abort_rules: Vec<AbortRule>,
     /// The sequence of actions to be performed during ignition.
     pub ignition_sequence: Vec<Action>,
     /// The sequence of actions to be performed during emergency stop.
     pub estop_sequence: Vec<Action>,
+    /// A set of named auxiliary sequences of actions, which can be referred to by name elsewhere in
+    /// the configuration.
+    #[serde(default)]
+    pub sequences: HashMap<String, Vec<Action>>,
+    /// The policy for what the controller should do when it loses contact with the dashboard.
+    #[serde(default)]
+    pub on_disconnect: DisconnectPolicy,
+    /// The minimum level of user log entry which will be forwarded to the dashboard, or `None` if
+    /// the user log should not be forwarded at all.
+    #[serde(default)]
+    pub log_level_dashboard: Option<LogLevel>,
     /// The Master Output / Slave Input pin ID for the SPI bus.
     pub spi_mosi: u8,
     /// The Master Input / Slave Output pin ID for the SPI bus.
@@ -89,22 +127,292 @@ pub struct Driver {
     pub protected: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(untagged)]
+/// A reference to a driver, which may be given either as its identifier (i.e. index) or as its
+/// label.
+///
+/// Once a configuration is parsed, every reference inside it has been resolved into an identifier,
+/// so reordering the drivers cannot silently change which driver a reference points to.
+pub enum DriverRef {
+    /// The identifier (i.e. index) of the driver.
+    Id(u8),
+    /// The label of the driver.
+    Label(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 #[serde(tag = "type")]
 /// The set of actions that can be taken in an ignition or shutoff sequence.
 pub enum Action {
     /// Actuate a driver to a logic level.
     Actuate {
-        /// The identifier (i.e. index) of the driver to be actuated.
-        driver_id: u8,
+        /// The driver to be actuated.
+        driver_id: DriverRef,
         /// The logic level that the driver will be actuated to.
         value: bool,
     },
+    /// Actuate a driver to `true` for a given amount of time, and then return it to the level it
+    /// was at before.
+    /// The rest of the procedure carries on while the driver is pulsed, but the procedure does not
+    /// finish until every pulse has ended.
+    Pulse {
+        /// The driver to be pulsed.
+        driver_id: DriverRef,
+        /// The amount of time for which the driver is actuated.
+        duration: Duration,
+    },
     /// Sleep for a given amount of time.
     Sleep {
         /// The amount of time to sleep for.
         duration: Duration,
     },
+    /// Wait until the calibrated value of a sensor crosses a threshold.
+    WaitUntil {
+        /// The label of the sensor to be watched.
+        sensor: String,
+        /// The side of the threshold which the sensor's value must be on.
+        condition: Condition,
+        /// The threshold which the sensor's value is compared against.
+        threshold: f64,
+        /// The maximum amount of time to wait for the condition to hold.
+        timeout: Duration,
+        /// What to do if the condition does not hold before the timeout.
+        #[serde(default)]
+        on_timeout: TimeoutAction,
+    },
+    /// Perform several tracks of steps concurrently, finishing once every track has finished.
+    /// Every track starts at the same time, and is scheduled against the same clock.
+    /// A track may not contain another `Parallel` step.
+    Parallel {
+        /// The tracks to be performed, each of which is a sequence of steps.
+        tracks: Vec<Vec<Action>>,
+    },
+}
+
+impl Action {
+    /// Iterate over this step, followed by every step in its tracks if it is a `Parallel` step.
+    /// Since `Parallel` steps cannot be nested, this covers every step which could be performed as
+    /// part of this one.
+    pub fn with_substeps(&self) -> impl Iterator<Item = &Action> {
+        let tracks: &[Vec<Action>] = match self {
+            Action::Parallel { tracks } => tracks,
+            _ => &[],
+        };
+        std::iter::once(self).chain(tracks.iter().flatten())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(default)]
+/// A policy for arming the controller.
+///
+/// An ignition may only be started while the controller is armed, and the controller may only be
+/// armed from standby.
+pub struct ArmingPolicy {
+    /// The amount of time (in milliseconds) for which the controller stays armed.
+    /// If no ignition is started in this time, the controller returns to standby.
+    pub timeout: u32,
+    /// The GPIO pin ID of an arm-key input.
+    /// If given, the controller can only be armed while this pin reads high.
+    pub key_pin: Option<u8>,
+}
+
+impl Default for ArmingPolicy {
+    fn default() -> Self {
+        ArmingPolicy {
+            timeout: 30_000,
+            key_pin: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// A T-minus countdown, which is run after an ignition command is accepted and before the
+/// controller moves into pre-ignition.
+/// The countdown may be held, resumed, or recycled back to standby by the dashboard.
+pub struct Countdown {
+    /// The length of the countdown, in milliseconds.
+    pub length: u32,
+    /// The events which are performed at given times during the countdown.
+    #[serde(default)]
+    pub events: Vec<CountdownEvent>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// A set of actions which are performed at a given time during a countdown.
+pub struct CountdownEvent {
+    /// The time (in milliseconds) before the end of the countdown at which this event occurs.
+    pub t_minus: u32,
+    /// The actions to perform.
+    /// Only `Actuate` actions are allowed, so that an event can never stop the clock.
+    pub actions: Vec<Action>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(tag = "type")]
+/// A rule restricting when a driver may be actuated.
+///
+/// Interlocks are checked every time a driver is actuated, whether by the dashboard or by a
+/// procedure, except during an emergency stop.
+/// Only actuating a driver to `true` can break an interlock.
+pub enum Interlock {
+    /// At most one of the drivers may be `true` at a time.
+    Exclusive {
+        /// The drivers which exclude one another.
+        drivers: Vec<DriverRef>,
+    },
+    /// The driver may only be actuated to `true` while the controller is in one of the states.
+    OnlyInStates {
+        /// The driver which is restricted.
+        driver_id: DriverRef,
+        /// The states in which the driver may be actuated to `true`.
+        states: Vec<State>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+/// A rule which triggers an emergency stop when its condition holds for long enough.
+///
+/// Each rule latches when it trips, so that it triggers only a single emergency stop.
+/// The latch is released once the controller is in standby and the condition no longer holds.
+pub struct AbortRule {
+    /// The name of the rule, used when logging that it tripped.
+    pub name: String,
+    /// The condition which must hold for the rule to trip.
+    pub condition: AbortCondition,
+    /// The number of milliseconds for which the condition must hold continuously before the rule
+    /// trips.
+    #[serde(default)]
+    pub persistence: u32,
+    /// The states in which the rule is active.
+    /// If `None`, the rule is active in every state.
+    #[serde(default)]
+    pub states: Option<Vec<State>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(tag = "type")]
+/// The condition of an abort rule, which is evaluated on the filtered values of sensors.
+pub enum AbortCondition {
+    /// A sensor is outside of a window.
+    OutOfRange {
+        /// The label of the sensor.
+        sensor: String,
+        /// The minimum allowable value of the sensor.
+        min: f64,
+        /// The maximum allowable value of the sensor.
+        max: f64,
+    },
+    /// At least `votes` of a set of redundant sensors are outside of a window.
+    Vote {
+        /// The labels of the sensors.
+        sensors: Vec<String>,
+        /// The minimum allowable value of each sensor.
+        min: f64,
+        /// The maximum allowable value of each sensor.
+        max: f64,
+        /// The number of sensors which must be out of the window for the condition to hold.
+        votes: usize,
+    },
+    /// A sensor is changing faster than a limit.
+    RateOfChange {
+        /// The label of the sensor.
+        sensor: String,
+        /// The maximum allowable magnitude of the rate of change of the sensor, in units per
+        /// second.
+        max_rate: f64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "type")]
+/// A go/no-go check, which is evaluated just before the controller enters pre-ignition.
+/// If any check fails, the ignition is called off.
+pub enum Check {
+    /// The calibrated value of a sensor must be within a window.
+    SensorInRange {
+        /// The label of the sensor to be checked.
+        sensor: String,
+        /// The minimum allowable value of the sensor.
+        min: f64,
+        /// The maximum allowable value of the sensor.
+        max: f64,
+    },
+    /// A driver must be at a given logic level.
+    DriverValue {
+        /// The driver to be checked.
+        driver_id: DriverRef,
+        /// The logic level that the driver must be at.
+        value: bool,
+    },
+    /// No sensor may have failed to be read in any of its most recent samples.
+    NoReadErrors {
+        /// The number of recent samples of each sensor to check.
+        /// May be no more than 64.
+        samples: u8,
+    },
+    /// A dashboard must be connected and in command of the controller.
+    DashboardConnected,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+/// A comparison between a sensor value and a threshold.
+pub enum Condition {
+    /// The value must be strictly greater than the threshold.
+    Above,
+    /// The value must be strictly less than the threshold.
+    Below,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default, Clone, Copy)]
+/// The set of things which can be done when a `WaitUntil` action times out.
+pub enum TimeoutAction {
+    /// Abandon the rest of the sequence and perform an emergency stop.
+    #[default]
+    Abort,
+    /// Carry on with the rest of the sequence as if the condition had held.
+    Continue,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+/// A policy for what to do when the controller loses contact with the dashboard.
+///
+/// Contact is lost either when the dashboard disconnects, or when the dashboard has not sent any
+/// message (including a `Ping`) for too long during a countdown, pre-ignition or ignition.
+pub struct DisconnectPolicy {
+    /// The action to take when contact with the dashboard is lost.
+    #[serde(default)]
+    pub action: DisconnectAction,
+    /// The amount of time (in milliseconds) to wait after the dashboard disconnects before taking
+    /// the action.
+    /// If the dashboard reconnects before the grace period is over, no action will be taken.
+    #[serde(default)]
+    pub grace_period: u32,
+    /// The maximum amount of time (in milliseconds) that may pass without receiving any message from
+    /// the dashboard while counting down, in pre-ignition, or in ignition.
+    /// If this much time passes, the action will be taken even though the dashboard is still
+    /// connected.
+    /// If `None`, no such timeout will be enforced.
+    #[serde(default)]
+    pub timeout: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+#[serde(tag = "type")]
+/// The set of actions which can be taken when contact with the dashboard is lost.
+pub enum DisconnectAction {
+    /// Do nothing, and keep running in whatever state the controller was already in.
+    #[default]
+    Nothing,
+    /// Perform an emergency stop, running the configured `estop_sequence`.
+    EmergencyStop,
+    /// Perform an emergency stop, but run a named sequence from `sequences` instead of the
+    /// `estop_sequence`.
+    Sequence {
+        /// The name of the sequence to run.
+        name: String,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -121,6 +429,31 @@ pub struct SensorGroup {
     pub frequency_transmission: u32,
     /// The set of sensors managed by this sensor group.
     pub sensors: Vec<Sensor>,
+    #[serde(default)]
+    /// The channels computed by this sensor group from the values of other sensors.
+    /// Each derived channel is numbered as if it were a sensor following every real sensor in the
+    /// group.
+    pub derived: Vec<Derived>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+/// The ways in which the calibration of a sensor may be written in a configuration.
+enum SensorCalibration {
+    /// Any calibration function, in the field `calibration`.
+    Current { calibration: Calibration },
+    /// A linear calibration, in the fields used before other calibrations were supported.
+    Legacy {
+        calibration_slope: f64,
+        calibration_intercept: f64,
+    },
+}
+
+#[derive(Serialize)]
+/// The calibration of a sensor, as it is written out.
+struct CalibrationField<'a> {
+    /// The calibration function.
+    calibration: &'a Calibration,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -132,17 +465,28 @@ pub struct Sensor {
     pub color: String,
     /// The units of the sensor's calibrated value.
     pub units: String,
-    /// The minimum and maximum allowable range of values that the calibrated
-    /// value of this sensor can take be allowed to take on until an estop is triggered.
-    pub range: Option<(f64, f64)>,
-    /// The intercept of the linear calibration function for this sensor.
-    /// In the expression of the calibration function `y = mx + b`, this would be `b`.
-    pub calibration_intercept: f64,
-    /// The slope of the linear calibration function for this sensor.
-    /// In the expression of the calibration function `y = mx + b`, this would be `m`.
-    pub calibration_slope: f64,
-    /// The width of a rolling average for this device, used to filter data on the controller side.
-    pub rolling_average_width: Option<u32>,
+    /// The limits on the filtered value of this sensor, outside of which an estop is triggered.
+    #[serde(alias = "range")]
+    pub abort_range: Option<Limits>,
+    /// The limits on the filtered value of this sensor, outside of which a warning is raised.
+    pub warn_range: Option<Limits>,
+    /// How far back inside its limits the filtered value of this sensor must come before an alarm
+    /// is cleared.
+    #[serde(default)]
+    pub hysteresis: f64,
+    /// The function converting raw readings from this sensor into calibrated values.
+    /// Older configurations instead give a linear calibration as the fields `calibration_slope`
+    /// and `calibration_intercept`, which are still accepted.
+    #[serde(
+        flatten,
+        serialize_with = "serialize_calibration",
+        deserialize_with = "deserialize_calibration"
+    )]
+    pub calibration: Calibration,
+    #[serde(default)]
+    /// The filters applied, in order, to the calibrated values of this sensor before they are
+    /// checked against its limits.
+    pub filters: Vec<Filter>,
     /// The ID of the ADC used by this device.
     /// This maps to the field `adc_cs` in `Configuration`.
     /// For instance, if the value of `adc` is 2, and `adc[2]` is 33, then this sensor uses the ADC
@@ -152,6 +496,90 @@ pub struct Sensor {
     pub channel: u8,
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// Information needed to define a derived channel, which is computed from the values of other
+/// sensors rather than read from an ADC.
+pub struct Derived {
+    /// The label giving the name of the channel.
+    pub label: String,
+    /// The color that this channel should be displayed with.
+    pub color: String,
+    /// The units of the channel's value.
+    pub units: String,
+    /// The expression computing the value of this channel from the calibrated values of sensors.
+    pub expression: Expression,
+    /// The limits on the filtered value of this channel, outside of which an estop is triggered.
+    pub abort_range: Option<Limits>,
+    /// The limits on the filtered value of this channel, outside of which a warning is raised.
+    pub warn_range: Option<Limits>,
+    /// How far back inside its limits the filtered value of this channel must come before an
+    /// alarm is cleared.
+    #[serde(default)]
+    pub hysteresis: f64,
+    #[serde(default)]
+    /// The filters applied, in order, to the values of this channel before they are checked
+    /// against its limits.
+    pub filters: Vec<Filter>,
+}
+
+#[derive(Clone, Copy, Debug)]
+/// The parts of a sensor or derived channel which are needed to check its values, whichever kind
+/// of channel it is.
+pub struct Channel<'a> {
+    /// The label of the channel.
+    pub label: &'a str,
+    /// The filters applied to the values of the channel.
+    pub filters: &'a [Filter],
+    /// The limits outside of which an estop is triggered.
+    pub abort_range: Option<&'a Limits>,
+    /// The limits outside of which a warning is raised.
+    pub warn_range: Option<&'a Limits>,
+    /// How far back inside its limits the channel must come before an alarm is cleared.
+    pub hysteresis: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(untagged)]
+/// The minimum and maximum allowable values of a sensor, either in every state or per state.
+pub enum Limits {
+    /// The same limits apply in every state.
+    Always((f64, f64)),
+    /// Each state has its own limits.
+    /// In states which are not listed, there are no limits.
+    PerState(HashMap<State, (f64, f64)>),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(tag = "type")]
+/// A filter which smooths the calibrated values of a sensor.
+pub enum Filter {
+    /// The mean of the most recent `width` samples.
+    MovingAverage {
+        /// The number of samples to average.
+        width: usize,
+    },
+    /// The median of the most recent `width` samples, which rejects short spikes.
+    Median {
+        /// The number of samples to take the median of.
+        width: usize,
+    },
+    /// A first-order (RC) low-pass filter.
+    LowPass {
+        /// The cutoff frequency of the filter, in hertz.
+        cutoff: f64,
+    },
+    /// A second-order Butterworth low-pass filter.
+    Butterworth {
+        /// The cutoff frequency of the filter, in hertz.
+        cutoff: f64,
+    },
+    /// An exponential moving average, `y = alpha * x + (1 - alpha) * y`.
+    Exponential {
+        /// The weight given to each new sample, between 0 (exclusive) and 1 (inclusive).
+        alpha: f64,
+    },
+}
+
 #[derive(Debug)]
 /// The set of errors that can occur when validating a configuration.
 pub enum Error {
@@ -165,7 +593,43 @@ pub enum Error {
     /// The SPI clock frequency was set too slow.
     ClockTooSlow,
     /// A procedure references a driver which does not exist.
-    NoSuchDriver(u8),
+    NoSuchDriver(DriverRef),
+    /// The configuration refers to a named sequence which does not exist.
+    NoSuchSequence(String),
+    /// A procedure or calibration refers to a sensor label which does not exist.
+    NoSuchSensor(String),
+    /// The emergency stop sequence contains a `WaitUntil` action which aborts on timeout.
+    /// There is nothing to abort into from an emergency stop.
+    AbortInEstop,
+    /// A countdown event is scheduled for a T-minus time before the countdown starts.
+    /// The value is the `t_minus` of the event.
+    EarlyCountdownEvent(u32),
+    /// A countdown event contains an action other than `Actuate`, which could stop the clock.
+    BlockingCountdownAction,
+    /// A `Parallel` step contains another `Parallel` step in one of its tracks.
+    NestedParallel,
+    /// A `NoReadErrors` check asks for more samples than are remembered.
+    /// The value is the number of samples requested.
+    TooManySamples(u8),
+    /// A sensor's calibration is malformed, or its cold junction is not a plain sensor.
+    /// The value is the label of the sensor.
+    BadCalibration(String),
+    /// A sensor's filter has a width of zero, a cutoff frequency which is not positive, or an
+    /// alpha outside of (0, 1].
+    /// The value is the label of the sensor.
+    BadFilter(String),
+    /// A sensor's limits have a minimum above their maximum, or its hysteresis is negative.
+    /// The value is the label of the sensor.
+    BadLimits(String),
+    /// A derived channel refers to another derived channel.
+    /// The value is the label of the channel.
+    BadDerived(String),
+    /// A voting abort rule asks for no votes, or for more votes than it has sensors.
+    /// The value is the name of the rule.
+    BadVote(String),
+    /// Two of the drivers, sensor groups and sensors share a label.
+    /// The value is the shared label.
+    DuplicateLabel(String),
     /// Two pins are duplicated for differing functions.
     DuplicatePin(u8),
     /// A pin is used for
@@ -182,7 +646,8 @@ impl Configuration {
     /// This function will return errors in line with the definition of `Error` in this module.
     pub fn parse(source: &mut impl Read) -> Result<Configuration, Error> {
         // deserialize the configuration
-        let config: Configuration = serde_json::from_reader(source).map_err(Error::Malformed)?;
+        let mut config: Configuration =
+            serde_json::from_reader(source).map_err(Error::Malformed)?;
 
         // now validate it
 
@@ -191,29 +656,84 @@ impl Configuration {
             return Err(Error::ClockTooSlow);
         }
 
-        // check that each sensor has an ADC associated with it
-        for group in &config.sensor_groups {
-            for sensor in &group.sensors {
-                if usize::from(sensor.adc) >= config.adc_cs.len() {
-                    return Err(Error::NoSuchAdc(sensor.adc));
-                }
+        // check that each sensor has an ADC associated with it and a sensible calibration
+        for sensor in config.sensor_groups.iter().flat_map(|group| &group.sensors) {
+            config.validate_sensor(sensor)?;
+        }
+        for derived in config.sensor_groups.iter().flat_map(|group| &group.derived) {
+            config.validate_derived(derived)?;
+        }
+        for channel in config.sensor_groups.iter().flat_map(SensorGroup::channels) {
+            validate_channel(&channel)?;
+        }
 
-                if sensor.channel >= 8 {
-                    return Err(Error::BadChannel(sensor.channel));
-                }
+        // check that every label is unique, so that labels can be used to refer to things
+        config.check_labels()?;
+
+        // check that every reference to a driver is to a real driver, and pin it down by ID
+        config.resolve_drivers()?;
+
+        // check that waits correspond to real sensors
+        let countdown_events = config.countdown.iter().flat_map(|c| &c.events);
+        for procedure in [&config.ignition_sequence, &config.estop_sequence]
+            .into_iter()
+            .chain(config.sequences.values())
+            .chain(countdown_events.map(|event| &event.actions))
+        {
+            for step in procedure.iter().flat_map(Action::with_substeps) {
+                config.validate_step(step)?;
             }
         }
 
-        // check that actuations correspond to real drivers
-        for procedure in [&config.ignition_sequence, &config.estop_sequence] {
-            for step in procedure {
-                let Action::Actuate { driver_id, value: _ } = step else { continue; };
-                if usize::from(*driver_id) > config.drivers.len() {
-                    return Err(Error::NoSuchDriver(*driver_id));
+        // check that the emergency stop sequence can never try to abort
+        if config
+            .estop_sequence
+            .iter()
+            .flat_map(Action::with_substeps)
+            .any(|step| {
+                matches!(
+                    step,
+                    Action::WaitUntil {
+                        on_timeout: TimeoutAction::Abort,
+                        ..
+                    }
+                )
+            })
+        {
+            return Err(Error::AbortInEstop);
+        }
+
+        // check that countdown events happen during the countdown and never block the clock
+        if let Some(countdown) = &config.countdown {
+            for event in &countdown.events {
+                if event.t_minus > countdown.length {
+                    return Err(Error::EarlyCountdownEvent(event.t_minus));
+                }
+                if !event
+                    .actions
+                    .iter()
+                    .all(|action| matches!(action, Action::Actuate { .. }))
+                {
+                    return Err(Error::BlockingCountdownAction);
                 }
             }
         }
 
+        // check that go/no-go checks and abort rules refer to real sensors
+        for check in &config.checks {
+            config.validate_check(check)?;
+        }
+        for rule in &config.abort_rules {
+            config.validate_abort_rule(rule)?;
+        }
+
+        // check that the disconnect policy refers to a real sequence
+        if let DisconnectAction::Sequence { name } = &config.on_disconnect.action {
+            if !config.sequences.contains_key(name) {
+                return Err(Error::NoSuchSequence(name.clone()));
+            }
+        }
+
         // check that no pins are reused in the configuration
         // also, check that no illegal pins (i.e. ones on the Raspberry Pi which are reserved) are
         // used
@@ -224,6 +744,7 @@ impl Configuration {
             .map(|d| d.pin)
             .chain([config.spi_mosi, config.spi_miso, config.spi_clk])
             .chain(config.adc_cs.iter().copied())
+            .chain(config.arming.key_pin)
         {
             if !is_legal(pin) {
                 return Err(Error::ReservedPin(pin));
@@ -237,6 +758,343 @@ impl Configuration {
         // all validation steps passed
         Ok(config)
     }
+
+    /// Find the identifier (i.e. index) of the driver referred to by `driver`.
+    /// Returns `None` if there is no such driver.
+    pub fn driver_id(&self, driver: &DriverRef) -> Option<u8> {
+        find_driver(&self.drivers, driver)
+    }
+
+    /// Resolve every reference to a driver in the procedures, interlocks and checks of this
+    /// configuration into a driver ID.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any reference is to a driver which does not exist.
+    fn resolve_drivers(&mut self) -> Result<(), Error> {
+        let mut refs: Vec<&mut DriverRef> = Vec::new();
+        let countdown_events = self.countdown.iter_mut().flat_map(|c| &mut c.events);
+        for procedure in [&mut self.ignition_sequence, &mut self.estop_sequence]
+            .into_iter()
+            .chain(self.sequences.values_mut())
+            .chain(countdown_events.map(|event| &mut event.actions))
+        {
+            collect_driver_refs(procedure, &mut refs);
+        }
+        for interlock in &mut self.interlocks {
+            match interlock {
+                Interlock::Exclusive { drivers } => refs.extend(drivers),
+                Interlock::OnlyInStates { driver_id, .. } => refs.push(driver_id),
+            }
+        }
+        for check in &mut self.checks {
+            if let Check::DriverValue { driver_id, .. } = check {
+                refs.push(driver_id);
+            }
+        }
+
+        for driver in refs {
+            let id = find_driver(&self.drivers, driver)
+                .ok_or_else(|| Error::NoSuchDriver(driver.clone()))?;
+            *driver = DriverRef::Id(id);
+        }
+
+        Ok(())
+    }
+
+    /// Check that no two drivers, sensor groups, sensors or derived channels share a label.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any label is used more than once.
+    fn check_labels(&self) -> Result<(), Error> {
+        let mut labels = HashSet::new();
+        for label in self
+            .drivers
+            .iter()
+            .map(|driver| driver.label.as_str())
+            .chain(self.sensor_groups.iter().map(|group| group.label.as_str()))
+            .chain(
+                self.sensor_groups
+                    .iter()
+                    .flat_map(SensorGroup::channels)
+                    .map(|channel| channel.label),
+            )
+        {
+            if !labels.insert(label) {
+                return Err(Error::DuplicateLabel(label.to_string()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Determine whether any sensor group contains a sensor or derived channel labeled `label`.
+    fn has_sensor(&self, label: &str) -> bool {
+        self.sensor_groups
+            .iter()
+            .flat_map(SensorGroup::channels)
+            .any(|channel| channel.label == label)
+    }
+
+    /// Check that `sensor` is read from a real ADC channel, that its calibration is well-formed,
+    /// and that the cold junction of a thermocouple is a sensor which does not depend on any other
+    /// sensor.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the ADC or channel does not exist, if the calibration
+    /// is malformed, or if its cold junction is not a suitable sensor.
+    fn validate_sensor(&self, sensor: &Sensor) -> Result<(), Error> {
+        if usize::from(sensor.adc) >= self.adc_cs.len() {
+            return Err(Error::NoSuchAdc(sensor.adc));
+        }
+
+        if sensor.channel >= 8 {
+            return Err(Error::BadChannel(sensor.channel));
+        }
+
+        if !sensor.calibration.is_valid() {
+            return Err(Error::BadCalibration(sensor.label.clone()));
+        }
+
+        if let Some(cold_junction) = sensor.calibration.cold_junction() {
+            let Some(reference) = self
+                .sensor_groups
+                .iter()
+                .flat_map(|group| &group.sensors)
+                .find(|other| other.label == cold_junction)
+            else {
+                return Err(Error::NoSuchSensor(cold_junction.to_string()));
+            };
+            if reference.calibration.cold_junction().is_some() {
+                return Err(Error::BadCalibration(sensor.label.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that the expression of `derived` refers only to sensors which exist, and not to other
+    /// derived channels.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the expression refers to a nonexistent sensor or to a
+    /// derived channel.
+    fn validate_derived(&self, derived: &Derived) -> Result<(), Error> {
+        for input in derived.expression.inputs() {
+            let groups = self.sensor_groups.iter();
+            if groups
+                .flat_map(|group| &group.derived)
+                .any(|other| other.label == input)
+            {
+                return Err(Error::BadDerived(derived.label.clone()));
+            }
+            if !self.has_sensor(input) {
+                return Err(Error::NoSuchSensor(input.to_string()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that a step of a procedure refers only to sensors which exist, and that it does not
+    /// nest `Parallel` steps.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the step refers to a nonexistent sensor, or if it is a
+    /// `Parallel` step containing another `Parallel` step.
+    fn validate_step(&self, step: &Action) -> Result<(), Error> {
+        match step {
+            Action::WaitUntil { sensor, .. } => {
+                if !self.has_sensor(sensor) {
+                    return Err(Error::NoSuchSensor(sensor.clone()));
+                }
+            }
+            Action::Parallel { tracks } => {
+                let nested = tracks
+                    .iter()
+                    .flatten()
+                    .any(|step| matches!(step, Action::Parallel { .. }));
+                if nested {
+                    return Err(Error::NestedParallel);
+                }
+            }
+            Action::Actuate { .. } | Action::Pulse { .. } | Action::Sleep { .. } => (),
+        }
+
+        Ok(())
+    }
+
+    /// Check that an abort rule refers only to sensors which exist, and that a vote is winnable.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the rule refers to a nonexistent sensor, or asks for
+    /// no votes or more votes than it has sensors.
+    fn validate_abort_rule(&self, rule: &AbortRule) -> Result<(), Error> {
+        if let Some(sensor) = rule
+            .condition
+            .sensors()
+            .find(|sensor| !self.has_sensor(sensor))
+        {
+            return Err(Error::NoSuchSensor(sensor.to_string()));
+        }
+
+        if let AbortCondition::Vote { sensors, votes, .. } = &rule.condition {
+            if *votes == 0 || *votes > sensors.len() {
+                return Err(Error::BadVote(rule.name.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that a go/no-go check refers only to sensors which exist.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the check refers to a nonexistent sensor, or asks for
+    /// more samples than are remembered.
+    fn validate_check(&self, check: &Check) -> Result<(), Error> {
+        match check {
+            Check::SensorInRange { sensor, .. } => {
+                if !self.has_sensor(sensor) {
+                    return Err(Error::NoSuchSensor(sensor.clone()));
+                }
+            }
+            Check::NoReadErrors { samples } => {
+                if *samples > 64 {
+                    return Err(Error::TooManySamples(*samples));
+                }
+            }
+            Check::DriverValue { .. } | Check::DashboardConnected => (),
+        }
+
+        Ok(())
+    }
+}
+
+/// Check that the filters and limits of a sensor or derived channel are well-formed.
+///
+/// # Errors
+///
+/// This function will return an error if a filter is malformed, if a minimum is above its maximum,
+/// or if the hysteresis is negative.
+fn validate_channel(channel: &Channel<'_>) -> Result<(), Error> {
+    if !channel.filters.iter().all(Filter::is_valid) {
+        return Err(Error::BadFilter(channel.label.to_string()));
+    }
+
+    let limits_valid = [channel.abort_range, channel.warn_range]
+        .into_iter()
+        .flatten()
+        .all(Limits::is_valid);
+    if !(limits_valid && channel.hysteresis >= 0.0) {
+        return Err(Error::BadLimits(channel.label.to_string()));
+    }
+
+    Ok(())
+}
+
+/// Deserialize the calibration of a sensor, accepting either the `calibration` field or the legacy
+/// `calibration_slope` and `calibration_intercept` fields.
+///
+/// # Errors
+///
+/// This function will return an error if neither form of calibration is given.
+fn deserialize_calibration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Calibration, D::Error> {
+    Ok(match SensorCalibration::deserialize(deserializer)? {
+        SensorCalibration::Current { calibration } => calibration,
+        SensorCalibration::Legacy {
+            calibration_slope,
+            calibration_intercept,
+        } => Calibration::Linear {
+            slope: calibration_slope,
+            intercept: calibration_intercept,
+        },
+    })
+}
+
+/// Serialize the calibration of a sensor in the `calibration` field.
+///
+/// # Errors
+///
+/// This function will return an error if `serializer` fails.
+fn serialize_calibration<S: Serializer>(
+    calibration: &Calibration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    CalibrationField { calibration }.serialize(serializer)
+}
+
+impl SensorGroup {
+    /// Get every channel of this sensor group: first its sensors, then its derived channels.
+    /// The index of each channel is its ID within the group.
+    pub fn channels(&self) -> impl Iterator<Item = Channel<'_>> {
+        let sensors = self.sensors.iter().map(|sensor| Channel {
+            label: &sensor.label,
+            filters: &sensor.filters,
+            abort_range: sensor.abort_range.as_ref(),
+            warn_range: sensor.warn_range.as_ref(),
+            hysteresis: sensor.hysteresis,
+        });
+        let derived = self.derived.iter().map(|derived| Channel {
+            label: &derived.label,
+            filters: &derived.filters,
+            abort_range: derived.abort_range.as_ref(),
+            warn_range: derived.warn_range.as_ref(),
+            hysteresis: derived.hysteresis,
+        });
+        sensors.chain(derived)
+    }
+}
+
+impl Limits {
+    /// Get the minimum and maximum allowable values in `state`, if there are any.
+    pub fn get(&self, state: State) -> Option<(f64, f64)> {
+        match self {
+            Limits::Always(limits) => Some(*limits),
+            Limits::PerState(limits) => limits.get(&state).copied(),
+        }
+    }
+
+    /// Determine whether every minimum is at most its maximum.
+    fn is_valid(&self) -> bool {
+        match self {
+            Limits::Always((min, max)) => min <= max,
+            Limits::PerState(limits) => limits.values().all(|(min, max)| min <= max),
+        }
+    }
+}
+
+impl AbortCondition {
+    /// Get the labels of every sensor which this condition depends on.
+    pub fn sensors(&self) -> impl Iterator<Item = &str> {
+        let sensors: &[String] = match self {
+            AbortCondition::OutOfRange { sensor, .. }
+            | AbortCondition::RateOfChange { sensor, .. } => std::slice::from_ref(sensor),
+            AbortCondition::Vote { sensors, .. } => sensors,
+        };
+        sensors.iter().map(String::as_str)
+    }
+}
+
+impl Filter {
+    /// Determine whether the parameters of this filter are sensible.
+    fn is_valid(&self) -> bool {
+        match *self {
+            Filter::MovingAverage { width } | Filter::Median { width } => width > 0,
+            Filter::LowPass { cutoff } | Filter::Butterworth { cutoff } => {
+                cutoff.is_finite() && cutoff > 0.0
+            }
+            Filter::Exponential { alpha } => 0.0 < alpha && alpha <= 1.0,
+        }
+    }
 }
 
 /// Determine whether a GPIO pin ID is a legal pin for use in the controller.
@@ -246,6 +1104,44 @@ fn is_legal(pin: u8) -> bool {
     1 < pin && pin <= 27
 }
 
+/// Find the identifier (i.e. index) of the driver in `drivers` which is referred to by `driver`.
+/// Returns `None` if there is no such driver.
+fn find_driver(drivers: &[Driver], driver: &DriverRef) -> Option<u8> {
+    match driver {
+        DriverRef::Id(id) => (usize::from(*id) < drivers.len()).then_some(*id),
+        DriverRef::Label(label) => drivers
+            .iter()
+            .position(|d| d.label == *label)
+            .and_then(|i| u8::try_from(i).ok()),
+    }
+}
+
+/// Add every reference to a driver in `steps`, including those inside `Parallel` steps, to `refs`.
+fn collect_driver_refs<'a>(steps: &'a mut [Action], refs: &mut Vec<&'a mut DriverRef>) {
+    for step in steps {
+        match step {
+            Action::Actuate { driver_id, .. } | Action::Pulse { driver_id, .. } => {
+                refs.push(driver_id);
+            }
+            Action::Parallel { tracks } => {
+                for track in tracks {
+                    collect_driver_refs(track, refs);
+                }
+            }
+            Action::Sleep { .. } | Action::WaitUntil { .. } => (),
+        }
+    }
+}
+
+impl Display for DriverRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DriverRef::Id(id) => write!(f, "{id}"),
+            DriverRef::Label(label) => write!(f, "{label}"),
+        }
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -262,7 +1158,20 @@ impl Display for Error {
                 "SPI clock frequency is too slow (must be at least {} Hz)",
                 Mcp3208::<ListenerPin>::SPI_MIN_FREQUENCY
             ),
-            Error::NoSuchDriver(d) => write!(f, "A procedure refers to a driver with ID {d}, but no such driver is given in the list of drivers"),
+            Error::NoSuchDriver(d) => write!(f, "The configuration refers to the driver {d}, but no such driver is given in the list of drivers"),
+            Error::NoSuchSequence(name) => write!(f, "The configuration refers to a sequence named {name:?}, but no such sequence is given in the set of sequences"),
+            Error::NoSuchSensor(label) => write!(f, "The configuration refers to a sensor labeled {label:?}, but no such sensor is given in any sensor group"),
+            Error::AbortInEstop => write!(f, "The emergency stop sequence may not contain a WaitUntil action which aborts on timeout"),
+            Error::EarlyCountdownEvent(t) => write!(f, "A countdown event is scheduled at T-{t} ms, which is before the countdown starts"),
+            Error::BlockingCountdownAction => write!(f, "Countdown events may only contain Actuate actions"),
+            Error::NestedParallel => write!(f, "Parallel steps may not be nested inside one another"),
+            Error::TooManySamples(n) => write!(f, "A go/no-go check asks for the last {n} samples of each sensor, but only the last 64 are remembered"),
+            Error::BadCalibration(label) => write!(f, "The calibration of sensor {label:?} is malformed, or its cold junction is not a plain sensor"),
+            Error::BadFilter(label) => write!(f, "A filter of sensor {label:?} has a zero width, a cutoff frequency which is not positive, or an alpha outside of (0, 1]"),
+            Error::BadLimits(label) => write!(f, "The limits of sensor {label:?} have a minimum above their maximum, or its hysteresis is negative"),
+            Error::BadDerived(label) => write!(f, "The derived channel {label:?} refers to another derived channel, but may only refer to sensors"),
+            Error::BadVote(name) => write!(f, "The abort rule {name:?} must ask for at least one vote, and no more votes than it has sensors"),
+            Error::DuplicateLabel(label) => write!(f, "The label {label:?} is given to more than one driver, sensor group or sensor"),
             Error::DuplicatePin(p) => write!(f, "GPIO pin {p} is used for multiple purposes"),
             Error::ReservedPin(p) => write!(f, "GPIO pin {p} is not allowed to be used on the Raspberry Pi"),
         }
@@ -293,9 +1202,17 @@ mod tests {
                             "label": "LC_MAIN",
                             "color": "#ef3b9e",
                             "units": "lb",
-                            "calibration_intercept": 0.34,
-                            "calibration_slope": 33.2,
-                            "rolling_average_width": 5,
+                            "calibration": {
+                                "type": "Linear",
+                                "slope": 33.2,
+                                "intercept": 0.34
+                            },
+                            "filters": [
+                                {
+                                    "type": "MovingAverage",
+                                    "width": 5
+                                }
+                            ],
                             "adc": 0,
                             "channel": 0
                         },
@@ -303,12 +1220,29 @@ mod tests {
                             "label": "PT_FEED",
                             "color": "#ef3b9e",
                             "units": "psi",
-                            "range": [-500, 3000],
-                            "calibration_intercept": 92.3,
-                            "calibration_slope": -302.4,
+                            "abort_range": [-500, 3000],
+                            "warn_range": {
+                                "Standby": [-100, 500],
+                                "Ignite": [-100, 2500]
+                            },
+                            "hysteresis": 10,
+                            "calibration": {
+                                "type": "Linear",
+                                "slope": -302.4,
+                                "intercept": 92.3
+                            },
                             "adc": 0,
                             "channel": 1
                         }
+                    ],
+                    "derived": [
+                        {
+                            "label": "THRUST",
+                            "color": "#1d8718",
+                            "units": "lbf",
+                            "expression": "2 * LC_MAIN",
+                            "abort_range": [-100, 2000]
+                        }
                     ]
                 }
             ],
@@ -371,10 +1305,14 @@ mod tests {
                         label: "LC_MAIN".into(),
                         color: "#ef3b9e".into(),
                         units: "lb".into(),
-                        range: None,
-                        calibration_intercept: 0.34,
-                        calibration_slope: 33.2,
-                        rolling_average_width: Some(5),
+                        abort_range: None,
+                        warn_range: None,
+                        hysteresis: 0.0,
+                        calibration: Calibration::Linear {
+                            slope: 33.2,
+                            intercept: 0.34,
+                        },
+                        filters: vec![Filter::MovingAverage { width: 5 }],
                         adc: 0,
                         channel: 0,
                     },
@@ -382,14 +1320,31 @@ mod tests {
                         label: "PT_FEED".into(),
                         color: "#ef3b9e".into(),
                         units: "psi".into(),
-                        range: Some((-500., 3000.)),
-                        calibration_intercept: 92.3,
-                        calibration_slope: -302.4,
-                        rolling_average_width: None,
+                        abort_range: Some(Limits::Always((-500., 3000.))),
+                        warn_range: Some(Limits::PerState(HashMap::from([
+                            (State::Standby, (-100., 500.)),
+                            (State::Ignite, (-100., 2500.)),
+                        ]))),
+                        hysteresis: 10.0,
+                        calibration: Calibration::Linear {
+                            slope: -302.4,
+                            intercept: 92.3,
+                        },
+                        filters: Vec::new(),
                         adc: 0,
                         channel: 1,
                     },
                 ],
+                derived: vec![Derived {
+                    label: "THRUST".into(),
+                    color: "#1d8718".into(),
+                    units: "lbf".into(),
+                    expression: Expression::parse("2 * LC_MAIN").unwrap(),
+                    abort_range: Some(Limits::Always((-100., 2000.))),
+                    warn_range: None,
+                    hysteresis: 0.0,
+                    filters: Vec::new(),
+                }],
             }],
             pre_ignite_time: 500,
             post_ignite_time: 5000,
@@ -402,21 +1357,29 @@ mod tests {
             }],
             ignition_sequence: vec![
                 Action::Actuate {
-                    driver_id: 0,
+                    driver_id: DriverRef::Id(0),
                     value: true,
                 },
                 Action::Sleep {
                     duration: Duration::from_secs(10),
                 },
                 Action::Actuate {
-                    driver_id: 0,
+                    driver_id: DriverRef::Id(0),
                     value: false,
                 },
             ],
+            arming: ArmingPolicy::default(),
+            countdown: None,
+            checks: Vec::new(),
+            interlocks: Vec::new(),
+            abort_rules: Vec::new(),
             estop_sequence: vec![Action::Actuate {
-                driver_id: 0,
+                driver_id: DriverRef::Id(0),
                 value: false,
             }],
+            sequences: HashMap::new(),
+            on_disconnect: DisconnectPolicy::default(),
+            log_level_dashboard: None,
             spi_mosi: 26,
             spi_miso: 25,
             spi_clk: 24,
@@ -428,4 +1391,633 @@ mod tests {
         let mut cursor = Cursor::new(config_str);
         assert_eq!(config, Configuration::parse(&mut cursor).unwrap());
     }
+
+    #[test]
+    /// Test that a configuration which gives linear calibrations in the legacy format still
+    /// parses, and that it is written back out in the current format.
+    fn legacy_calibration() {
+        let config_str = r##"{
+            "frequency_status": 10,
+            "log_buffer_size": 256,
+            "sensor_groups": [
+                {
+                    "label": "FAST",
+                    "frequency_standby": 10,
+                    "frequency_ignition": 1000,
+                    "frequency_transmission": 10,
+                    "sensors": [
+                        {
+                            "label": "PT_FEED",
+                            "color": "#ef3b9e",
+                            "units": "psi",
+                            "range": [-500, 3000],
+                            "calibration_intercept": 0.34,
+                            "calibration_slope": 33.2,
+                            "adc": 0,
+                            "channel": 0
+                        }
+                    ]
+                }
+            ],
+            "pre_ignite_time": 500,
+            "post_ignite_time": 5000,
+            "drivers": [],
+            "ignition_sequence": [],
+            "estop_sequence": [],
+            "spi_mosi": 26,
+            "spi_miso": 25,
+            "spi_clk": 24,
+            "spi_frequency_clk": 50000,
+            "adc_cs": [5],
+            "pin_heartbeat": 0
+        }"##;
+
+        let config = Configuration::parse(&mut Cursor::new(config_str)).unwrap();
+        let sensor = &config.sensor_groups[0].sensors[0];
+        assert_eq!(
+            sensor.calibration,
+            Calibration::Linear {
+                slope: 33.2,
+                intercept: 0.34
+            }
+        );
+
+        let written = serde_json::to_value(sensor).unwrap();
+        assert_eq!(written["calibration"]["type"], "Linear");
+        assert!(written.get("calibration_slope").is_none());
+    }
+
+    #[test]
+    /// Test that a disconnect policy which refers to a nonexistent sequence is rejected.
+    fn disconnect_no_such_sequence() {
+        let config_str = r#"{
+            "frequency_status": 10,
+            "log_buffer_size": 256,
+            "sensor_groups": [],
+            "pre_ignite_time": 500,
+            "post_ignite_time": 5000,
+            "drivers": [],
+            "ignition_sequence": [],
+            "estop_sequence": [],
+            "sequences": {
+                "purge": []
+            },
+            "on_disconnect": {
+                "action": {
+                    "type": "Sequence",
+                    "name": "vent"
+                },
+                "grace_period": 1000
+            },
+            "spi_mosi": 26,
+            "spi_miso": 25,
+            "spi_clk": 24,
+            "spi_frequency_clk": 50000,
+            "adc_cs": [],
+            "pin_heartbeat": 0
+        }"#;
+
+        let mut cursor = Cursor::new(config_str);
+        let Err(Error::NoSuchSequence(name)) = Configuration::parse(&mut cursor) else {
+            panic!()
+        };
+        assert_eq!(name, "vent");
+    }
+
+    #[test]
+    /// Test that a `WaitUntil` action which refers to a nonexistent sensor is rejected.
+    fn wait_no_such_sensor() {
+        let config_str = r#"{
+            "frequency_status": 10,
+            "log_buffer_size": 256,
+            "sensor_groups": [],
+            "pre_ignite_time": 500,
+            "post_ignite_time": 5000,
+            "drivers": [],
+            "ignition_sequence": [
+                {
+                    "type": "WaitUntil",
+                    "sensor": "PT_CHAMBER",
+                    "condition": "Above",
+                    "threshold": 50,
+                    "timeout": {
+                        "secs": 1,
+                        "nanos": 0
+                    }
+                }
+            ],
+            "estop_sequence": [],
+            "spi_mosi": 26,
+            "spi_miso": 25,
+            "spi_clk": 24,
+            "spi_frequency_clk": 50000,
+            "adc_cs": [],
+            "pin_heartbeat": 0
+        }"#;
+
+        let mut cursor = Cursor::new(config_str);
+        let Err(Error::NoSuchSensor(label)) = Configuration::parse(&mut cursor) else {
+            panic!()
+        };
+        assert_eq!(label, "PT_CHAMBER");
+    }
+
+    #[test]
+    /// Test that countdown events which could stop the clock are rejected.
+    fn countdown_blocking_event() {
+        let config_str = r#"{
+            "frequency_status": 10,
+            "log_buffer_size": 256,
+            "sensor_groups": [],
+            "pre_ignite_time": 500,
+            "post_ignite_time": 5000,
+            "drivers": [],
+            "countdown": {
+                "length": 10000,
+                "events": [
+                    {
+                        "t_minus": 5000,
+                        "actions": [
+                            {
+                                "type": "Sleep",
+                                "duration": {
+                                    "secs": 1,
+                                    "nanos": 0
+                                }
+                            }
+                        ]
+                    }
+                ]
+            },
+            "ignition_sequence": [],
+            "estop_sequence": [],
+            "spi_mosi": 26,
+            "spi_miso": 25,
+            "spi_clk": 24,
+            "spi_frequency_clk": 50000,
+            "adc_cs": [],
+            "pin_heartbeat": 0
+        }"#;
+
+        let mut cursor = Cursor::new(config_str);
+        assert!(matches!(
+            Configuration::parse(&mut cursor),
+            Err(Error::BlockingCountdownAction)
+        ));
+    }
+
+    #[test]
+    /// Test that an interlock referring to a nonexistent driver is rejected.
+    fn interlock_no_such_driver() {
+        let config_str = r#"{
+            "frequency_status": 10,
+            "log_buffer_size": 256,
+            "sensor_groups": [],
+            "pre_ignite_time": 500,
+            "post_ignite_time": 5000,
+            "drivers": [
+                {
+                    "label": "OXI_FILL",
+                    "label_actuate": "Open",
+                    "label_deactuate": "Close",
+                    "pin": 33,
+                    "protected": false
+                }
+            ],
+            "interlocks": [
+                {
+                    "type": "Exclusive",
+                    "drivers": [0, 1]
+                }
+            ],
+            "ignition_sequence": [],
+            "estop_sequence": [],
+            "spi_mosi": 26,
+            "spi_miso": 25,
+            "spi_clk": 24,
+            "spi_frequency_clk": 50000,
+            "adc_cs": [],
+            "pin_heartbeat": 0
+        }"#;
+
+        let mut cursor = Cursor::new(config_str);
+        assert!(matches!(
+            Configuration::parse(&mut cursor),
+            Err(Error::NoSuchDriver(DriverRef::Id(1)))
+        ));
+    }
+
+    #[test]
+    /// Test that drivers may be referred to by label, and that the labels are resolved into IDs.
+    fn driver_by_label() {
+        let config_str = |label: &str| {
+            format!(
+                r#"{{
+                "frequency_status": 10,
+                "log_buffer_size": 256,
+                "sensor_groups": [],
+                "pre_ignite_time": 500,
+                "post_ignite_time": 5000,
+                "drivers": [
+                    {{
+                        "label": "OXI_FILL",
+                        "label_actuate": "Open",
+                        "label_deactuate": "Close",
+                        "pin": 20,
+                        "protected": false
+                    }},
+                    {{
+                        "label": "IGNITER",
+                        "label_actuate": "Fire",
+                        "label_deactuate": "Off",
+                        "pin": 21,
+                        "protected": true
+                    }}
+                ],
+                "ignition_sequence": [
+                    {{
+                        "type": "Actuate",
+                        "driver_id": "{label}",
+                        "value": true
+                    }}
+                ],
+                "estop_sequence": [],
+                "spi_mosi": 26,
+                "spi_miso": 25,
+                "spi_clk": 24,
+                "spi_frequency_clk": 50000,
+                "adc_cs": [],
+                "pin_heartbeat": 0
+            }}"#
+            )
+        };
+
+        let config = Configuration::parse(&mut Cursor::new(config_str("IGNITER"))).unwrap();
+        assert_eq!(
+            config.ignition_sequence,
+            [Action::Actuate {
+                driver_id: DriverRef::Id(1),
+                value: true
+            }]
+        );
+
+        assert!(matches!(
+            Configuration::parse(&mut Cursor::new(config_str("IGNITOR"))),
+            Err(Error::NoSuchDriver(DriverRef::Label(label))) if label == "IGNITOR"
+        ));
+    }
+
+    #[test]
+    /// Test that a driver and a sensor may not share a label.
+    fn duplicate_label() {
+        let config_str = r##"{
+            "frequency_status": 10,
+            "log_buffer_size": 256,
+            "sensor_groups": [
+                {
+                    "label": "FAST",
+                    "frequency_standby": 10,
+                    "frequency_ignition": 1000,
+                    "frequency_transmission": 10,
+                    "sensors": [
+                        {
+                            "label": "OXI_FILL",
+                            "color": "#000000",
+                            "units": "psi",
+                            "calibration": {
+                                "type": "Linear",
+                                "slope": 1.0,
+                                "intercept": 0.0
+                            },
+                            "adc": 0,
+                            "channel": 0
+                        }
+                    ]
+                }
+            ],
+            "pre_ignite_time": 500,
+            "post_ignite_time": 5000,
+            "drivers": [
+                {
+                    "label": "OXI_FILL",
+                    "label_actuate": "Open",
+                    "label_deactuate": "Close",
+                    "pin": 33,
+                    "protected": false
+                }
+            ],
+            "ignition_sequence": [],
+            "estop_sequence": [],
+            "spi_mosi": 26,
+            "spi_miso": 25,
+            "spi_clk": 24,
+            "spi_frequency_clk": 50000,
+            "adc_cs": [5],
+            "pin_heartbeat": 0
+        }"##;
+
+        let mut cursor = Cursor::new(config_str);
+        assert!(matches!(
+            Configuration::parse(&mut cursor),
+            Err(Error::DuplicateLabel(label)) if label == "OXI_FILL"
+        ));
+    }
+
+    #[test]
+    /// Test that the cold junction of a thermocouple must be a real sensor which is not itself a
+    /// thermocouple.
+    fn thermocouple_cold_junction() {
+        let config_str = |cold_junction: &str| {
+            format!(
+                r##"{{
+                "frequency_status": 10,
+                "log_buffer_size": 256,
+                "sensor_groups": [
+                    {{
+                        "label": "TEMPERATURES",
+                        "frequency_standby": 10,
+                        "frequency_ignition": 100,
+                        "frequency_transmission": 10,
+                        "sensors": [
+                            {{
+                                "label": "TC_CHAMBER",
+                                "color": "#000000",
+                                "units": "°C",
+                                "calibration": {{
+                                    "type": "Thermocouple",
+                                    "kind": "K",
+                                    "cold_junction": "{cold_junction}",
+                                    "slope": 0.01,
+                                    "intercept": 0
+                                }},
+                                "adc": 0,
+                                "channel": 0
+                            }},
+                            {{
+                                "label": "TC_CJ",
+                                "color": "#000000",
+                                "units": "°C",
+                                "calibration": {{
+                                    "type": "Table",
+                                    "points": [[0, -40], [4095, 125]]
+                                }},
+                                "adc": 0,
+                                "channel": 1
+                            }}
+                        ]
+                    }}
+                ],
+                "pre_ignite_time": 500,
+                "post_ignite_time": 5000,
+                "drivers": [],
+                "ignition_sequence": [],
+                "estop_sequence": [],
+                "spi_mosi": 26,
+                "spi_miso": 25,
+                "spi_clk": 24,
+                "spi_frequency_clk": 50000,
+                "adc_cs": [5],
+                "pin_heartbeat": 0
+            }}"##
+            )
+        };
+
+        let config = Configuration::parse(&mut Cursor::new(config_str("TC_CJ"))).unwrap();
+        assert_eq!(
+            config.sensor_groups[0].sensors[0]
+                .calibration
+                .cold_junction(),
+            Some("TC_CJ")
+        );
+
+        assert!(matches!(
+            Configuration::parse(&mut Cursor::new(config_str("TC_COLD"))),
+            Err(Error::NoSuchSensor(label)) if label == "TC_COLD"
+        ));
+
+        // a thermocouple cannot be its own cold junction
+        assert!(matches!(
+            Configuration::parse(&mut Cursor::new(config_str("TC_CHAMBER"))),
+            Err(Error::BadCalibration(label)) if label == "TC_CHAMBER"
+        ));
+    }
+
+    #[test]
+    /// Test that the old name `range` is still accepted for abort limits, and that malformed limits
+    /// are rejected.
+    fn sensor_limits() {
+        let config_str = |limits: &str| {
+            format!(
+                r##"{{
+                "frequency_status": 10,
+                "log_buffer_size": 256,
+                "sensor_groups": [
+                    {{
+                        "label": "PRESSURES",
+                        "frequency_standby": 10,
+                        "frequency_ignition": 100,
+                        "frequency_transmission": 10,
+                        "sensors": [
+                            {{
+                                "label": "PT_TANK",
+                                "color": "#000000",
+                                "units": "psi",
+                                "calibration": {{
+                                    "type": "Linear",
+                                    "slope": 1.0,
+                                    "intercept": 0.0
+                                }},
+                                "adc": 0,
+                                "channel": 0,
+                                {limits}
+                            }}
+                        ]
+                    }}
+                ],
+                "pre_ignite_time": 500,
+                "post_ignite_time": 5000,
+                "drivers": [],
+                "ignition_sequence": [],
+                "estop_sequence": [],
+                "spi_mosi": 26,
+                "spi_miso": 25,
+                "spi_clk": 24,
+                "spi_frequency_clk": 50000,
+                "adc_cs": [5],
+                "pin_heartbeat": 0
+            }}"##
+            )
+        };
+
+        let config =
+            Configuration::parse(&mut Cursor::new(config_str(r#""range": [0, 800]"#))).unwrap();
+        let sensor = &config.sensor_groups[0].sensors[0];
+        assert_eq!(sensor.abort_range, Some(Limits::Always((0.0, 800.0))));
+        assert_eq!(sensor.warn_range, None);
+
+        let config = Configuration::parse(&mut Cursor::new(config_str(
+            r#""abort_range": {"Standby": [0, 300], "Ignite": [0, 800]}"#,
+        )))
+        .unwrap();
+        let limits = config.sensor_groups[0].sensors[0].abort_range.as_ref();
+        assert_eq!(limits.unwrap().get(State::Ignite), Some((0.0, 800.0)));
+        assert_eq!(limits.unwrap().get(State::PreIgnite), None);
+
+        for limits in [
+            r#""warn_range": [800, 0]"#,
+            r#""abort_range": {"Standby": [300, 0]}"#,
+            r#""warn_range": [0, 800], "hysteresis": -5"#,
+        ] {
+            assert!(matches!(
+                Configuration::parse(&mut Cursor::new(config_str(limits))),
+                Err(Error::BadLimits(label)) if label == "PT_TANK"
+            ));
+        }
+    }
+
+    #[test]
+    /// Test that derived channels may be used like sensors, but may only be computed from real
+    /// sensors.
+    fn derived_channels() {
+        let config_str = |expression: &str| {
+            format!(
+                r##"{{
+                "frequency_status": 10,
+                "log_buffer_size": 256,
+                "sensor_groups": [
+                    {{
+                        "label": "PRESSURES",
+                        "frequency_standby": 10,
+                        "frequency_ignition": 100,
+                        "frequency_transmission": 10,
+                        "sensors": [
+                            {{
+                                "label": "PT_FEED",
+                                "color": "#000000",
+                                "units": "psi",
+                                "calibration": {{
+                                    "type": "Linear",
+                                    "slope": 1.0,
+                                    "intercept": 0.0
+                                }},
+                                "adc": 0,
+                                "channel": 0
+                            }},
+                            {{
+                                "label": "PT_CHAMBER",
+                                "color": "#000000",
+                                "units": "psi",
+                                "calibration": {{
+                                    "type": "Linear",
+                                    "slope": 1.0,
+                                    "intercept": 0.0
+                                }},
+                                "adc": 0,
+                                "channel": 1
+                            }}
+                        ],
+                        "derived": [
+                            {{
+                                "label": "DP_INJECTOR",
+                                "color": "#000000",
+                                "units": "psi",
+                                "expression": "{expression}"
+                            }},
+                            {{
+                                "label": "FLOW",
+                                "color": "#000000",
+                                "units": "lb/s",
+                                "expression": "0.02 * sqrt(max(PT_FEED - PT_CHAMBER, 0))",
+                                "warn_range": [0, 1.5]
+                            }}
+                        ]
+                    }}
+                ],
+                "pre_ignite_time": 500,
+                "post_ignite_time": 5000,
+                "drivers": [],
+                "abort_rules": [
+                    {{
+                        "name": "injector backflow",
+                        "condition": {{
+                            "type": "OutOfRange",
+                            "sensor": "DP_INJECTOR",
+                            "min": 0,
+                            "max": 1000
+                        }}
+                    }}
+                ],
+                "ignition_sequence": [],
+                "estop_sequence": [],
+                "spi_mosi": 26,
+                "spi_miso": 25,
+                "spi_clk": 24,
+                "spi_frequency_clk": 50000,
+                "adc_cs": [5],
+                "pin_heartbeat": 0
+            }}"##
+            )
+        };
+
+        let config =
+            Configuration::parse(&mut Cursor::new(config_str("PT_FEED - PT_CHAMBER"))).unwrap();
+        let labels: Vec<&str> = config.sensor_groups[0]
+            .channels()
+            .map(|channel| channel.label)
+            .collect();
+        assert_eq!(labels, ["PT_FEED", "PT_CHAMBER", "DP_INJECTOR", "FLOW"]);
+
+        assert!(matches!(
+            Configuration::parse(&mut Cursor::new(config_str("FLOW * 2"))),
+            Err(Error::BadDerived(label)) if label == "DP_INJECTOR"
+        ));
+        assert!(matches!(
+            Configuration::parse(&mut Cursor::new(config_str("PT_FEED - PT_OX"))),
+            Err(Error::NoSuchSensor(label)) if label == "PT_OX"
+        ));
+        assert!(matches!(
+            Configuration::parse(&mut Cursor::new(config_str("PT_FEED -"))),
+            Err(Error::Malformed(_))
+        ));
+    }
+
+    #[test]
+    /// Test that `Parallel` steps may not be nested.
+    fn nested_parallel() {
+        let config_str = r#"{
+            "frequency_status": 10,
+            "log_buffer_size": 256,
+            "sensor_groups": [],
+            "pre_ignite_time": 500,
+            "post_ignite_time": 5000,
+            "drivers": [],
+            "ignition_sequence": [
+                {
+                    "type": "Parallel",
+                    "tracks": [
+                        [],
+                        [
+                            {
+                                "type": "Parallel",
+                                "tracks": []
+                            }
+                        ]
+                    ]
+                }
+            ],
+            "estop_sequence": [],
+            "spi_mosi": 26,
+            "spi_miso": 25,
+            "spi_clk": 24,
+            "spi_frequency_clk": 50000,
+            "adc_cs": [],
+            "pin_heartbeat": 0
+        }"#;
+
+        let mut cursor = Cursor::new(config_str);
+        assert!(matches!(
+            Configuration::parse(&mut cursor),
+            Err(Error::NestedParallel)
+        ));
+    }
 }
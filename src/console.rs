@@ -16,7 +16,26 @@
   along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-use std::{fmt::Display, io::Write, sync::Mutex, time::SystemTime};
+use std::{
+    fmt::Display,
+    io::Write,
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    outgoing::{DashChannel, Message},
+    state::{Guard, State},
+    ControllerError,
+};
+
+/// The period at which the log forwarding thread checks whether the controller is quitting.
+const FORWARD_POLL_PERIOD: Duration = Duration::from_millis(100);
 
 /// A log for data displayed to the user.
 /// The data sent to the user log need not be machine-readable.
@@ -24,16 +43,30 @@ use std::{fmt::Display, io::Write, sync::Mutex, time::SystemTime};
 pub struct UserLog<W: Write> {
     /// The buffer to which user log information will be written.
     log_buffer: Mutex<W>,
+    /// If entries are being forwarded, the minimum level of entry to forward and the channel to
+    /// forward them on.
+    forward: Mutex<Option<(LogLevel, Sender<LogEntry>)>>,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+/// A single entry in the user log.
+pub struct LogEntry {
+    /// The level of the entry.
+    pub level: LogLevel,
+    /// The time at which the entry was logged.
+    pub time: SystemTime,
+    /// The text of the entry.
+    pub message: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Default)]
 /// The levels for logging.
-enum LogLevel {
+pub enum LogLevel {
     /// The lowest log level.
     /// Used exclusively for displaying random garbage to help the developer debug their problems.
     Debug = 0,
     /// The second-lowest log level.
     /// Used for information which might be useful to have after an event occurs.
+    #[default]
     Info = 1,
     /// The second-highest log level.
     /// Used for notifying the user of potential problems, but which are nonfatal.
@@ -65,9 +98,25 @@ impl<W: Write> UserLog<W> {
     pub fn new(buf: W) -> UserLog<W> {
         UserLog {
             log_buffer: Mutex::new(buf),
+            forward: Mutex::new(None),
         }
     }
 
+    /// Begin forwarding every entry of level `min_level` or higher to the returned channel.
+    /// If entries were already being forwarded, the previous channel will stop receiving them.
+    ///
+    /// Sending an entry on the channel never blocks, so it is safe to keep logging while holding
+    /// locks which the receiver may need.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the internal forwarding lock is poisoned.
+    pub fn forward(&self, min_level: LogLevel) -> Receiver<LogEntry> {
+        let (tx, rx) = channel();
+        *self.forward.lock().unwrap() = Some((min_level, tx));
+        rx
+    }
+
     #[allow(clippy::missing_errors_doc)]
     /// Log some debug information for the user.
     ///
@@ -96,14 +145,22 @@ impl<W: Write> UserLog<W> {
         self.write(LogLevel::Critical, string)
     }
 
+    #[allow(clippy::missing_errors_doc)]
+    /// Write a warning for the user which is never forwarded.
+    ///
+    /// This is used to report problems with forwarding itself, which forwarding could not deliver.
+    pub fn warn_unforwarded(&self, string: &str) -> std::io::Result<()> {
+        self.write_local(LogLevel::Warn, string, SystemTime::now())
+    }
+
     /// Log some information.
     ///
     /// # Inputs
     ///
     /// * `level`: The level of the log.
-    ///     Higher-level logs are more critical.
+    ///   Higher-level logs are more critical.
     /// * `string`: The information to log.
-    ///     I recommend using `format!()` to construct this string.
+    ///   I recommend using `format!()` to construct this string.
     ///
     /// # Errors
     ///
@@ -113,8 +170,45 @@ impl<W: Write> UserLog<W> {
     ///
     /// This function will panic if the current time is before the UNIX epoch.
     fn write(&self, level: LogLevel, string: &str) -> std::io::Result<()> {
+        let log_time = SystemTime::now();
+        self.write_local(level, string, log_time)?;
+
+        // we trust that forwarding will not cause a panic either.
+        let mut forward_guard = self.forward.lock().unwrap();
+        if let Some((min_level, tx)) = forward_guard.as_ref() {
+            if level >= *min_level {
+                let entry = LogEntry {
+                    level,
+                    time: log_time,
+                    message: string.to_string(),
+                };
+                if tx.send(entry).is_err() {
+                    // nobody is listening anymore
+                    *forward_guard = None;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Log some information to standard output and the log buffer, without forwarding it.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an `Error` if we are unable to write to the log buffer.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `log_time` is before the UNIX epoch.
+    fn write_local(
+        &self,
+        level: LogLevel,
+        string: &str,
+        log_time: SystemTime,
+    ) -> std::io::Result<()> {
         // we trust that this code was run after January 1st, 1970
-        let log_time_nanos = SystemTime::now()
+        let log_time_nanos = log_time
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
             .as_nanos();
@@ -125,7 +219,7 @@ impl<W: Write> UserLog<W> {
             LogLevel::Warn => print!("\x1b[33m"),     // yellow
             LogLevel::Info => (),
             LogLevel::Debug => print!("\x1b[90m"), // faded
-        };
+        }
         println!("[{log_time_nanos}] [{level}] {string}");
 
         // wipe previous coloring
@@ -134,7 +228,114 @@ impl<W: Write> UserLog<W> {
             // we trust writing to the log buffer will not cause a panic.
             self.log_buffer.lock().unwrap(),
             "[{log_time_nanos}] [{level}] {string}"
-        )?;
-        Ok(())
+        )
+    }
+}
+
+/// Transmit forwarded user log entries to the dashboard as `Log` messages.
+///
+/// # Inputs
+///
+/// * `entries`: The channel of forwarded entries, created by `UserLog::forward`.
+/// * `state`: The overall system state.
+///   This function will only return after `state` transitions to `State::Quit`, or once nothing
+///   is being forwarded on `entries` anymore.
+/// * `dashboard_stream`: A channel by which messages can be sent to the dashboard.
+/// * `user_log`: The log to which failures to send entries will be reported.
+///   Those reports are not forwarded themselves.
+///
+/// # Errors
+///
+/// This function will return an error if the state lock is poisoned or if we are unable to write
+/// to the user log.
+pub fn log_forward(
+    entries: &Receiver<LogEntry>,
+    state: &Guard,
+    dashboard_stream: &DashChannel<impl Write, impl Write>,
+    user_log: &UserLog<impl Write>,
+) -> Result<(), ControllerError> {
+    while state.status()? != State::Quit {
+        match entries.recv_timeout(FORWARD_POLL_PERIOD) {
+            Ok(entry) => {
+                if let Err(e) = dashboard_stream.send(&Message::Log {
+                    level: entry.level,
+                    time: entry.time,
+                    message: &entry.message,
+                }) {
+                    user_log.warn_unforwarded(&format!(
+                        "Unable to forward log entry to dashboard: {e:?}"
+                    ))?;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::scope;
+
+    use crate::outgoing::Feature;
+
+    use super::*;
+
+    #[test]
+    /// Test that only entries at or above the minimum level are forwarded.
+    fn forward_min_level() {
+        let log = UserLog::new(Vec::new());
+        log.info("not forwarded").unwrap();
+
+        let rx = log.forward(LogLevel::Warn);
+        log.debug("too low").unwrap();
+        log.info("still too low").unwrap();
+        log.warn("uh oh").unwrap();
+        log.critical("UH OH").unwrap();
+
+        let entries: Vec<(LogLevel, String)> =
+            rx.try_iter().map(|e| (e.level, e.message)).collect();
+        assert_eq!(
+            entries,
+            [
+                (LogLevel::Warn, String::from("uh oh")),
+                (LogLevel::Critical, String::from("UH OH"))
+            ]
+        );
+    }
+
+    #[test]
+    /// Test that forwarded entries make it to the dashboard.
+    fn forward_to_dashboard() {
+        let log = UserLog::new(Vec::new());
+        let rx = log.forward(LogLevel::Info);
+        let state = Guard::new(State::Standby);
+        let output_stream = DashChannel::<Vec<u8>, Vec<u8>>::new(Vec::new());
+        let client = output_stream.add_client(Vec::new()).unwrap();
+        output_stream
+            .set_features(client, vec![Feature::Logs])
+            .unwrap();
+
+        scope(|s| {
+            let (state_ref, output_stream_ref) = (&state, &output_stream);
+            s.spawn(move || {
+                log_forward(&rx, state_ref, output_stream_ref, &UserLog::new(Vec::new()))
+            });
+
+            log.warn("Sensor was out of bounds").unwrap();
+
+            // give the forwarder time to send the entry, then shut it down
+            while output_stream.sent(client).is_empty() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            state.move_to(State::Quit).unwrap();
+        });
+
+        let message = &output_stream.sent(client)[0];
+        assert_eq!(message["type"], "Log");
+        assert_eq!(message["level"], "Warn");
+        assert_eq!(message["message"], "Sensor was out of bounds");
     }
 }
@@ -0,0 +1,470 @@
+/*
+  slonk, a rocket engine controller.
+  Copyright (C) 2022 Rice Eclipse.
+
+  slonk is free software: you can redistribute it and/or modify
+  it under the terms of the GNU General Public License as published by
+  the Free Software Foundation, either version 3 of the License, or
+  (at your option) any later version.
+
+  slonk is distributed in the hope that it will be useful,
+  but WITHOUT ANY WARRANTY; without even the implied warranty of
+  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+  GNU General Public License for more details.
+
+  You should have received a copy of the GNU General Public License
+  along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Abort rules, which decide when the sensors show that the engine must be emergency stopped.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    config::{AbortCondition, AbortRule, Configuration, Limits, SensorGroup},
+    state::State,
+    ControllerError,
+};
+
+use super::Readings;
+
+/// The abort rules of a configuration, along with whether each one is currently tripping.
+///
+/// The monitor is shared between every sensor listener thread.
+/// Each thread checks the rules which depend on its sensor group every time it reads the group.
+pub struct AbortMonitor {
+    /// Every rule being monitored.
+    watches: Vec<Watch>,
+}
+
+/// A single abort rule being monitored.
+struct Watch {
+    /// The rule.
+    rule: AbortRule,
+    /// The IDs of the sensor groups containing the sensors the rule depends on.
+    groups: Vec<usize>,
+    /// Whether the rule's condition currently holds, and whether the rule has tripped.
+    status: Mutex<Status>,
+}
+
+#[derive(Default)]
+/// The progress of an abort rule toward tripping.
+struct Status {
+    /// The time since which the rule's condition has held continuously, or `None` if it does not
+    /// currently hold.
+    since: Option<SystemTime>,
+    /// Whether the rule has tripped since the controller was last in standby with the rule's
+    /// condition not holding.
+    latched: bool,
+}
+
+/// The record of an abort rule tripping.
+pub struct Trip<'a> {
+    /// The name of the rule which tripped.
+    pub name: &'a str,
+    /// A description of the sensor values which made the rule trip.
+    pub values: String,
+}
+
+impl AbortMonitor {
+    /// Construct a new monitor for the abort rules of `configuration`, none of which are tripping.
+    ///
+    /// The `abort_range` of each sensor and derived channel is monitored as abort rules of its own
+    /// (one for each state with its own limits), which trip immediately.
+    pub fn new(configuration: &Configuration) -> AbortMonitor {
+        let ranges = configuration
+            .sensor_groups
+            .iter()
+            .flat_map(SensorGroup::channels)
+            .flat_map(|channel| {
+                let bands = match channel.abort_range {
+                    None => Vec::new(),
+                    Some(Limits::Always(limits)) => vec![(None, *limits)],
+                    Some(Limits::PerState(limits)) => limits
+                        .iter()
+                        .map(|(&state, &limits)| (Some(vec![state]), limits))
+                        .collect(),
+                };
+                bands
+                    .into_iter()
+                    .map(move |(states, (min, max))| AbortRule {
+                        name: format!("{} abort range", channel.label),
+                        condition: AbortCondition::OutOfRange {
+                            sensor: channel.label.to_string(),
+                            min,
+                            max,
+                        },
+                        persistence: 0,
+                        states,
+                    })
+            });
+
+        AbortMonitor {
+            watches: ranges
+                .chain(configuration.abort_rules.iter().cloned())
+                .map(|rule| Watch {
+                    groups: configuration
+                        .sensor_groups
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, group)| {
+                            group.channels().any(|channel| {
+                                rule.condition.sensors().any(|label| label == channel.label)
+                            })
+                        })
+                        .map(|(group_id, _)| group_id)
+                        .collect(),
+                    rule,
+                    status: Mutex::new(Status::default()),
+                })
+                .collect(),
+        }
+    }
+
+    /// Check every rule which depends on the sensor group with ID `group_id`, given that the
+    /// controller is in `state` and the time is `now`.
+    ///
+    /// Returns the rules which tripped.
+    /// A rule trips once its condition has held for its persistence time, and then stays latched
+    /// so that it does not trip again until the controller is in standby and the condition no
+    /// longer holds (or the rule is inactive in standby).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an internal lock is poisoned.
+    pub fn check(
+        &self,
+        group_id: usize,
+        readings: &Readings,
+        state: State,
+        now: SystemTime,
+    ) -> Result<Vec<Trip<'_>>, ControllerError> {
+        let mut trips = Vec::new();
+        for watch in self
+            .watches
+            .iter()
+            .filter(|watch| watch.groups.contains(&group_id))
+        {
+            let active = watch
+                .rule
+                .states
+                .as_ref()
+                .map_or(true, |states| states.contains(&state));
+            let values = if active {
+                evaluate(&watch.rule.condition, readings)?
+            } else {
+                None
+            };
+
+            let mut status = watch.status.lock()?;
+            let Some(values) = values else {
+                status.since = None;
+                // a value hovering at a limit must not trigger an emergency stop each time it
+                // crosses, so the latch is only released once the engine is safe again
+                if state == State::Standby {
+                    status.latched = false;
+                }
+                continue;
+            };
+
+            let since = *status.since.get_or_insert(now);
+            let persistence = Duration::from_millis(u64::from(watch.rule.persistence));
+            if !status.latched && now.duration_since(since).unwrap_or_default() >= persistence {
+                status.latched = true;
+                trips.push(Trip {
+                    name: &watch.rule.name,
+                    values,
+                });
+            }
+        }
+
+        Ok(trips)
+    }
+}
+
+/// Determine whether `condition` holds given the latest `readings`.
+/// Returns a description of the values of the sensors involved if it holds, and `None` otherwise.
+/// A sensor which has never been read, or whose most recent read failed, cannot make a condition
+/// hold.
+///
+/// # Errors
+///
+/// This function will return an error if the readings' internal lock is poisoned.
+fn evaluate(
+    condition: &AbortCondition,
+    readings: &Readings,
+) -> Result<Option<String>, ControllerError> {
+    Ok(match condition {
+        AbortCondition::OutOfRange { sensor, min, max } => readings
+            .current_sample(sensor)?
+            .filter(|sample| sample.filtered < *min || *max < sample.filtered)
+            .map(|sample| format!("{sensor} = {}", sample.filtered)),
+        AbortCondition::Vote {
+            sensors,
+            min,
+            max,
+            votes,
+        } => {
+            let mut ayes = 0;
+            let mut values = Vec::new();
+            for sensor in sensors {
+                match readings.current_sample(sensor)? {
+                    Some(sample) => {
+                        if sample.filtered < *min || *max < sample.filtered {
+                            ayes += 1;
+                        }
+                        values.push(format!("{sensor} = {}", sample.filtered));
+                    }
+                    None => values.push(format!("{sensor} unavailable")),
+                }
+            }
+            (ayes >= *votes).then(|| values.join(", "))
+        }
+        AbortCondition::RateOfChange { sensor, max_rate } => readings
+            .current_sample(sensor)?
+            .filter(|sample| sample.rate.abs() > *max_rate)
+            .map(|sample| format!("{sensor} changing at {}/s", sample.rate)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::data::Sample;
+
+    /// Construct a configuration with three pressure transducers, `PT_A`, `PT_B` and `PT_C`, and
+    /// the abort rules `rules`.
+    fn rules_config(rules: &str) -> Configuration {
+        let config = format!(
+            r##"{{
+                "frequency_status": 10,
+                "log_buffer_size": 1,
+                "sensor_groups": [
+                    {{
+                        "label": "FAST",
+                        "frequency_standby": 10,
+                        "frequency_ignition": 1000,
+                        "frequency_transmission": 10,
+                        "sensors": [
+                            {{
+                                "label": "PT_A",
+                                "color": "#000000",
+                                "units": "psi",
+                                "calibration": {{
+                                    "type": "Linear",
+                                    "slope": 1,
+                                    "intercept": 0
+                                }},
+                                "adc": 0,
+                                "channel": 0
+                            }},
+                            {{
+                                "label": "PT_B",
+                                "color": "#000000",
+                                "units": "psi",
+                                "calibration": {{
+                                    "type": "Linear",
+                                    "slope": 1,
+                                    "intercept": 0
+                                }},
+                                "adc": 0,
+                                "channel": 1
+                            }},
+                            {{
+                                "label": "PT_C",
+                                "color": "#000000",
+                                "units": "psi",
+                                "abort_range": [0, 1000],
+                                "calibration": {{
+                                    "type": "Linear",
+                                    "slope": 1,
+                                    "intercept": 0
+                                }},
+                                "adc": 0,
+                                "channel": 2
+                            }}
+                        ]
+                    }}
+                ],
+                "pre_ignite_time": 0,
+                "post_ignite_time": 0,
+                "drivers": [],
+                "abort_rules": {rules},
+                "ignition_sequence": [],
+                "estop_sequence": [],
+                "spi_mosi": 11,
+                "spi_miso": 12,
+                "spi_clk": 13,
+                "spi_frequency_clk": 50000,
+                "adc_cs": [14],
+                "pin_heartbeat": 0
+            }}"##
+        );
+        Configuration::parse(&mut Cursor::new(config)).unwrap()
+    }
+
+    /// Record a sample of each of the three transducers, given as `(filtered value, rate)`.
+    fn record(readings: &Readings, samples: [(f64, f64); 3]) {
+        let samples = samples.map(|(filtered, rate)| {
+            Some(Sample {
+                value: filtered,
+                filtered,
+                rate,
+            })
+        });
+        readings.update(0, &samples).unwrap();
+    }
+
+    /// Check the rules at `ms` milliseconds after the epoch, returning the names of those which
+    /// tripped.
+    fn check(monitor: &AbortMonitor, readings: &Readings, state: State, ms: u64) -> Vec<String> {
+        monitor
+            .check(
+                0,
+                readings,
+                state,
+                SystemTime::UNIX_EPOCH + Duration::from_millis(ms),
+            )
+            .unwrap()
+            .into_iter()
+            .map(|trip| trip.name.to_string())
+            .collect()
+    }
+
+    #[test]
+    /// Test that a rule only trips once its condition has persisted, and only trips once.
+    fn persistence() {
+        let config = rules_config(
+            r#"[{
+                "name": "overpressure",
+                "condition": { "type": "OutOfRange", "sensor": "PT_A", "min": 0, "max": 500 },
+                "persistence": 100
+            }]"#,
+        );
+        let readings = Readings::new(&config);
+        let monitor = AbortMonitor::new(&config);
+
+        record(&readings, [(600.0, 0.0), (0.0, 0.0), (0.0, 0.0)]);
+        assert!(check(&monitor, &readings, State::Ignite, 0).is_empty());
+        assert!(check(&monitor, &readings, State::Ignite, 99).is_empty());
+        assert_eq!(
+            check(&monitor, &readings, State::Ignite, 100),
+            ["overpressure"]
+        );
+        // the rule is latched
+        assert!(check(&monitor, &readings, State::Ignite, 200).is_empty());
+
+        // a dip back into range does not release the latch while the engine is running
+        record(&readings, [(400.0, 0.0), (0.0, 0.0), (0.0, 0.0)]);
+        assert!(check(&monitor, &readings, State::EStopping, 300).is_empty());
+        record(&readings, [(600.0, 0.0), (0.0, 0.0), (0.0, 0.0)]);
+        assert!(check(&monitor, &readings, State::EStopping, 350).is_empty());
+        assert!(check(&monitor, &readings, State::EStopping, 450).is_empty());
+
+        // nor does returning to standby while the condition still holds
+        assert!(check(&monitor, &readings, State::Standby, 500).is_empty());
+
+        // but the latch is released once the condition clears in standby, and the clock restarts
+        record(&readings, [(400.0, 0.0), (0.0, 0.0), (0.0, 0.0)]);
+        assert!(check(&monitor, &readings, State::Standby, 600).is_empty());
+        record(&readings, [(600.0, 0.0), (0.0, 0.0), (0.0, 0.0)]);
+        assert!(check(&monitor, &readings, State::Standby, 650).is_empty());
+        assert_eq!(
+            check(&monitor, &readings, State::Standby, 750),
+            ["overpressure"]
+        );
+    }
+
+    #[test]
+    /// Test that a sensor whose most recent read failed cannot keep a rule's condition holding.
+    fn failed_read() {
+        let config = rules_config(
+            r#"[{
+                "name": "overpressure",
+                "condition": { "type": "OutOfRange", "sensor": "PT_A", "min": 0, "max": 500 },
+                "persistence": 100
+            }]"#,
+        );
+        let readings = Readings::new(&config);
+        let monitor = AbortMonitor::new(&config);
+
+        record(&readings, [(600.0, 0.0), (0.0, 0.0), (0.0, 0.0)]);
+        assert!(check(&monitor, &readings, State::Ignite, 0).is_empty());
+
+        // the stale value does not count, so the clock restarts
+        let failed = Sample {
+            value: 0.0,
+            filtered: 0.0,
+            rate: 0.0,
+        };
+        readings
+            .update(0, &[None, Some(failed), Some(failed)])
+            .unwrap();
+        assert!(check(&monitor, &readings, State::Ignite, 50).is_empty());
+        record(&readings, [(600.0, 0.0), (0.0, 0.0), (0.0, 0.0)]);
+        assert!(check(&monitor, &readings, State::Ignite, 100).is_empty());
+        assert_eq!(
+            check(&monitor, &readings, State::Ignite, 200),
+            ["overpressure"]
+        );
+    }
+
+    #[test]
+    /// Test that a vote needs enough of its sensors to agree, and that a sensor's own range is
+    /// monitored as a rule.
+    fn vote() {
+        let config = rules_config(
+            r#"[{
+                "name": "chamber overpressure",
+                "condition": {
+                    "type": "Vote",
+                    "sensors": ["PT_A", "PT_B", "PT_C"],
+                    "min": 0,
+                    "max": 500,
+                    "votes": 2
+                }
+            }]"#,
+        );
+        let readings = Readings::new(&config);
+        let monitor = AbortMonitor::new(&config);
+
+        // one failed transducer is outvoted
+        record(&readings, [(900.0, 0.0), (300.0, 0.0), (300.0, 0.0)]);
+        assert!(check(&monitor, &readings, State::Ignite, 0).is_empty());
+
+        record(&readings, [(900.0, 0.0), (300.0, 0.0), (1200.0, 0.0)]);
+        assert_eq!(
+            check(&monitor, &readings, State::Ignite, 10),
+            ["PT_C abort range", "chamber overpressure"]
+        );
+    }
+
+    #[test]
+    /// Test that a rate-of-change rule only trips in its states.
+    fn rate_of_change() {
+        let config = rules_config(
+            r#"[{
+                "name": "pressure spike",
+                "condition": { "type": "RateOfChange", "sensor": "PT_B", "max_rate": 1000 },
+                "states": ["Ignite", "PostIgnite"]
+            }]"#,
+        );
+        let readings = Readings::new(&config);
+        let monitor = AbortMonitor::new(&config);
+
+        record(&readings, [(0.0, 0.0), (300.0, -1500.0), (0.0, 0.0)]);
+        assert!(check(&monitor, &readings, State::Standby, 0).is_empty());
+        assert_eq!(
+            check(&monitor, &readings, State::Ignite, 10),
+            ["pressure spike"]
+        );
+
+        record(&readings, [(0.0, 0.0), (300.0, 900.0), (0.0, 0.0)]);
+        assert!(check(&monitor, &readings, State::Ignite, 20).is_empty());
+    }
+}
@@ -0,0 +1,315 @@
+/*
+  slonk, a rocket engine controller.
+  Copyright (C) 2022 Rice Eclipse.
+
+  slonk is free software: you can redistribute it and/or modify
+  it under the terms of the GNU General Public License as published by
+  the Free Software Foundation, either version 3 of the License, or
+  (at your option) any later version.
+
+  slonk is distributed in the hope that it will be useful,
+  but WITHOUT ANY WARRANTY; without even the implied warranty of
+  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+  GNU General Public License for more details.
+
+  You should have received a copy of the GNU General Public License
+  along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Filters which smooth the calibrated values of sensors before they are checked against limits.
+
+use std::{
+    collections::VecDeque,
+    f64::consts::{PI, SQRT_2},
+    time::SystemTime,
+};
+
+use crate::config::Filter;
+
+/// The filters of a single sensor, applied one after another to each new sample.
+pub struct Pipeline {
+    /// The state of each filter, in the order they are applied.
+    stages: Vec<Stage>,
+    /// The time at which the most recent sample was taken, or `None` if there have been no samples
+    /// yet.
+    last_time: Option<SystemTime>,
+}
+
+/// The state of a single filter in a pipeline.
+enum Stage {
+    /// A moving average or median over a window of the most recent samples.
+    Window {
+        /// Whether the output is the median of the window (as opposed to its mean).
+        median: bool,
+        /// The maximum number of samples in the window.
+        width: usize,
+        /// The most recent samples, with the newest at the back.
+        samples: VecDeque<f64>,
+    },
+    /// A first-order low-pass filter.
+    LowPass {
+        /// The cutoff frequency, in hertz.
+        cutoff: f64,
+        /// The most recent output, or `None` if there have been no samples yet.
+        output: Option<f64>,
+    },
+    /// A second-order Butterworth low-pass filter.
+    Butterworth {
+        /// The cutoff frequency, in hertz.
+        cutoff: f64,
+        /// The two most recent inputs followed by the two most recent outputs, newest first, or
+        /// `None` if there have been no samples yet.
+        history: Option<[f64; 4]>,
+    },
+    /// An exponential moving average.
+    Exponential {
+        /// The weight given to each new sample.
+        alpha: f64,
+        /// The most recent output, or `None` if there have been no samples yet.
+        output: Option<f64>,
+    },
+}
+
+impl Pipeline {
+    /// Construct a new pipeline applying `filters` in order, which has not yet seen any samples.
+    pub fn new(filters: &[Filter]) -> Pipeline {
+        Pipeline {
+            stages: filters.iter().map(Stage::new).collect(),
+            last_time: None,
+        }
+    }
+
+    /// Feed the sample `value`, taken at `time`, through the pipeline, returning the filtered
+    /// value.
+    ///
+    /// Every filter starts from its first sample, so a pipeline's output is never biased toward
+    /// any value which was not actually read.
+    pub fn update(&mut self, value: f64, time: SystemTime) -> f64 {
+        let dt = self
+            .last_time
+            .and_then(|last| time.duration_since(last).ok())
+            .map(|dt| dt.as_secs_f64());
+        self.last_time = Some(time);
+
+        self.stages
+            .iter_mut()
+            .fold(value, |value, stage| stage.update(value, dt))
+    }
+}
+
+impl Stage {
+    /// Construct the state of a filter which has not yet seen any samples.
+    fn new(filter: &Filter) -> Stage {
+        match *filter {
+            Filter::MovingAverage { width } => Stage::Window {
+                median: false,
+                width,
+                samples: VecDeque::with_capacity(width),
+            },
+            Filter::Median { width } => Stage::Window {
+                median: true,
+                width,
+                samples: VecDeque::with_capacity(width),
+            },
+            Filter::LowPass { cutoff } => Stage::LowPass {
+                cutoff,
+                output: None,
+            },
+            Filter::Butterworth { cutoff } => Stage::Butterworth {
+                cutoff,
+                history: None,
+            },
+            Filter::Exponential { alpha } => Stage::Exponential {
+                alpha,
+                output: None,
+            },
+        }
+    }
+
+    /// Feed the sample `x` through this filter, returning its output.
+    /// `dt` is the time since the previous sample in seconds, or `None` if this is the first.
+    fn update(&mut self, x: f64, dt: Option<f64>) -> f64 {
+        match self {
+            Stage::Window {
+                median,
+                width,
+                samples,
+            } => {
+                if samples.len() == *width {
+                    samples.pop_front();
+                }
+                samples.push_back(x);
+
+                #[allow(clippy::cast_precision_loss)]
+                let n = samples.len() as f64;
+                if *median {
+                    let mut sorted: Vec<f64> = samples.iter().copied().collect();
+                    sorted.sort_by(f64::total_cmp);
+                    let mid = sorted.len() / 2;
+                    if sorted.len() % 2 == 0 {
+                        (sorted[mid - 1] + sorted[mid]) / 2.0
+                    } else {
+                        sorted[mid]
+                    }
+                } else {
+                    samples.iter().sum::<f64>() / n
+                }
+            }
+            Stage::LowPass { cutoff, output } => {
+                let y = match (*output, dt) {
+                    (Some(y), Some(dt)) => {
+                        let rc = 1.0 / (2.0 * PI * *cutoff);
+                        y + (x - y) * dt / (rc + dt)
+                    }
+                    _ => x,
+                };
+                *output = Some(y);
+                y
+            }
+            Stage::Butterworth { cutoff, history } => match (*history, dt) {
+                // no time has passed, so there is nothing new to say
+                (Some([_, _, y1, _]), Some(dt)) if dt <= 0.0 => y1,
+                (Some([x1, x2, y1, y2]), Some(dt)) if *cutoff * dt < 0.5 => {
+                    // bilinear transform of the analog filter, with the cutoff pre-warped
+                    let k = (PI * *cutoff * dt).tan();
+                    let norm = 1.0 / (1.0 + SQRT_2 * k + k * k);
+                    let b0 = k * k * norm;
+                    let a1 = 2.0 * (k * k - 1.0) * norm;
+                    let a2 = (1.0 - SQRT_2 * k + k * k) * norm;
+                    let y = b0 * (x + 2.0 * x1 + x2) - a1 * y1 - a2 * y2;
+                    *history = Some([x, x1, y, y1]);
+                    y
+                }
+                _ => {
+                    // either this is the first sample, or the sampling is too slow for the cutoff
+                    // to mean anything, so start again from a steady state
+                    *history = Some([x; 4]);
+                    x
+                }
+            },
+            Stage::Exponential { alpha, output } => {
+                let y = match *output {
+                    Some(y) => *alpha * x + (1.0 - *alpha) * y,
+                    None => x,
+                };
+                *output = Some(y);
+                y
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Feed `values`, sampled at `frequency` hertz, through a pipeline of `filters`, returning
+    /// each output.
+    fn run(filters: &[Filter], frequency: u32, values: impl IntoIterator<Item = f64>) -> Vec<f64> {
+        let mut pipeline = Pipeline::new(filters);
+        let period = Duration::from_secs(1) / frequency;
+        let mut time = SystemTime::UNIX_EPOCH;
+        values
+            .into_iter()
+            .map(|value| {
+                time += period;
+                pipeline.update(value, time)
+            })
+            .collect()
+    }
+
+    /// Get the amplitude of the output of `filter` in its second second, when fed a unit sine wave
+    /// at `signal` hertz, sampled at 1 kHz.
+    fn amplitude(filter: Filter, signal: f64) -> f64 {
+        let samples = (0..2000).map(|i| (2.0 * PI * signal * f64::from(i) / 1000.0).sin());
+        run(&[filter], 1000, samples)[1000..]
+            .iter()
+            .fold(0.0, |max: f64, y| max.max(y.abs()))
+    }
+
+    #[test]
+    /// Test that a moving average is the true mean of its window, starting from the first sample.
+    fn moving_average() {
+        assert_eq!(
+            run(
+                &[Filter::MovingAverage { width: 3 }],
+                100,
+                [3.0, 6.0, 9.0, 12.0]
+            ),
+            [3.0, 4.5, 6.0, 9.0]
+        );
+    }
+
+    #[test]
+    /// Test that a median filter rejects a single-sample spike.
+    fn median_rejects_spike() {
+        assert_eq!(
+            run(
+                &[Filter::Median { width: 3 }],
+                100,
+                [1.0, 1.0, 100.0, 1.0, 1.0]
+            ),
+            [1.0; 5]
+        );
+    }
+
+    #[test]
+    /// Test that an exponential moving average weights new samples by alpha.
+    fn exponential() {
+        assert_eq!(
+            run(
+                &[Filter::Exponential { alpha: 0.5 }],
+                100,
+                [0.0, 10.0, 10.0]
+            ),
+            [0.0, 5.0, 7.5]
+        );
+    }
+
+    #[test]
+    /// Test that a first-order low-pass filter reaches 63% of a step after one time constant.
+    fn low_pass_step() {
+        // a time constant of 100 ms, or 100 samples
+        let cutoff = 10.0 / (2.0 * PI);
+        let outputs = run(
+            &[Filter::LowPass { cutoff }],
+            1000,
+            std::iter::once(0.0).chain(std::iter::repeat(1.0).take(100)),
+        );
+        let y = outputs.last().unwrap();
+        assert!((y - (1.0 - (-1.0f64).exp())).abs() < 0.01, "got {y}");
+    }
+
+    #[test]
+    /// Test that a Butterworth filter passes low frequencies, is 3 dB down at its cutoff, and
+    /// strongly attenuates high frequencies.
+    fn butterworth_response() {
+        let filter = Filter::Butterworth { cutoff: 10.0 };
+        assert!((amplitude(filter, 1.0) - 1.0).abs() < 0.02);
+        assert!((amplitude(filter, 10.0) - SQRT_2 / 2.0).abs() < 0.02);
+        assert!(amplitude(filter, 200.0) < 0.01);
+
+        // a constant signal passes straight through
+        assert!(run(&[filter], 1000, [5.0; 100])
+            .iter()
+            .all(|y| (y - 5.0).abs() < 1e-9));
+    }
+
+    #[test]
+    /// Test that filters in a pipeline are applied in order.
+    fn pipeline_order() {
+        let outputs = run(
+            &[
+                Filter::Median { width: 3 },
+                Filter::MovingAverage { width: 2 },
+            ],
+            100,
+            [0.0, 0.0, 90.0, 30.0, 30.0],
+        );
+        // the spike is removed by the median before it can reach the average
+        assert_eq!(outputs, [0.0, 0.0, 0.0, 15.0, 30.0]);
+        assert_eq!(run(&[], 100, [1.0, 2.0]), [1.0, 2.0]);
+    }
+}
@@ -0,0 +1,1665 @@
+/*
+  slonk, a rocket engine controller.
+  Copyright (C) 2022 Rice Eclipse.
+
+  slonk is free software: you can redistribute it and/or modify
+  it under the terms of the GNU General Public License as published by
+  the Free Software Foundation, either version 3 of the License, or
+  (at your option) any later version.
+
+  slonk is distributed in the hope that it will be useful,
+  but WITHOUT ANY WARRANTY; without even the implied warranty of
+  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+  GNU General Public License for more details.
+
+  You should have received a copy of the GNU General Public License
+  along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! The core sensor data collection threads.
+
+mod abort;
+mod filter;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Write,
+    sync::{mpsc::Receiver, Mutex},
+    thread::{sleep, Scope},
+    time::{Duration, SystemTime},
+};
+
+pub use abort::AbortMonitor;
+use filter::Pipeline;
+use serde::Serialize;
+
+use crate::{
+    config::{Channel, Configuration, Limits},
+    console::UserLog,
+    execution::emergency_stop,
+    hardware::{Adc, GpioPin},
+    outgoing::{DashChannel, Message, SensorReading},
+    state::{Guard, State, Transition},
+    ControllerError,
+};
+
+/// The most recent calibrated value of every sensor and derived channel.
+/// Derived channels are treated exactly like sensors, and are looked up by their labels too.
+///
+/// This is written to by the sensor listener threads, and allows procedures to make decisions
+/// based on the current sensor values.
+pub struct Readings {
+    /// The group ID and sensor ID of each sensor, keyed by the sensor's label.
+    indices: HashMap<String, (usize, usize)>,
+    /// The latest information about each sensor.
+    /// Each index corresponds to a sensor group in the configuration, and each index in each
+    /// group corresponds to a sensor in that group.
+    values: Vec<Mutex<Vec<Latest>>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A single successful reading of a sensor.
+pub struct Sample {
+    /// The calibrated value of the sensor.
+    pub value: f64,
+    /// The calibrated value after being passed through the sensor's filters.
+    pub filtered: f64,
+    /// The rate of change of the filtered value since the previous sample, in units per second.
+    /// This is zero for the first sample.
+    pub rate: f64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+/// How far outside of its limits a sensor is.
+/// The levels are ordered from least to most severe.
+pub enum AlarmLevel {
+    /// The sensor is within all of its limits.
+    #[default]
+    Nominal,
+    /// The sensor is outside of its warning limits.
+    Warning,
+    /// The sensor is outside of its abort limits.
+    Abort,
+}
+
+#[derive(Clone, Copy, Default)]
+/// The latest information about a single sensor.
+struct Latest {
+    /// The most recent sample of the sensor, or `None` if it has never been read.
+    sample: Option<Sample>,
+    /// The current alarm level of the sensor.
+    alarm: AlarmLevel,
+    /// A record of which of the most recent samples of the sensor failed to be read.
+    /// The lowest bit corresponds to the most recent sample, and a bit is set if that sample
+    /// failed.
+    errors: u64,
+}
+
+impl Readings {
+    /// Construct a new set of readings for the sensors in `configuration`, none of which have yet
+    /// been read.
+    pub fn new(configuration: &Configuration) -> Readings {
+        Readings {
+            indices: configuration
+                .sensor_groups
+                .iter()
+                .enumerate()
+                .flat_map(|(group_id, group)| {
+                    group
+                        .channels()
+                        .enumerate()
+                        .map(move |(sensor_id, channel)| {
+                            (channel.label.to_string(), (group_id, sensor_id))
+                        })
+                })
+                .collect(),
+            values: configuration
+                .sensor_groups
+                .iter()
+                .map(|group| Mutex::new(vec![Latest::default(); group.channels().count()]))
+                .collect(),
+        }
+    }
+
+    /// Get the most recent calibrated value of the sensor labeled `label`.
+    /// Returns `None` if there is no such sensor or if it has never been read.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the internal lock is poisoned.
+    pub fn get(&self, label: &str) -> Result<Option<f64>, ControllerError> {
+        Ok(self.sample(label)?.map(|sample| sample.value))
+    }
+
+    /// Get the most recent sample of the sensor labeled `label`.
+    /// Returns `None` if there is no such sensor or if it has never been read.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the internal lock is poisoned.
+    pub fn sample(&self, label: &str) -> Result<Option<Sample>, ControllerError> {
+        let Some(&(group_id, sensor_id)) = self.indices.get(label) else {
+            return Ok(None);
+        };
+        Ok(self.values[group_id].lock()?[sensor_id].sample)
+    }
+
+    /// Get the most recent sample of the sensor labeled `label`, but only if the most recent
+    /// attempt to read it succeeded.
+    /// Returns `None` if there is no such sensor, if it has never been read, or if its last read
+    /// failed.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the internal lock is poisoned.
+    pub fn current_sample(&self, label: &str) -> Result<Option<Sample>, ControllerError> {
+        let Some(&(group_id, sensor_id)) = self.indices.get(label) else {
+            return Ok(None);
+        };
+        let latest = self.values[group_id].lock()?[sensor_id];
+        Ok(latest.sample.filter(|_| latest.errors & 1 == 0))
+    }
+
+    /// Get the current alarm level of the sensor labeled `label`.
+    /// Returns `None` if there is no such sensor.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the internal lock is poisoned.
+    pub fn alarm(&self, label: &str) -> Result<Option<AlarmLevel>, ControllerError> {
+        let Some(&(group_id, sensor_id)) = self.indices.get(label) else {
+            return Ok(None);
+        };
+        Ok(Some(self.values[group_id].lock()?[sensor_id].alarm))
+    }
+
+    /// Count the number of times that the sensor labeled `label` failed to be read in its most
+    /// recent `samples` samples.
+    /// At most the 64 most recent samples are remembered.
+    /// Returns `None` if there is no such sensor.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the internal lock is poisoned.
+    pub fn read_errors(&self, label: &str, samples: u8) -> Result<Option<u32>, ControllerError> {
+        let Some(&(group_id, sensor_id)) = self.indices.get(label) else {
+            return Ok(None);
+        };
+        let errors = self.values[group_id].lock()?[sensor_id].errors;
+        let mask = u64::MAX
+            .checked_shr(64 - u32::from(samples.min(64)))
+            .unwrap_or(0);
+        Ok(Some((errors & mask).count_ones()))
+    }
+
+    /// Record the most recent samples of the sensors in the group with ID `group_id`.
+    /// Each element of `samples` is `None` if its sensor failed to be read.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the internal lock is poisoned.
+    pub fn update(
+        &self,
+        group_id: usize,
+        samples: &[Option<Sample>],
+    ) -> Result<(), ControllerError> {
+        let mut group_guard = self.values[group_id].lock()?;
+        for (latest, sample) in group_guard.iter_mut().zip(samples) {
+            latest.errors = (latest.errors << 1) | u64::from(sample.is_none());
+            if sample.is_some() {
+                latest.sample = *sample;
+            }
+        }
+        Ok(())
+    }
+
+    /// Record the current alarm level of the sensor with ID `sensor_id` in the group with ID
+    /// `group_id`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the internal lock is poisoned.
+    pub fn set_alarm(
+        &self,
+        group_id: usize,
+        sensor_id: usize,
+        alarm: AlarmLevel,
+    ) -> Result<(), ControllerError> {
+        self.values[group_id].lock()?[sensor_id].alarm = alarm;
+        Ok(())
+    }
+}
+
+/// Determine the alarm level of `channel` in `state`, given that its filtered value is `value` and
+/// its alarm level was previously `previous`.
+///
+/// Once an alarm has been raised, the value must come back inside the limits by at least the
+/// channel's hysteresis before the alarm is lowered, so that a value hovering near a limit does not
+/// repeatedly raise and lower it.
+pub fn alarm_level(
+    channel: &Channel<'_>,
+    state: State,
+    value: f64,
+    previous: AlarmLevel,
+) -> AlarmLevel {
+    let outside = |limits: Option<&Limits>, level: AlarmLevel| {
+        let Some((min, max)) = limits.and_then(|limits| limits.get(state)) else {
+            return false;
+        };
+        let margin = if previous >= level {
+            channel.hysteresis
+        } else {
+            0.0
+        };
+        value < min + margin || value > max - margin
+    };
+
+    if outside(channel.abort_range, AlarmLevel::Abort) {
+        AlarmLevel::Abort
+    } else if outside(channel.warn_range, AlarmLevel::Warning) {
+        AlarmLevel::Warning
+    } else {
+        AlarmLevel::Nominal
+    }
+}
+
+#[allow(dead_code)]
+#[allow(clippy::too_many_arguments, clippy::too_many_lines)]
+/// A function which will continuously listen for new data from sensors.
+/// It will loop indefinitely.
+///
+/// # Inputs
+///
+/// * `thread_scope`: A reference to a scope that this function can use to spawn other threads.
+///   This is required so that the sensor listener thread can emergency-stop, if needed.
+/// * `group_id`: The ID of the sensor group that this thread is responsible for.
+///   This is equal to the index of the sensor group in the configuration object.
+/// * `adcs`: The set of ADCs which can be read from by the sensors.
+/// * `configuration`: The primary configuration of the controller.
+/// * `driver_lines`: The GPIO lines for each driver.
+/// * `log_files`: Handles for log files associated with the sensors and derived channels in this
+///   sensor group.
+///   Each index corresponds exactly to its associated index in `SensorGroup::channels`.
+/// * `readings`: The most recent values of every sensor.
+///   This thread will update the values for the sensors in its group as it reads them.
+/// * `aborts`: The abort rules, which this thread checks every time it reads its group.
+/// * `state`: The state of the whole system.
+///   If a sensor enters an invalid value during ignition, this thread will automatically update
+///   the state as needed.
+/// * `dashboard_stream`: A stream where messages can be sent to the dashboard.
+///
+/// # Errors
+///
+/// This function will only return an error if the controller state status lock is poisoned.
+///
+/// # Panics
+///
+/// This function will panic in any of the following cases:
+///
+/// * If the value of `group_id` does not correspond to an existing sensor group.
+/// * If the current system time is before the UNIX epoch.
+pub fn sensor_listen<'a>(
+    thread_scope: &'a Scope<'a, '_>,
+    group_id: u8,
+    configuration: &'a Configuration,
+    driver_lines: &'a Mutex<Vec<impl GpioPin + Send + Sync>>,
+    log_files: &mut [impl Write],
+    user_log: &'a UserLog<impl Write + Send>,
+    adcs: &[Mutex<impl Adc>],
+    readings: &'a Readings,
+    aborts: &AbortMonitor,
+    state: &'a Guard,
+    dashboard_stream: &'a DashChannel<impl Write, impl Write>,
+) -> Result<(), ControllerError> {
+    assert!(usize::from(group_id) < configuration.sensor_groups.len());
+
+    // more convenient access to our sensor group data
+    let group = &configuration.sensor_groups[usize::from(group_id)];
+    // the last time that we sent a sensor status update
+    let mut last_transmission_time = SystemTime::now();
+
+    // every sensor in the group, followed by every derived channel
+    let n_channels = group.channels().count();
+    // the ID of each sensor in the group, so that derived channels can use this pass's values
+    let local_ids: HashMap<&str, usize> = group
+        .sensors
+        .iter()
+        .enumerate()
+        .map(|(idx, sensor)| (sensor.label.as_str(), idx))
+        .collect();
+
+    // the most recent reading from each channel which has *not* already been sent to the
+    // dashboard.
+    // each element will be None if the most recent reading was sent to the dashboard.
+    // derived channels have no raw reading.
+    let mut transmission_readings: Vec<Option<(SystemTime, Option<u16>, f64)>> =
+        vec![None; n_channels];
+
+    // most recent values read, to be logged.
+    // in each queue, the "back" contains the most recent readings and the "front" contains the
+    // oldest ones.
+    let mut most_recent_readings: Vec<VecDeque<(SystemTime, Option<u16>, f64)>> =
+        vec![VecDeque::new(); n_channels];
+
+    // the filters which smooth each channel's values before they are checked against its limits
+    let mut filters: Vec<Pipeline> = group
+        .channels()
+        .map(|channel| Pipeline::new(channel.filters))
+        .collect();
+    // the time and filtered value of the previous sample of each channel, for finding its rate of
+    // change
+    let mut previous_filtered: Vec<Option<(SystemTime, f64)>> = vec![None; n_channels];
+    // the alarm level of each channel, as last reported
+    let mut alarms = vec![AlarmLevel::Nominal; n_channels];
+
+    let standby_period = Duration::from_secs(1) / group.frequency_standby;
+    let ignition_period = Duration::from_secs(1) / group.frequency_ignition;
+    let transmission_period = Duration::from_secs(1) / group.frequency_transmission;
+
+    // the calibrated values read on this pass, to be shared with everyone else
+    let mut latest_samples: Vec<Option<Sample>> = vec![None; n_channels];
+
+    while state.status()? != State::Quit {
+        // read from each device
+        latest_samples.fill(None);
+        for (idx, sensor) in group.sensors.iter().enumerate() {
+            let Ok(mut adc_guard) = adcs[usize::from(sensor.adc)].lock() else {
+                #[allow(unused_must_use)]
+                {
+                    user_log.critical(&format!(
+                        "unable to acquire mutex on sensor ADC for {} due to poisoning",
+                        sensor.label
+                    ));
+                }
+                continue;
+            };
+            let adc_read_result = adc_guard.read(sensor.channel);
+            let Ok(reading) = adc_read_result else {
+                #[allow(unused_must_use)]
+                {
+                    user_log.warn(&format!(
+                        "unable to read {} due to error: {adc_read_result:?}",
+                        sensor.label
+                    ));
+                }
+                continue;
+            };
+            let read_time = SystemTime::now();
+            let cold_junction = match sensor.calibration.cold_junction() {
+                Some(label) => readings.get(label)?,
+                None => None,
+            };
+            let Some(calibrated_value) = sensor.calibration.apply(reading, cold_junction) else {
+                #[allow(unused_must_use)]
+                {
+                    user_log.warn(&format!(
+                        "unable to calibrate {} because its cold junction has not been read",
+                        sensor.label
+                    ));
+                }
+                continue;
+            };
+            most_recent_readings[idx].push_back((read_time, Some(reading), calibrated_value));
+            transmission_readings[idx] = Some((read_time, Some(reading), calibrated_value));
+            latest_samples[idx] = Some(sample(
+                &mut filters[idx],
+                &mut previous_filtered[idx],
+                calibrated_value,
+                read_time,
+            ));
+        }
+
+        // compute each derived channel from the values of its inputs.
+        // inputs in this group use the values from this pass, and inputs in other groups must
+        // have been read successfully on their group's latest pass, so that a failed read is not
+        // hidden behind a stale value.
+        for (offset, derived) in group.derived.iter().enumerate() {
+            let idx = group.sensors.len() + offset;
+            let value = derived
+                .expression
+                .evaluate(&mut |label| match local_ids.get(label) {
+                    Some(&id) => Ok(latest_samples[id].map(|sample| sample.value)),
+                    None => readings
+                        .current_sample(label)
+                        .map(|sample| sample.map(|sample| sample.value)),
+                })?;
+            // a missing input or a nonsensical result counts as a failed read
+            let Some(value) = value.filter(|value| value.is_finite()) else {
+                continue;
+            };
+            let time = SystemTime::now();
+            most_recent_readings[idx].push_back((time, None, value));
+            transmission_readings[idx] = Some((time, None, value));
+            latest_samples[idx] = Some(sample(
+                &mut filters[idx],
+                &mut previous_filtered[idx],
+                value,
+                time,
+            ));
+        }
+        readings.update(usize::from(group_id), &latest_samples)?;
+
+        let status = state.status()?;
+        update_alarms(
+            group_id,
+            configuration,
+            &latest_samples,
+            &mut alarms,
+            readings,
+            status,
+            user_log,
+            dashboard_stream,
+        )?;
+
+        // if any abort rule tripped, immediately start emergency stopping
+        let trips = aborts.check(usize::from(group_id), readings, status, SystemTime::now())?;
+        #[allow(unused_must_use)]
+        if !trips.is_empty() {
+            for trip in &trips {
+                user_log.warn(&format!(
+                    "Abort rule {:?} tripped with {}, attempting emergency stop",
+                    trip.name, trip.values
+                ));
+            }
+            // oh no! something has gone wrong!
+            // spin up another thread to emergency stop.
+            // this may return an error due to illegal transistion, but that is not our
+            // problem.
+            thread_scope.spawn(|| {
+                emergency_stop(configuration, driver_lines, readings, state, user_log);
+            });
+        }
+
+        // transmit data to the dashboard if it's been long enough since our last transmission
+        if SystemTime::now() > last_transmission_time + transmission_period {
+            if dashboard_stream.has_target() {
+                // send message to dashboard
+                dashboard_stream.send(&Message::SensorValue {
+                    group_id,
+                    readings: &transmission_readings
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(sensor_id, opt)| {
+                            #[allow(clippy::cast_possible_truncation)]
+                            opt.map(|(time, reading, value)| SensorReading {
+                                sensor_id: sensor_id as u8,
+                                reading,
+                                value,
+                                time,
+                            })
+                        })
+                        .collect::<Vec<_>>(),
+                })?;
+            }
+
+            last_transmission_time = SystemTime::now();
+            transmission_readings = vec![None; n_channels];
+        }
+
+        for (sensor_id, reading_queue) in most_recent_readings.iter_mut().enumerate() {
+            if reading_queue.len() >= configuration.log_buffer_size {
+                #[allow(unused_must_use)]
+                if let Err(e) = write_sensor_log(&mut log_files[sensor_id], reading_queue.iter()) {
+                    user_log.warn(&format!(
+                        "unable to write data for sensor {}: {e:?}",
+                        group.channels().nth(sensor_id).unwrap().label
+                    ));
+                }
+
+                reading_queue.clear();
+            }
+        }
+
+        // use the system state to determine how long to sleep until the next loop.
+        // standby means we are sampling slowly, and anything else means we sample quickly.
+        let sleep_time = match state.status()? {
+            State::Standby => standby_period,
+            _ => ignition_period,
+        };
+
+        // now take a nap until we next need to get data
+        sleep(sleep_time);
+    }
+
+    // we are now quitting
+    Ok(())
+}
+
+/// Pass the calibrated value `value` of a channel, taken at `time`, through the channel's
+/// `filters`, and work out its rate of change since the `previous` filtered value, which is then
+/// replaced.
+fn sample(
+    filters: &mut Pipeline,
+    previous: &mut Option<(SystemTime, f64)>,
+    value: f64,
+    time: SystemTime,
+) -> Sample {
+    let filtered = filters.update(value, time);
+    let rate = match *previous {
+        Some((previous_time, previous_value)) => match time.duration_since(previous_time) {
+            Ok(dt) if !dt.is_zero() => (filtered - previous_value) / dt.as_secs_f64(),
+            _ => 0.0,
+        },
+        None => 0.0,
+    };
+    *previous = Some((time, filtered));
+    Sample {
+        value,
+        filtered,
+        rate,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+/// Update the alarm level of each channel in the group with ID `group_id` from its latest sample,
+/// reporting every change to the user log and the dashboard.
+///
+/// `alarms` is the alarm level of each channel in the group as last reported, and is updated in
+/// place.
+/// Channels which were not read on this pass are judged on their last successful sample, so that
+/// their alarm level still follows the limits of the current state.
+///
+/// # Errors
+///
+/// This function will return an error if a lock is poisoned or if a message cannot be queued for
+/// the dashboard.
+fn update_alarms(
+    group_id: u8,
+    configuration: &Configuration,
+    latest_samples: &[Option<Sample>],
+    alarms: &mut [AlarmLevel],
+    readings: &Readings,
+    state: State,
+    user_log: &UserLog<impl Write>,
+    dashboard_stream: &DashChannel<impl Write, impl Write>,
+) -> Result<(), ControllerError> {
+    let group = &configuration.sensor_groups[usize::from(group_id)];
+    for (sensor_id, (channel, sample)) in group.channels().zip(latest_samples).enumerate() {
+        let sample = match sample {
+            Some(sample) => *sample,
+            None => match readings.sample(channel.label)? {
+                Some(sample) => sample,
+                None => continue,
+            },
+        };
+        let level = alarm_level(&channel, state, sample.filtered, alarms[sensor_id]);
+        if level == alarms[sensor_id] {
+            continue;
+        }
+        alarms[sensor_id] = level;
+        readings.set_alarm(usize::from(group_id), sensor_id, level)?;
+
+        #[allow(unused_must_use)]
+        match level {
+            AlarmLevel::Nominal => {
+                user_log.info(&format!(
+                    "{} is back within its limits at {}",
+                    channel.label, sample.filtered
+                ));
+            }
+            AlarmLevel::Warning => {
+                user_log.warn(&format!(
+                    "{} is outside of its warning limits at {}",
+                    channel.label, sample.filtered
+                ));
+            }
+            AlarmLevel::Abort => {
+                user_log.warn(&format!(
+                    "{} is outside of its abort limits at {}",
+                    channel.label, sample.filtered
+                ));
+            }
+        }
+        #[allow(clippy::cast_possible_truncation)]
+        dashboard_stream.send(&Message::Alarm {
+            group_id,
+            sensor_id: sensor_id as u8,
+            level,
+            value: sample.filtered,
+        })?;
+    }
+
+    Ok(())
+}
+
+#[allow(dead_code)]
+/// Periodically check in on the status of the drivers, and log that status.
+/// Will also transmit that driver status to the dashboard.
+///
+/// # Inputs
+///
+/// * `configuration`: The configuration for the current mode of the controller.
+/// * `driver_lines`: The driver GPIO pins.
+/// * `log_file`: The file to which logs should be written.
+///   Information will be written to the log file in the following format:
+///     ```text
+///      {time},{driver0_status},{driver1_status},
+///
+///     ```
+///     with one row for every sample.
+///   `{time}` is the number of nanoseconds since the UNIX epoch.
+/// * `state`: The overall system state.
+///   This function will only return after `State` transitions to `State::Quit`.
+/// * `dashboard_stream`: A channel by which messages can be sent to the dashboard.
+///
+/// # Errors
+///
+/// This function will return an error if we are unable to acquire a lock or if we are unable to
+/// write to the log file.
+///
+/// # Panics
+///
+/// This function will panic if the current time is before the UNIX epoch.
+pub fn driver_status_listen(
+    configuration: &Configuration,
+    driver_lines: &Mutex<Vec<impl GpioPin>>,
+    log_file: &mut impl Write,
+    user_log: &UserLog<impl Write>,
+    state: &Guard,
+    dashboard_stream: &DashChannel<impl Write, impl Write>,
+) -> Result<(), ControllerError> {
+    // the time required to sleep
+    let sleep_time = Duration::from_secs(1) / configuration.frequency_status;
+    let mut driver_states = vec![false; driver_lines.lock()?.len()];
+    while state.status()? != State::Quit {
+        // read off the states of the drivers
+        let read_time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+
+        let mut drivers_guard = driver_lines.lock().map_err(|e| {
+            #[allow(unused_must_use)]
+            {
+                user_log.critical("Unable to acquire lock over driver mutex");
+            }
+            e
+        })?;
+
+        for (driver_idx, (driver_line, state_ref)) in
+            drivers_guard.iter_mut().zip(&mut driver_states).enumerate()
+        {
+            match driver_line.read() {
+                Ok(read_value) => *state_ref = read_value,
+                Err(e) => {
+                    user_log.warn(&format!(
+                        "Unable to read state of driver {driver_idx}: {e:?}"
+                    ))?;
+                }
+            }
+        }
+
+        // write driver status information
+        write_driver_log(log_file, read_time, &driver_states)?;
+
+        // optionally transmit to dashboard
+        dashboard_stream.send(&Message::DriverValue {
+            values: &driver_states,
+        })?;
+
+        drop(drivers_guard); // don't keep the drivers guard while we sleep!
+
+        // take a nap until we are ready to send another message
+        sleep(sleep_time);
+    }
+
+    Ok(())
+}
+
+/// Write the status of the drivers to a log file.
+///
+/// # Inputs
+///
+/// * `log_file`: The file to which log information should be written.
+/// * `read_time`: The time at which the read of driver states occurred.
+/// * `driver_states`: The state of each driver.
+///
+/// # Errors
+///
+/// This function will return an `Err` if writing to the log file fails.
+fn write_driver_log(
+    log_file: &mut impl Write,
+    read_time: Duration,
+    driver_states: &[bool],
+) -> std::io::Result<()> {
+    write!(log_file, "{},", read_time.as_nanos())?;
+    for driver_state in driver_states {
+        write!(log_file, "{driver_state},")?;
+    }
+    writeln!(log_file)?;
+
+    Ok(())
+}
+
+/// Log every change in the state of the controller, and transmit each change to the dashboard.
+///
+/// # Inputs
+///
+/// * `transitions`: A channel on which every state transition will be received.
+///   This should be created by calling `Guard::subscribe` before any other thread can change the
+///   state.
+/// * `log_file`: The file to which logs should be written.
+///   Information will be written to the log file in the following format:
+///   ```text
+///   {time},{from},{to}
+///
+///   ```
+///   with one row for every transition.
+///   `{time}` is the number of nanoseconds since the UNIX epoch.
+/// * `user_log`: The log to which any failure to reach the dashboard is reported.
+/// * `dashboard_stream`: A channel by which messages can be sent to the dashboard.
+///
+/// A transition from a state into itself (such as a second emergency stop during an emergency
+/// stop) is not a change, and is neither logged nor sent.
+/// Failing to send a transition to the dashboard does not stop the transitions from being logged.
+///
+/// This function will return after it receives a transition into `State::Quit`, or if every
+/// sender for `transitions` is dropped.
+///
+/// # Errors
+///
+/// This function will return an error if we are unable to write to the log file.
+///
+/// # Panics
+///
+/// This function will panic if a transition occurred before the UNIX epoch.
+pub fn state_listen(
+    transitions: Receiver<Transition>,
+    log_file: &mut impl Write,
+    user_log: &UserLog<impl Write>,
+    dashboard_stream: &DashChannel<impl Write, impl Write>,
+) -> Result<(), ControllerError> {
+    for transition in transitions {
+        if transition.from == transition.to {
+            continue;
+        }
+
+        writeln!(
+            log_file,
+            "{},{:?},{:?}",
+            transition
+                .time
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos(),
+            transition.from,
+            transition.to,
+        )?;
+        log_file.flush()?;
+
+        if let Err(e) = dashboard_stream.send(&Message::State {
+            from: transition.from,
+            to: transition.to,
+            time: transition.time,
+        }) {
+            user_log.warn(&format!(
+                "Unable to send transition from {:?} to {:?} to dashboard: {e:?}",
+                transition.from, transition.to
+            ))?;
+        }
+
+        if transition.to == State::Quit {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write a new log datum to the sensor log file.
+///
+/// # Inputs
+///
+/// * `log_file`: The file to which the log will be written.
+///   There must be exactly one log file per sensor.
+/// * `adc_readings`: All the most recent sensor readings to be written to the file.
+///
+/// # Results
+///
+/// Will write the data from the ADC readings in a CSV format to the file.
+/// There will be three "columns" to this CSV data:
+/// 1. The time since the UNIX epoch, in nanoseconds.
+/// 1. The raw ADC value of the sensor at this time.
+///    This is empty for a derived channel, which has no raw value.
+/// 1. The calibrated value of the sensor at this time.
+///
+/// Will also include a trailing newline after the last row.
+/// At the end of writing all of these lines, the file will be "flushed," meaning that all data will
+/// be immediately saved.
+///
+/// For instance, if a sensor had a reading of 42 at a time of 1 second, 500 nanoseconds after the
+/// UNIX epoch began, and the calibrated reading from the sensor was 1.25, the following text would
+/// be written:
+///
+/// ```text
+/// 1000000500,42,1.24
+///
+/// ```
+///
+/// If the entire process succeeds, this function will return `Ok(())`.
+///
+/// # Errors
+///
+/// This function will return an `Err` if writing to the log file fails.
+///
+/// # Panics
+///
+/// This function will panic if a time contained in the ADC readings was before the UNIX epoch.
+fn write_sensor_log<'a>(
+    log_file: &mut impl Write,
+    adc_readings: impl IntoIterator<Item = &'a (SystemTime, Option<u16>, f64)>,
+) -> std::io::Result<()> {
+    for (sys_time, reading, calib) in adc_readings {
+        let since_epoch_time = sys_time.duration_since(SystemTime::UNIX_EPOCH).unwrap();
+
+        write!(log_file, "{},", since_epoch_time.as_nanos())?;
+        if let Some(reading) = reading {
+            write!(log_file, "{reading}")?;
+        }
+        writeln!(log_file, ",{calib}")?;
+    }
+
+    log_file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, thread::scope};
+
+    use serde_json::Value;
+
+    use crate::{
+        config::SensorGroup,
+        hardware::{ListenerPin, ReturnsNumber},
+    };
+
+    use super::*;
+
+    #[test]
+    #[allow(clippy::too_many_lines)]
+    fn data_written() {
+        // create some dummy configuration for the sensor listener thread to read
+        let config = r##"{
+            "frequency_status": 10,
+            "log_buffer_size": 1,
+            "sensor_groups": [
+                {
+                    "label": "dummy",
+                    "frequency_standby": 10,
+                    "frequency_ignition": 10,
+                    "frequency_transmission": 10,
+                    "sensors": [
+                        {
+                            "label": "dummy_sensor0",
+                            "color": "#ef3b9e",
+                            "units": "mops",
+                            "calibration": {
+                                "type": "Linear",
+                                "slope": 0,
+                                "intercept": 0
+                            },
+                            "adc": 0,
+                            "channel": 0
+                        },
+                        {
+                            "label": "dummy_sensor1",
+                            "color": "#ef3b9e",
+                            "units": "mops",
+                            "calibration": {
+                                "type": "Linear",
+                                "slope": 0,
+                                "intercept": 0
+                            },
+                            "adc": 1,
+                            "channel": 0
+                        }
+                    ]
+                }
+            ],
+            "pre_ignite_time": 500,
+            "post_ignite_time": 5000,
+            "drivers": [],
+            "ignition_sequence": [],
+            "estop_sequence": [],
+            "spi_mosi": 11,
+            "spi_miso": 12,
+            "spi_clk": 13,
+            "spi_frequency_clk": 50000,
+            "adc_cs": [14, 15],
+            "pin_heartbeat": 0
+        }"##;
+        let adcs: Vec<Mutex<ReturnsNumber>> =
+            (0..2).map(|n| Mutex::new(ReturnsNumber(n))).collect();
+        let mut cfg_cursor = Cursor::new(config);
+        let config = Configuration::parse(&mut cfg_cursor).unwrap();
+        let readings = Readings::new(&config);
+        let state = Guard::new(State::Standby);
+        let mut logs = vec![Cursor::new(Vec::new()); 2];
+        // log file of outputs
+        let mut output_log = Vec::new();
+        // stream of outgoing messages
+        let mut output_stream_buf = Vec::new();
+        let output_stream = DashChannel::<&mut Vec<u8>, &mut Vec<u8>>::new(&mut output_log);
+        let client = output_stream.add_client(&mut output_stream_buf).unwrap();
+        output_stream.set_features(client, Vec::new()).unwrap();
+        let driver_lines = Mutex::new(Vec::<ListenerPin>::new());
+
+        // actual magic happens here
+        let user_log = UserLog::new(Vec::<u8>::new());
+        scope(|s| {
+            // spawn a sensor listener thread and let it do its thing
+            let handle = s.spawn(|| {
+                sensor_listen(
+                    s,
+                    0,
+                    &config,
+                    &driver_lines,
+                    &mut logs,
+                    &user_log,
+                    &adcs,
+                    &readings,
+                    &AbortMonitor::new(&config),
+                    &state,
+                    &output_stream,
+                )
+            });
+
+            // give the thread enough time to read exactly one value
+            sleep(Duration::from_millis(150));
+
+            // notify the thread to die
+            // hackery to make a valid state transition sequence
+            state.move_to(State::Quit).unwrap();
+            println!("joining...");
+
+            // collect the thread's return value
+            handle.join().unwrap().unwrap();
+        });
+
+        // the reading must have been shared with everyone else
+        assert_eq!(readings.get("dummy_sensor0").unwrap(), Some(0.0));
+
+        // write out the queued sensor reading, then validate it
+        output_stream.flush();
+
+        let json_val: Value = serde_json::from_slice(&output_stream_buf).unwrap();
+
+        let json_obj = json_val.as_object().unwrap();
+
+        assert_eq!(
+            json_obj.get("type").unwrap().as_str().unwrap(),
+            "SensorValue"
+        );
+
+        assert_eq!(json_obj.get("group_id").unwrap().as_u64().unwrap(), 0);
+
+        // extract group ids and reading values
+        let readings: Vec<(u64, u64)> = json_obj
+            .get("readings")
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|val| val.as_object().unwrap())
+            .map(|obj| {
+                (
+                    obj.get("sensor_id").unwrap().as_u64().unwrap(),
+                    obj.get("reading").unwrap().as_u64().unwrap(),
+                )
+            })
+            .collect();
+
+        assert_eq!(readings, [(0, 0), (1, 1)]);
+
+        // check that log information was generated correctly
+        let logged_strings: Vec<String> = logs
+            .into_iter()
+            .map(|cursor| String::from_utf8(cursor.into_inner()).unwrap())
+            .collect();
+
+        for (idx, logged_string) in logged_strings.iter().enumerate() {
+            let adc_readings = logged_string
+                .split('\n')
+                .filter_map(|l| Some(l.split(',').nth(1)?.parse::<usize>().unwrap()))
+                .collect::<Vec<usize>>();
+
+            for &adc_val in &adc_readings {
+                assert_eq!(adc_val, idx);
+            }
+
+            assert_eq!(adc_readings.len(), 2);
+        }
+    }
+
+    #[test]
+    #[allow(clippy::too_many_lines)]
+    /// Test that derived channels are computed, shared, logged and sent like sensors.
+    fn derived_channel() {
+        let config = r##"{
+            "frequency_status": 10,
+            "log_buffer_size": 1,
+            "sensor_groups": [
+                {
+                    "label": "dummy",
+                    "frequency_standby": 10,
+                    "frequency_ignition": 10,
+                    "frequency_transmission": 10,
+                    "sensors": [
+                        {
+                            "label": "PT_FEED",
+                            "color": "#ef3b9e",
+                            "units": "psi",
+                            "calibration": {
+                                "type": "Linear",
+                                "slope": 10,
+                                "intercept": 0
+                            },
+                            "adc": 0,
+                            "channel": 0
+                        },
+                        {
+                            "label": "PT_CHAMBER",
+                            "color": "#ef3b9e",
+                            "units": "psi",
+                            "calibration": {
+                                "type": "Linear",
+                                "slope": 10,
+                                "intercept": 0
+                            },
+                            "adc": 1,
+                            "channel": 0
+                        }
+                    ],
+                    "derived": [
+                        {
+                            "label": "DP_INJECTOR",
+                            "color": "#ef3b9e",
+                            "units": "psi",
+                            "expression": "PT_FEED - PT_CHAMBER",
+                            "warn_range": [0, 10]
+                        }
+                    ]
+                }
+            ],
+            "pre_ignite_time": 500,
+            "post_ignite_time": 5000,
+            "drivers": [],
+            "ignition_sequence": [],
+            "estop_sequence": [],
+            "spi_mosi": 11,
+            "spi_miso": 12,
+            "spi_clk": 13,
+            "spi_frequency_clk": 50000,
+            "adc_cs": [14, 15],
+            "pin_heartbeat": 0
+        }"##;
+        let adcs = [Mutex::new(ReturnsNumber(5)), Mutex::new(ReturnsNumber(3))];
+        let config = Configuration::parse(&mut Cursor::new(config)).unwrap();
+        let readings = Readings::new(&config);
+        let state = Guard::new(State::Standby);
+        let mut logs = vec![Cursor::new(Vec::new()); 3];
+        let mut output_stream_buf = Vec::new();
+        let output_stream = DashChannel::<&mut Vec<u8>, Vec<u8>>::new(Vec::new());
+        let client = output_stream.add_client(&mut output_stream_buf).unwrap();
+        output_stream.set_features(client, Vec::new()).unwrap();
+        let driver_lines = Mutex::new(Vec::<ListenerPin>::new());
+        let user_log = UserLog::new(Vec::<u8>::new());
+
+        scope(|s| {
+            let handle = s.spawn(|| {
+                sensor_listen(
+                    s,
+                    0,
+                    &config,
+                    &driver_lines,
+                    &mut logs,
+                    &user_log,
+                    &adcs,
+                    &readings,
+                    &AbortMonitor::new(&config),
+                    &state,
+                    &output_stream,
+                )
+            });
+
+            sleep(Duration::from_millis(150));
+            state.move_to(State::Quit).unwrap();
+            handle.join().unwrap().unwrap();
+        });
+
+        assert_eq!(readings.get("DP_INJECTOR").unwrap(), Some(20.0));
+        assert_eq!(
+            readings.alarm("DP_INJECTOR").unwrap(),
+            Some(AlarmLevel::Warning)
+        );
+
+        // the alarm is raised before the values are sent
+        output_stream.flush();
+        let messages: Vec<Value> = serde_json::Deserializer::from_slice(&output_stream_buf)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(messages[0]["type"], "Alarm");
+        assert_eq!(messages[0]["sensor_id"], 2);
+        assert_eq!(messages[1]["type"], "SensorValue");
+        let derived_reading = &messages[1]["readings"][2];
+        assert_eq!(derived_reading["sensor_id"], 2);
+        assert_eq!(derived_reading["value"], 20.0);
+        assert!(derived_reading.get("reading").is_none());
+
+        // a derived channel has no raw value to log
+        let log = String::from_utf8(logs[2].get_ref().clone()).unwrap();
+        let line = log.lines().next().unwrap();
+        assert!(line.ends_with(",,20"), "got {line}");
+    }
+
+    #[test]
+    #[allow(clippy::too_many_lines)]
+    /// Test that a derived channel counts as failing to be read when one of its inputs in another
+    /// group failed to be read.
+    fn derived_stale_input() {
+        let config = r##"{
+            "frequency_status": 10,
+            "log_buffer_size": 1,
+            "sensor_groups": [
+                {
+                    "label": "SLOW",
+                    "frequency_standby": 10,
+                    "frequency_ignition": 10,
+                    "frequency_transmission": 10,
+                    "sensors": [
+                        {
+                            "label": "PT_TANK",
+                            "color": "#ef3b9e",
+                            "units": "psi",
+                            "calibration": {
+                                "type": "Linear",
+                                "slope": 1,
+                                "intercept": 0
+                            },
+                            "adc": 0,
+                            "channel": 0
+                        }
+                    ]
+                },
+                {
+                    "label": "FAST",
+                    "frequency_standby": 10,
+                    "frequency_ignition": 10,
+                    "frequency_transmission": 10,
+                    "sensors": [
+                        {
+                            "label": "PT_FEED",
+                            "color": "#ef3b9e",
+                            "units": "psi",
+                            "calibration": {
+                                "type": "Linear",
+                                "slope": 1,
+                                "intercept": 0
+                            },
+                            "adc": 0,
+                            "channel": 1
+                        }
+                    ],
+                    "derived": [
+                        {
+                            "label": "DP_FEED",
+                            "color": "#ef3b9e",
+                            "units": "psi",
+                            "expression": "PT_TANK - PT_FEED"
+                        }
+                    ]
+                }
+            ],
+            "pre_ignite_time": 500,
+            "post_ignite_time": 5000,
+            "drivers": [],
+            "ignition_sequence": [],
+            "estop_sequence": [],
+            "spi_mosi": 11,
+            "spi_miso": 12,
+            "spi_clk": 13,
+            "spi_frequency_clk": 50000,
+            "adc_cs": [14],
+            "pin_heartbeat": 0
+        }"##;
+        let adcs = [Mutex::new(ReturnsNumber(5))];
+        let config = Configuration::parse(&mut Cursor::new(config)).unwrap();
+        let readings = Readings::new(&config);
+        let state = Guard::new(State::Standby);
+        let mut logs = vec![Cursor::new(Vec::new()); 2];
+        let output_stream = DashChannel::<Vec<u8>, Vec<u8>>::new(Vec::new());
+        let driver_lines = Mutex::new(Vec::<ListenerPin>::new());
+        let user_log = UserLog::new(Vec::<u8>::new());
+
+        // the tank transducer was read once, and has failed since
+        readings
+            .update(
+                0,
+                &[Some(Sample {
+                    value: 100.0,
+                    filtered: 100.0,
+                    rate: 0.0,
+                })],
+            )
+            .unwrap();
+        readings.update(0, &[None]).unwrap();
+
+        scope(|s| {
+            let handle = s.spawn(|| {
+                sensor_listen(
+                    s,
+                    1,
+                    &config,
+                    &driver_lines,
+                    &mut logs,
+                    &user_log,
+                    &adcs,
+                    &readings,
+                    &AbortMonitor::new(&config),
+                    &state,
+                    &output_stream,
+                )
+            });
+
+            sleep(Duration::from_millis(150));
+            state.move_to(State::Quit).unwrap();
+            handle.join().unwrap().unwrap();
+        });
+
+        assert_eq!(readings.get("PT_FEED").unwrap(), Some(5.0));
+        assert_eq!(readings.get("DP_FEED").unwrap(), None);
+        assert_eq!(readings.read_errors("DP_FEED", 1).unwrap(), Some(1));
+    }
+
+    #[test]
+    /// Test that an emergency stop is successfully called.
+    fn estop_called() {
+        // create some dummy configuration for the sensor listener thread to read
+        let config = r##"{
+            "frequency_status": 10,
+            "log_buffer_size": 1,
+            "sensor_groups": [
+                {
+                    "label": "dummy",
+                    "frequency_standby": 1,
+                    "frequency_ignition": 1,
+                    "frequency_transmission": 1,
+                    "sensors": [
+                        {
+                            "label": "dummy_sensor0",
+                            "color": "#ef3b9e",
+                            "units": "mops",
+                            "calibration": {
+                                "type": "Linear",
+                                "slope": 1,
+                                "intercept": 0
+                            },
+                            "adc": 0,
+                            "channel": 0,
+                            "filters": [
+                                {
+                                    "type": "MovingAverage",
+                                    "width": 2
+                                }
+                            ],
+                            "range": [-5, 5]
+                        }
+                    ]
+                }
+            ],
+            "pre_ignite_time": 0,
+            "post_ignite_time": 0,
+            "drivers": [],
+            "ignition_sequence": [],
+            "estop_sequence": [ {
+                "type": "Sleep",
+                "duration": {
+                    "secs": 1,
+                    "nanos": 0
+                }
+            }],
+            "spi_mosi": 11,
+            "spi_miso": 12,
+            "spi_clk": 13,
+            "spi_frequency_clk": 50000,
+            "adc_cs": [14],
+            "pin_heartbeat": 0
+        }"##;
+
+        let adc = Mutex::new(ReturnsNumber(100));
+
+        let mut cfg_cursor = Cursor::new(config);
+        let config = Configuration::parse(&mut cfg_cursor).unwrap();
+        let readings = Readings::new(&config);
+
+        let state = Guard::new(State::Standby);
+        let mut logs = vec![Cursor::new(Vec::new()); 2];
+        let output_stream = DashChannel::<Vec<u8>, Vec<u8>>::new(Vec::new());
+        let driver_lines = Mutex::new(Vec::<ListenerPin>::new());
+
+        // actual magic happens here
+        let user_log = UserLog::new(Vec::<u8>::new());
+        scope(|s| {
+            // spawn a sensor listener thread and let it do its thing
+            s.spawn(|| {
+                sensor_listen(
+                    s,
+                    0,
+                    &config,
+                    &driver_lines,
+                    &mut logs,
+                    &user_log,
+                    &[adc],
+                    &readings,
+                    &AbortMonitor::new(&config),
+                    &state,
+                    &output_stream,
+                )
+            });
+
+            // give the thread enough time to read values
+            sleep(Duration::from_millis(200));
+
+            // check that we are currently e-stopping
+            assert_eq!(state.status().unwrap(), State::EStopping);
+
+            // continually attempt to kill the thread
+            while state.move_to(State::Quit).is_err() {
+                println!("failed: {:?}", state.status().unwrap());
+            }
+        });
+    }
+
+    #[test]
+    /// Test that state transitions are logged and sent to the dashboard, and that re-entering the
+    /// same state is not.
+    fn states_logged() {
+        let state = Guard::new(State::Standby);
+        let transitions = state.subscribe().unwrap();
+        let mut log = Vec::new();
+        let output_stream = DashChannel::<Vec<u8>, Vec<u8>>::new(Vec::new());
+        let client = output_stream.add_client(Vec::new()).unwrap();
+        output_stream.set_features(client, Vec::new()).unwrap();
+
+        state.move_to(State::EStopping).unwrap();
+        // stopping again while already stopping is not a transition
+        state.move_to(State::EStopping).unwrap();
+        state.move_to(State::Standby).unwrap();
+        state.move_to(State::Quit).unwrap();
+
+        let user_log = UserLog::new(Vec::new());
+        state_listen(transitions, &mut log, &user_log, &output_stream).unwrap();
+
+        let log = String::from_utf8(log).unwrap();
+        let rows: Vec<Vec<&str>> = log
+            .lines()
+            .map(|line| line.split(',').skip(1).collect())
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ["Standby", "EStopping"],
+                ["EStopping", "Standby"],
+                ["Standby", "Quit"]
+            ]
+        );
+
+        let messages = output_stream.sent(client);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0]["type"], "State");
+        assert_eq!(messages[0]["from"], "Standby");
+        assert_eq!(messages[2]["to"], "Quit");
+    }
+
+    #[test]
+    /// Test that alarms are raised at the limits of the current state and only lowered once the
+    /// value comes back inside them by the hysteresis.
+    fn alarm_hysteresis() {
+        let group: SensorGroup = serde_json::from_str(
+            r##"{
+                "label": "TANK",
+                "frequency_standby": 10,
+                "frequency_ignition": 100,
+                "frequency_transmission": 10,
+                "sensors": [
+                    {
+                        "label": "PT_TANK",
+                        "color": "#000000",
+                        "units": "psi",
+                        "calibration": { "type": "Linear", "slope": 1, "intercept": 0 },
+                        "adc": 0,
+                        "channel": 0,
+                        "warn_range": { "Standby": [0, 300], "Ignite": [0, 600] },
+                        "abort_range": [0, 800],
+                        "hysteresis": 20
+                    }
+                ]
+            }"##,
+        )
+        .unwrap();
+        let channel = group.channels().next().unwrap();
+        let level = |state, value, previous| alarm_level(&channel, state, value, previous);
+
+        assert_eq!(
+            level(State::Standby, 250.0, AlarmLevel::Nominal),
+            AlarmLevel::Nominal
+        );
+        assert_eq!(
+            level(State::Standby, 310.0, AlarmLevel::Nominal),
+            AlarmLevel::Warning
+        );
+        assert_eq!(
+            level(State::Ignite, 310.0, AlarmLevel::Nominal),
+            AlarmLevel::Nominal
+        );
+        // states without warning limits only have the abort limits
+        assert_eq!(
+            level(State::Armed, 700.0, AlarmLevel::Nominal),
+            AlarmLevel::Nominal
+        );
+        assert_eq!(
+            level(State::Armed, 810.0, AlarmLevel::Nominal),
+            AlarmLevel::Abort
+        );
+
+        // a warning holds until the value is 20 psi inside the limit
+        assert_eq!(
+            level(State::Standby, 290.0, AlarmLevel::Warning),
+            AlarmLevel::Warning
+        );
+        assert_eq!(
+            level(State::Standby, 279.0, AlarmLevel::Warning),
+            AlarmLevel::Nominal
+        );
+        // coming down from an abort passes through a warning, which is also subject to hysteresis
+        assert_eq!(
+            level(State::Ignite, 790.0, AlarmLevel::Abort),
+            AlarmLevel::Abort
+        );
+        assert_eq!(
+            level(State::Ignite, 700.0, AlarmLevel::Abort),
+            AlarmLevel::Warning
+        );
+        assert_eq!(
+            level(State::Ignite, 590.0, AlarmLevel::Abort),
+            AlarmLevel::Warning
+        );
+        assert_eq!(
+            level(State::Ignite, 570.0, AlarmLevel::Abort),
+            AlarmLevel::Nominal
+        );
+    }
+
+    #[test]
+    /// Test that failed reads are remembered, and that a failed read does not overwrite the most
+    /// recent value.
+    fn read_errors() {
+        let config = Configuration::parse(&mut Cursor::new(
+            r##"{
+                "frequency_status": 10,
+                "log_buffer_size": 1,
+                "sensor_groups": [
+                    {
+                        "label": "FAST",
+                        "frequency_standby": 10,
+                        "frequency_ignition": 1000,
+                        "frequency_transmission": 10,
+                        "sensors": [
+                            {
+                                "label": "PT_FEED",
+                                "color": "#ef3b9e",
+                                "units": "psi",
+                                "calibration": {
+                                    "type": "Linear",
+                                    "slope": 1,
+                                    "intercept": 0
+                                },
+                                "adc": 0,
+                                "channel": 0
+                            }
+                        ]
+                    }
+                ],
+                "pre_ignite_time": 500,
+                "post_ignite_time": 5000,
+                "drivers": [],
+                "ignition_sequence": [],
+                "estop_sequence": [],
+                "spi_mosi": 11,
+                "spi_miso": 12,
+                "spi_clk": 13,
+                "spi_frequency_clk": 50000,
+                "adc_cs": [14],
+                "pin_heartbeat": 0
+            }"##,
+        ))
+        .unwrap();
+        let readings = Readings::new(&config);
+
+        readings
+            .update(
+                0,
+                &[Some(Sample {
+                    value: 1.0,
+                    filtered: 1.0,
+                    rate: 0.0,
+                })],
+            )
+            .unwrap();
+        readings.update(0, &[None]).unwrap();
+        readings
+            .update(
+                0,
+                &[Some(Sample {
+                    value: 2.0,
+                    filtered: 2.0,
+                    rate: 0.0,
+                })],
+            )
+            .unwrap();
+        readings.update(0, &[None]).unwrap();
+
+        assert_eq!(readings.get("PT_FEED").unwrap(), Some(2.0));
+        assert_eq!(readings.read_errors("PT_FEED", 1).unwrap(), Some(1));
+        assert_eq!(readings.read_errors("PT_FEED", 3).unwrap(), Some(2));
+        assert_eq!(readings.read_errors("PT_FEED", 64).unwrap(), Some(2));
+        assert_eq!(readings.read_errors("PT_FEED", 0).unwrap(), Some(0));
+        assert_eq!(readings.read_errors("PT_CHAMBER", 3).unwrap(), None);
+    }
+
+    #[test]
+    /// Test that a channel whose reads are failing still has its alarm level updated to the limits
+    /// of a new state.
+    fn alarm_after_state_change() {
+        let config = Configuration::parse(&mut Cursor::new(
+            r##"{
+                "frequency_status": 10,
+                "log_buffer_size": 1,
+                "sensor_groups": [
+                    {
+                        "label": "FAST",
+                        "frequency_standby": 10,
+                        "frequency_ignition": 1000,
+                        "frequency_transmission": 10,
+                        "sensors": [
+                            {
+                                "label": "PT_FEED",
+                                "color": "#ef3b9e",
+                                "units": "psi",
+                                "calibration": {
+                                    "type": "Linear",
+                                    "slope": 1,
+                                    "intercept": 0
+                                },
+                                "adc": 0,
+                                "channel": 0,
+                                "warn_range": { "Standby": [0, 300], "Ignite": [0, 600] }
+                            }
+                        ]
+                    }
+                ],
+                "pre_ignite_time": 500,
+                "post_ignite_time": 5000,
+                "drivers": [],
+                "ignition_sequence": [],
+                "estop_sequence": [],
+                "spi_mosi": 11,
+                "spi_miso": 12,
+                "spi_clk": 13,
+                "spi_frequency_clk": 50000,
+                "adc_cs": [14],
+                "pin_heartbeat": 0
+            }"##,
+        ))
+        .unwrap();
+        let readings = Readings::new(&config);
+        let user_log = UserLog::new(Vec::new());
+        let dashboard_stream = DashChannel::new(Vec::new());
+        let client = dashboard_stream.add_client(Vec::new()).unwrap();
+        dashboard_stream.set_features(client, Vec::new()).unwrap();
+        let mut alarms = [AlarmLevel::Nominal];
+
+        let samples = [Some(Sample {
+            value: 400.0,
+            filtered: 400.0,
+            rate: 0.0,
+        })];
+        readings.update(0, &samples).unwrap();
+        update_alarms(
+            0,
+            &config,
+            &samples,
+            &mut alarms,
+            &readings,
+            State::Ignite,
+            &user_log,
+            &dashboard_stream,
+        )
+        .unwrap();
+        assert_eq!(alarms, [AlarmLevel::Nominal]);
+
+        // the sensor stops responding, and then the controller goes back to standby
+        readings.update(0, &[None]).unwrap();
+        update_alarms(
+            0,
+            &config,
+            &[None],
+            &mut alarms,
+            &readings,
+            State::Standby,
+            &user_log,
+            &dashboard_stream,
+        )
+        .unwrap();
+        assert_eq!(alarms, [AlarmLevel::Warning]);
+        assert_eq!(
+            readings.alarm("PT_FEED").unwrap(),
+            Some(AlarmLevel::Warning)
+        );
+
+        let messages = dashboard_stream.sent(client);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["type"], "Alarm");
+        assert_eq!(messages[0]["level"], "Warning");
+    }
+}
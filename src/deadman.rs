@@ -0,0 +1,453 @@
+/*
+  slonk, a rocket engine controller.
+  Copyright (C) 2022 Rice Eclipse.
+
+  slonk is free software: you can redistribute it and/or modify
+  it under the terms of the GNU General Public License as published by
+  the Free Software Foundation, either version 3 of the License, or
+  (at your option) any later version.
+
+  slonk is distributed in the hope that it will be useful,
+  but WITHOUT ANY WARRANTY; without even the implied warranty of
+  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+  GNU General Public License for more details.
+
+  You should have received a copy of the GNU General Public License
+  along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! A deadman switch which makes the engine safe if contact with the commanding dashboard is lost.
+
+use std::{
+    io::Write,
+    sync::Mutex,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::{Configuration, DisconnectAction},
+    console::UserLog,
+    data::Readings,
+    execution::{self, emergency_stop, safe},
+    hardware::GpioPin,
+    state::{Guard, State},
+    ControllerError,
+};
+
+/// The period at which the deadman thread checks in on the dashboard connection.
+const POLL_PERIOD: Duration = Duration::from_millis(10);
+
+/// A record of contact with the commanding dashboard.
+/// The server updates this record as a dashboard takes control, sends messages, and disconnects,
+/// and the deadman thread uses it to decide whether contact has been lost.
+/// Dashboards which are not the commander are not watched over.
+pub struct Deadman {
+    /// The current status of contact with the dashboard.
+    contact: Mutex<Contact>,
+}
+
+/// The status of contact with the dashboard.
+struct Contact {
+    /// Whether a dashboard is currently connected.
+    connected: bool,
+    /// The last time we heard from the dashboard.
+    /// If the dashboard is disconnected, this is the time at which it disconnected.
+    last_heard: Instant,
+    /// Whether the disconnect policy has already been triggered since we last heard from the
+    /// dashboard.
+    /// This is also `true` if no dashboard has ever connected, since there is nothing to lose
+    /// contact with.
+    triggered: bool,
+}
+
+impl Deadman {
+    #[must_use]
+    /// Construct a new `Deadman` for a controller to which no dashboard has connected yet.
+    pub fn new() -> Deadman {
+        Deadman {
+            contact: Mutex::new(Contact {
+                connected: false,
+                last_heard: Instant::now(),
+                triggered: true,
+            }),
+        }
+    }
+
+    /// Record that a dashboard has just connected and become the commander.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the internal lock is poisoned.
+    pub fn connect(&self) -> Result<(), ControllerError> {
+        *self.contact.lock()? = Contact {
+            connected: true,
+            last_heard: Instant::now(),
+            triggered: false,
+        };
+        Ok(())
+    }
+
+    /// Record that we have just received a message from the dashboard.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the internal lock is poisoned.
+    pub fn touch(&self) -> Result<(), ControllerError> {
+        let mut contact = self.contact.lock()?;
+        contact.last_heard = Instant::now();
+        contact.triggered = false;
+        Ok(())
+    }
+
+    /// Record that the dashboard has deliberately stopped commanding the controller.
+    /// Since contact was given up on purpose, it cannot be lost, so the disconnect policy will not
+    /// be triggered until the next call to `connect`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the internal lock is poisoned.
+    pub fn release(&self) -> Result<(), ControllerError> {
+        let mut contact = self.contact.lock()?;
+        contact.connected = false;
+        contact.triggered = true;
+        Ok(())
+    }
+
+    /// Record that the dashboard has just disconnected.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the internal lock is poisoned.
+    pub fn disconnect(&self) -> Result<(), ControllerError> {
+        let mut contact = self.contact.lock()?;
+        contact.connected = false;
+        contact.last_heard = Instant::now();
+        Ok(())
+    }
+}
+
+impl Default for Deadman {
+    fn default() -> Self {
+        Deadman::new()
+    }
+}
+
+/// Watch over the connection to the dashboard, and execute the configured disconnect policy if
+/// contact with the dashboard is lost.
+///
+/// Contact is lost in either of the following cases:
+///
+/// * The dashboard disconnected, and did not reconnect within the policy's grace period.
+/// * The controller is in `PreIgnite` or `Ignite`, and the dashboard has not sent any message
+///   within the policy's timeout.
+///
+/// The policy is executed at most once each time contact is lost.
+/// This function will only return once the controller moves into the `Quit` state.
+///
+/// # Errors
+///
+/// This function will return an error if a lock is poisoned or if we are unable to write to the
+/// user log.
+pub fn deadman_listen(
+    configuration: &Configuration,
+    deadman: &Deadman,
+    driver_lines: &Mutex<Vec<impl GpioPin>>,
+    readings: &Readings,
+    user_log: &UserLog<impl Write>,
+    state: &Guard,
+) -> Result<(), ControllerError> {
+    let policy = &configuration.on_disconnect;
+    let grace_period = Duration::from_millis(u64::from(policy.grace_period));
+    let timeout = policy.timeout.map(|t| Duration::from_millis(u64::from(t)));
+
+    loop {
+        let status = state.status()?;
+        if status == State::Quit {
+            break;
+        }
+
+        let reason = {
+            let mut contact = deadman.contact.lock()?;
+            let since_contact = contact.last_heard.elapsed();
+            let reason = if contact.triggered {
+                None
+            } else if !contact.connected && since_contact >= grace_period {
+                Some("Dashboard disconnected")
+            } else if contact.connected
+                && matches!(
+                    status,
+                    State::Countdown | State::Hold | State::PreIgnite | State::Ignite
+                )
+                && matches!(timeout, Some(t) if since_contact >= t)
+            {
+                Some("Dashboard stopped responding")
+            } else {
+                None
+            };
+
+            if reason.is_some() {
+                contact.triggered = true;
+            }
+            reason
+        };
+
+        if let Some(reason) = reason {
+            let result = match &policy.action {
+                DisconnectAction::Nothing => {
+                    user_log.warn(&format!("{reason}. Taking no action."))?;
+                    Ok(())
+                }
+                DisconnectAction::EmergencyStop => {
+                    user_log.critical(&format!("{reason}. Attempting emergency stop."))?;
+                    emergency_stop(configuration, driver_lines, readings, state, user_log)
+                }
+                DisconnectAction::Sequence { name } => {
+                    user_log.critical(&format!("{reason}. Running safing sequence {name:?}."))?;
+                    // the configuration was validated, so the sequence must exist
+                    safe(
+                        configuration,
+                        driver_lines,
+                        readings,
+                        &configuration.sequences[name],
+                        state,
+                        user_log,
+                    )
+                }
+            };
+
+            match result {
+                Ok(()) | Err(execution::Error::Preempted) => (),
+                Err(e) => {
+                    user_log.critical(&format!("Failed to execute disconnect policy: {e}"))?;
+                }
+            }
+        }
+
+        sleep(POLL_PERIOD);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, thread::scope};
+
+    use crate::hardware::ListenerPin;
+
+    use super::*;
+
+    /// Construct a configuration with two drivers: a fill valve, which is closed by the estop
+    /// sequence, and a vent valve, which is opened by the sequence named `vent`.
+    /// `policy` is the JSON for the disconnect policy.
+    fn config_with_policy(policy: &str) -> Configuration {
+        let config = format!(
+            r#"{{
+                "frequency_status": 1,
+                "log_buffer_size": 1,
+                "sensor_groups": [],
+                "pre_ignite_time": 0,
+                "post_ignite_time": 0,
+                "drivers": [
+                    {{
+                        "label": "OXI_FILL",
+                        "label_actuate": "Open",
+                        "label_deactuate": "Close",
+                        "pin": 21,
+                        "protected": false
+                    }},
+                    {{
+                        "label": "VENT",
+                        "label_actuate": "Open",
+                        "label_deactuate": "Close",
+                        "pin": 22,
+                        "protected": false
+                    }}
+                ],
+                "ignition_sequence": [],
+                "estop_sequence": [
+                    {{
+                        "type": "Actuate",
+                        "driver_id": 0,
+                        "value": false
+                    }}
+                ],
+                "sequences": {{
+                    "vent": [
+                        {{
+                            "type": "Actuate",
+                            "driver_id": 1,
+                            "value": true
+                        }}
+                    ]
+                }},
+                "on_disconnect": {policy},
+                "spi_mosi": 11,
+                "spi_miso": 12,
+                "spi_clk": 13,
+                "spi_frequency_clk": 50000,
+                "adc_cs": [],
+                "pin_heartbeat": 0
+            }}"#
+        );
+
+        Configuration::parse(&mut Cursor::new(config)).unwrap()
+    }
+
+    /// Run a deadman thread while `f` is executed, and then shut it down.
+    /// Returns the history of each driver.
+    fn run_deadman(
+        config: &Configuration,
+        state: &Guard,
+        f: impl FnOnce(&Deadman),
+    ) -> Vec<Vec<bool>> {
+        let deadman = Deadman::new();
+        let driver_lines = Mutex::new(vec![ListenerPin::new(true), ListenerPin::new(false)]);
+
+        scope(|s| {
+            s.spawn(|| {
+                deadman_listen(
+                    config,
+                    &deadman,
+                    &driver_lines,
+                    &Readings::new(config),
+                    &UserLog::new(Vec::new()),
+                    state,
+                )
+            });
+
+            f(&deadman);
+
+            // continually attempt to kill the deadman thread
+            while state.move_to(State::Quit).is_err() {
+                sleep(Duration::from_millis(10));
+            }
+        });
+
+        driver_lines
+            .into_inner()
+            .unwrap()
+            .iter()
+            .map(|pin| pin.history().clone())
+            .collect()
+    }
+
+    #[test]
+    /// Test that the controller emergency stops once the grace period after a disconnect is over.
+    fn disconnect_estop() {
+        let config = config_with_policy(
+            r#"{
+                "action": { "type": "EmergencyStop" },
+                "grace_period": 200
+            }"#,
+        );
+        let state = Guard::new(State::Standby);
+
+        let histories = run_deadman(&config, &state, |deadman| {
+            deadman.connect().unwrap();
+            deadman.disconnect().unwrap();
+
+            sleep(Duration::from_millis(100));
+            // still within the grace period
+            assert_eq!(state.status().unwrap(), State::Standby);
+
+            sleep(Duration::from_millis(200));
+        });
+
+        assert_eq!(histories[0], [true, false]);
+        assert_eq!(histories[1], [false]);
+    }
+
+    #[test]
+    /// Test that no action is taken if the dashboard reconnects within the grace period.
+    fn reconnect_within_grace_period() {
+        let config = config_with_policy(
+            r#"{
+                "action": { "type": "EmergencyStop" },
+                "grace_period": 200
+            }"#,
+        );
+        let state = Guard::new(State::Standby);
+
+        let histories = run_deadman(&config, &state, |deadman| {
+            deadman.connect().unwrap();
+            deadman.disconnect().unwrap();
+            sleep(Duration::from_millis(100));
+            deadman.connect().unwrap();
+            sleep(Duration::from_millis(200));
+        });
+
+        assert_eq!(histories[0], [true]);
+        assert_eq!(histories[1], [false]);
+    }
+
+    #[test]
+    /// Test that a named safing sequence is run instead of the estop sequence when configured.
+    fn disconnect_sequence() {
+        let config = config_with_policy(
+            r#"{
+                "action": {
+                    "type": "Sequence",
+                    "name": "vent"
+                }
+            }"#,
+        );
+        let state = Guard::new(State::Standby);
+
+        let histories = run_deadman(&config, &state, |deadman| {
+            deadman.connect().unwrap();
+            deadman.disconnect().unwrap();
+            sleep(Duration::from_millis(100));
+        });
+
+        assert_eq!(histories[0], [true]);
+        assert_eq!(histories[1], [false, true]);
+    }
+
+    #[test]
+    /// Test that the policy is executed if a connected dashboard goes silent during ignition, but
+    /// only once until the dashboard is heard from again.
+    fn silent_during_ignition() {
+        let config = config_with_policy(
+            r#"{
+                "action": { "type": "EmergencyStop" },
+                "timeout": 100
+            }"#,
+        );
+        let state = Guard::new(State::Ignite);
+
+        let histories = run_deadman(&config, &state, |deadman| {
+            deadman.connect().unwrap();
+            sleep(Duration::from_millis(50));
+            deadman.touch().unwrap();
+            sleep(Duration::from_millis(50));
+            // we heard from the dashboard recently, so nothing should have happened
+            assert_eq!(state.status().unwrap(), State::Ignite);
+
+            sleep(Duration::from_millis(200));
+            assert_eq!(state.status().unwrap(), State::Standby);
+        });
+
+        assert_eq!(histories[0], [true, false]);
+    }
+
+    #[test]
+    /// Test that the silence timeout does not apply in standby.
+    fn silent_during_standby() {
+        let config = config_with_policy(
+            r#"{
+                "action": { "type": "EmergencyStop" },
+                "timeout": 50
+            }"#,
+        );
+        let state = Guard::new(State::Standby);
+
+        let histories = run_deadman(&config, &state, |deadman| {
+            deadman.connect().unwrap();
+            sleep(Duration::from_millis(200));
+        });
+
+        assert_eq!(histories[0], [true]);
+    }
+}
@@ -19,28 +19,70 @@
 //! Functions for command execution.
 
 use crate::{
-    config::{Action, Configuration},
+    config::{
+        Action, Check, Condition, Configuration, Countdown, DriverRef, Interlock, SensorGroup,
+        TimeoutAction,
+    },
     console::UserLog,
+    data::Readings,
     hardware::GpioPin,
-    incoming::Command,
+    incoming::{Command, Request},
+    outgoing::{ClientId, DashChannel, Message},
+    preview,
     state::{self, Guard, State},
 };
 use std::{
+    cmp::Reverse,
+    fmt::Display,
     io::Write,
-    sync::{Mutex, PoisonError},
-    thread::sleep,
-    time::{Duration, SystemTime},
+    sync::{mpsc::RecvTimeoutError, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant, SystemTime},
 };
 
-#[derive(Debug)]
+use serde::Serialize;
+
+/// The period at which a `WaitUntil` action checks whether its condition holds.
+const WAIT_POLL_PERIOD: Duration = Duration::from_millis(5);
+
+#[derive(Debug, Serialize)]
 /// The set of possible errors that could be encountered while executing a command.
 pub enum Error {
     /// A lock was poisoned.
     Poison,
     /// The command tried to actuate a driver that doesn't exist.
     DriverOutOfBounds,
+    /// The command tried to manually actuate a protected driver.
+    /// Protected drivers can only be actuated by the ignition and emergency stop sequences.
+    /// The value inside this variant is the ID of the driver.
+    ProtectedDriver(u8),
+    /// The command is not permitted while the controller is in its current state.
+    /// The value inside this variant is the state that the controller was in.
+    Forbidden(State),
+    /// The command may only be issued by the commander, and the dashboard which issued it is not
+    /// the commander.
+    NotCommander,
+    /// The dashboard tried to become the commander, but another dashboard already is.
+    ControlHeld,
+    /// The command tried to run a named sequence which does not exist.
+    /// The value inside this variant is the name of the sequence.
+    NoSuchSequence(String),
+    /// The controller could not be armed because the arm key was not turned.
+    ArmKeyOff,
+    /// The controller was armed, but no ignition was started before the arming timed out.
+    ArmingExpired,
+    /// At least one go/no-go check failed, so the ignition was called off.
+    NoGo,
+    /// The command or procedure tried to actuate a driver in a way which would break an interlock.
+    /// The value inside this variant is the index of the interlock in the configuration.
+    Interlocked(usize),
     /// While executing a procedure, an illegal transition was attempted.
     State(state::Error),
+    /// A `WaitUntil` action in the procedure timed out, and so the procedure was aborted.
+    /// The value inside this variant is the label of the sensor which was being waited on.
+    WaitTimeout(String),
+    /// The procedure was preempted by another thread changing the controller's state (such as by
+    /// an emergency stop) before the procedure could finish.
+    Preempted,
 }
 
 impl<T> From<PoisonError<T>> for Error {
@@ -55,33 +97,81 @@ impl From<state::Error> for Error {
     }
 }
 
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Poison => write!(f, "a lock was poisoned"),
+            Error::DriverOutOfBounds => write!(f, "no such driver exists"),
+            Error::ProtectedDriver(id) => write!(f, "driver {id} is protected"),
+            Error::Forbidden(state) => write!(f, "command is not permitted in state {state:?}"),
+            Error::NotCommander => write!(f, "command may only be issued by the commander"),
+            Error::ControlHeld => write!(f, "another dashboard is already the commander"),
+            Error::NoSuchSequence(name) => write!(f, "no sequence named {name:?} exists"),
+            Error::ArmKeyOff => write!(f, "the arm key is not turned"),
+            Error::ArmingExpired => write!(f, "arming timed out before an ignition was started"),
+            Error::NoGo => write!(f, "a go/no-go check failed"),
+            Error::Interlocked(i) => write!(f, "actuation would break interlock {i}"),
+            Error::State(e) => write!(f, "state error: {e:?}"),
+            Error::WaitTimeout(label) => write!(f, "timed out waiting on sensor {label}"),
+            Error::Preempted => write!(f, "preempted by a change in controller state"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+/// The outcome of a single go/no-go check.
+pub struct CheckResult<'a> {
+    /// The check which was evaluated.
+    pub check: &'a Check,
+    /// Whether the check passed.
+    pub go: bool,
+    /// A human-readable description of the outcome.
+    pub detail: String,
+}
+
+#[allow(clippy::too_many_arguments)]
 /// Execute a command and log the process of execution.
 ///
+/// The dashboard will be told about the outcome of the command: an `Ack` message is sent as soon
+/// as the command is authorized, followed by a `Done` message once it is finished executing.
+/// If the command is not authorized, a `Nack` message is sent instead.
+///
 /// # Inputs
 ///
-/// * `cmd`: The command to be executed.
+/// * `request`: The request containing the command to be executed.
+/// * `client`: The dashboard which sent the request.
 /// * `log_file`: Location where log information will be written.
 /// * `configuration`: Configuration object for program execution.
 /// * `driver_lines`: Output lines for the drivers.
-///     Each index in `driver_lines` corresponds one-to-one with the drivers in `configuration`.  
+///   Each index in `driver_lines` corresponds one-to-one with the drivers in `configuration`.  
+/// * `arm_key`: The arm-key input, if one is configured.
+/// * `readings`: The most recent values of every sensor, for use by procedures.
 /// * `state`: The controller for the current system state.
 /// * `dashboard_stream`: The stream to use for writing messages to the dashboard.
 ///
 /// # Errors
 ///
 /// This function will return an error if a lock is poisoned or if we are unable to actuate GPIO.
+/// If the command is not authorized (as determined by `authorize`), the rejection will be logged
+/// and the reason for the rejection will be returned as an error.
 ///
 /// # Panics
 ///
 /// This function will panic if the current system time is before the UNIX epoch.
 pub fn handle_command(
-    cmd: &Command,
+    request: &Request,
+    client: ClientId,
     log_file: &Mutex<impl Write>,
     user_log: &UserLog<impl Write>,
     configuration: &Configuration,
     driver_lines: &Mutex<Vec<impl GpioPin>>,
+    arm_key: &Mutex<Option<impl GpioPin>>,
+    readings: &Readings,
     state: &Guard,
+    dashboard_stream: &DashChannel<impl Write, impl Write>,
 ) -> Result<(), Error> {
+    let Request { id, command: cmd } = request;
+    let id = *id;
     let time = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap();
@@ -100,24 +190,81 @@ pub fn handle_command(
         user_log.warn(&format!("Unable to log command {cmd} to log file: {e:?}"));
     }
 
-    match cmd {
-        Command::Actuate { driver_id, value } => {
-            if usize::from(*driver_id) > configuration.drivers.len() {
-                // we were asked to actuate a non-existent driver
-                return Err(Error::DriverOutOfBounds);
-            }
+    // hold onto the drivers while authorizing the command, so that the state cannot change in
+    // between authorizing a manual actuation and performing it
+    let mut driver_guard = driver_lines.lock().map_err(|_| Error::Poison)?;
+    let commander = dashboard_stream.commander().map_err(|_| Error::Poison)?;
+    let status = state.status()?;
+    let verdict = authorize(cmd, configuration, status, client, commander).and_then(|()| {
+        let (driver_id, value) = match cmd {
+            Command::Actuate { driver_id, value } => (driver_id, *value),
+            Command::Pulse { driver_id, .. } => (driver_id, true),
+            _ => return Ok(()),
+        };
+        let id = resolve_driver(configuration, driver_id)?;
+        check_interlocks(configuration, &mut driver_guard, status, id, value)
+    });
+    if let Err(e) = verdict {
+        drop(driver_guard);
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
 
-            actuate_driver(
-                driver_lines.lock().map_err(|_| Error::Poison)?.as_mut(),
-                *driver_id,
-                *value,
-            )?;
-        }
-        Command::Ignition => ignition(configuration, driver_lines, state)?,
-        Command::EmergencyStop => {
-            emergency_stop(configuration, driver_lines, state)?;
+        #[allow(unused_must_use)]
+        if let Err(log_err) = writeln!(
+            log_file.lock().map_err(|_| Error::Poison)?,
+            "{},reject,{cmd},{e}",
+            time.as_nanos()
+        ) {
+            user_log.warn(&format!(
+                "Unable to log rejection of command {cmd} to log file: {log_err:?}"
+            ));
         }
-    };
+
+        report(
+            dashboard_stream,
+            client,
+            user_log,
+            &Message::Nack {
+                id,
+                command: cmd,
+                reason: &e,
+            },
+        );
+        return Err(e);
+    }
+
+    report(
+        dashboard_stream,
+        client,
+        user_log,
+        &Message::Ack { id, command: cmd },
+    );
+
+    let result = execute(
+        cmd,
+        client,
+        driver_guard,
+        configuration,
+        driver_lines,
+        arm_key,
+        readings,
+        state,
+        dashboard_stream,
+        user_log,
+    );
+
+    report(
+        dashboard_stream,
+        client,
+        user_log,
+        &Message::Done {
+            id,
+            command: cmd,
+            error: result.as_ref().err(),
+        },
+    );
+    result?;
 
     let time = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
@@ -136,6 +283,252 @@ pub fn handle_command(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
+/// Carry out an authorized command on behalf of `client`.
+///
+/// `driver_guard` is the lock on `driver_lines` which was held while authorizing the command.
+/// It is released before running any procedure which needs to take the lock itself.
+///
+/// # Errors
+///
+/// This function will return an error if the command fails to complete.
+fn execute<P: GpioPin>(
+    cmd: &Command,
+    client: ClientId,
+    mut driver_guard: MutexGuard<Vec<P>>,
+    configuration: &Configuration,
+    driver_lines: &Mutex<Vec<P>>,
+    arm_key: &Mutex<Option<impl GpioPin>>,
+    readings: &Readings,
+    state: &Guard,
+    dashboard_stream: &DashChannel<impl Write, impl Write>,
+    user_log: &UserLog<impl Write>,
+) -> Result<(), Error> {
+    match cmd {
+        Command::Actuate { driver_id, value } => actuate_driver(
+            driver_guard.as_mut(),
+            resolve_driver(configuration, driver_id)?,
+            *value,
+        ),
+        Command::Pulse {
+            driver_id,
+            duration,
+        } => {
+            let pulse = Pulse::start(
+                configuration,
+                driver_lines,
+                &mut driver_guard[..],
+                state,
+                State::Standby,
+                resolve_driver(configuration, driver_id)?,
+            )?;
+            drop(driver_guard);
+            let finished = state.sleep_in(State::Standby, *duration);
+            // return the driver before reporting that the pulse is over
+            drop(pulse);
+            if finished? {
+                Ok(())
+            } else {
+                Err(Error::Preempted)
+            }
+        }
+        Command::Arm => {
+            drop(driver_guard);
+            arm(configuration, arm_key, state)
+        }
+        Command::Disarm => advance(state, State::Armed, State::Standby),
+        Command::Ignition => {
+            drop(driver_guard);
+            ignition(
+                configuration,
+                driver_lines,
+                readings,
+                state,
+                dashboard_stream,
+                user_log,
+            )
+        }
+        Command::Hold => advance(state, State::Countdown, State::Hold),
+        Command::Resume => advance(state, State::Hold, State::Countdown),
+        Command::Recycle => {
+            if state.advance(State::Countdown, State::Standby)?
+                || state.advance(State::Hold, State::Standby)?
+            {
+                Ok(())
+            } else {
+                Err(Error::Preempted)
+            }
+        }
+        Command::EmergencyStop => {
+            drop(driver_guard);
+            emergency_stop(configuration, driver_lines, readings, state, user_log)
+        }
+        Command::RunSequence { name } => {
+            drop(driver_guard);
+            run_sequence(configuration, driver_lines, readings, name, state, user_log)
+        }
+        Command::Preview { sequence } => {
+            // the configuration was checked during authorization, so the sequence must exist
+            let actions = preview::find_sequence(configuration, sequence).unwrap();
+            let levels = driver_guard
+                .iter_mut()
+                .map(GpioPin::read)
+                .collect::<Result<Vec<bool>, _>>()
+                .map_err(|_| Error::Poison)?;
+            drop(driver_guard);
+            let timeline = preview::dry_run(configuration, actions, &levels);
+            report(
+                dashboard_stream,
+                client,
+                user_log,
+                &Message::Timeline {
+                    sequence,
+                    timeline: &timeline,
+                },
+            );
+            Ok(())
+        }
+        Command::TakeControl => match dashboard_stream.take_control(client) {
+            Ok(true) => Ok(()),
+            // another dashboard took control after this command was authorized
+            Ok(false) => Err(Error::ControlHeld),
+            Err(_) => Err(Error::Poison),
+        },
+        Command::ReleaseControl => dashboard_stream
+            .release_control(client)
+            .map(|_| ())
+            .map_err(|_| Error::Poison),
+        Command::Ping | Command::Hello { .. } => Ok(()),
+    }
+}
+
+/// Send a message about the progress of a command to the dashboard which issued it.
+/// Failing to send the message is not a reason to stop executing the command, so any failure will
+/// only be reported to the user.
+fn report(
+    dashboard_stream: &DashChannel<impl Write, impl Write>,
+    client: ClientId,
+    user_log: &UserLog<impl Write>,
+    message: &Message,
+) {
+    if let Err(e) = dashboard_stream.send_to(client, message) {
+        #[allow(unused_must_use)]
+        {
+            user_log.warn(&format!(
+                "Unable to report command progress to dashboard: {e:?}"
+            ));
+        }
+    }
+}
+
+/// Determine whether a command from the dashboard is allowed to be executed.
+///
+/// This is the authorization layer for all commands coming from the dashboard.
+/// A command is authorized if it is permitted in the current state of the controller, if it was
+/// issued by the commander (for commands which require it), and if it does not attempt to manually
+/// actuate a protected driver.
+/// Procedures such as the ignition, emergency stop and named sequences are not subject to this
+/// check, so protected drivers may still be actuated by them.
+///
+/// The states in which each command is permitted, and whether each requires the issuing dashboard
+/// (`client`) to be the commander, are as follows:
+///
+/// | Command          | Permitted states          | Commander only |
+/// | ---------------- | ------------------------- | -------------- |
+/// | `Actuate`        | `Standby`                 | yes            |
+/// | `Pulse`          | `Standby`                 | yes            |
+/// | `Arm`            | `Standby`                 | yes            |
+/// | `Disarm`         | `Armed`                   | yes            |
+/// | `Ignition`       | `Armed`                   | yes            |
+/// | `Hold`           | `Countdown`               | yes            |
+/// | `Resume`         | `Hold`                    | yes            |
+/// | `Recycle`        | `Countdown` or `Hold`     | yes            |
+/// | `EmergencyStop`  | every state except `Quit` | no             |
+/// | `RunSequence`    | `Standby`                 | yes            |
+/// | `Preview`        | `Standby`                 | no             |
+/// | `TakeControl`    | every state               | no             |
+/// | `ReleaseControl` | every state               | yes            |
+/// | `Ping`           | every state               | no             |
+/// | `Hello`          | every state               | no             |
+///
+/// Additionally, `TakeControl` is rejected if another dashboard is already the commander, and
+/// `RunSequence` and `Preview` are rejected if the sequence does not exist.
+///
+/// # Errors
+///
+/// This function will return an `Err` describing the reason that the command was rejected if it is
+/// not authorized.
+fn authorize(
+    cmd: &Command,
+    configuration: &Configuration,
+    state: State,
+    client: ClientId,
+    commander: Option<ClientId>,
+) -> Result<(), Error> {
+    let permitted = match cmd {
+        Command::Actuate { .. }
+        | Command::Pulse { .. }
+        | Command::Arm
+        | Command::RunSequence { .. }
+        | Command::Preview { .. } => state == State::Standby,
+        Command::Disarm | Command::Ignition => state == State::Armed,
+        Command::Hold => state == State::Countdown,
+        Command::Resume => state == State::Hold,
+        Command::Recycle => matches!(state, State::Countdown | State::Hold),
+        Command::EmergencyStop => state != State::Quit,
+        Command::TakeControl | Command::ReleaseControl | Command::Ping | Command::Hello { .. } => {
+            true
+        }
+    };
+
+    if !permitted {
+        return Err(Error::Forbidden(state));
+    }
+
+    let is_commander = commander == Some(client);
+    match cmd {
+        Command::Actuate { .. }
+        | Command::Pulse { .. }
+        | Command::Arm
+        | Command::Disarm
+        | Command::Ignition
+        | Command::Hold
+        | Command::Resume
+        | Command::Recycle
+        | Command::RunSequence { .. }
+        | Command::ReleaseControl
+            if !is_commander =>
+        {
+            return Err(Error::NotCommander);
+        }
+        Command::TakeControl if commander.is_some() && !is_commander => {
+            return Err(Error::ControlHeld);
+        }
+        _ => (),
+    }
+
+    if let Command::Actuate { driver_id, .. } | Command::Pulse { driver_id, .. } = cmd {
+        let id = resolve_driver(configuration, driver_id)?;
+        if configuration.drivers[usize::from(id)].protected {
+            return Err(Error::ProtectedDriver(id));
+        }
+    }
+
+    if let Command::RunSequence { name } = cmd {
+        if !configuration.sequences.contains_key(name) {
+            return Err(Error::NoSuchSequence(name.clone()));
+        }
+    }
+
+    if let Command::Preview { sequence } = cmd {
+        if preview::find_sequence(configuration, sequence).is_none() {
+            return Err(Error::NoSuchSequence(sequence.clone()));
+        }
+    }
+
+    Ok(())
+}
+
 /// Attempt to perform an emergency stop procedure.
 ///
 /// # Errors
@@ -148,62 +541,528 @@ pub fn handle_command(
 pub fn emergency_stop(
     configuration: &Configuration,
     driver_lines: &Mutex<Vec<impl GpioPin>>,
+    readings: &Readings,
+    state: &Guard,
+    user_log: &UserLog<impl Write>,
+) -> Result<(), Error> {
+    safe(
+        configuration,
+        driver_lines,
+        readings,
+        &configuration.estop_sequence,
+        state,
+        user_log,
+    )
+}
+
+/// Attempt to safe the engine by moving into the emergency stop state and running the sequence
+/// `actions`.
+///
+/// This is identical to an emergency stop, except that any sequence may be used in place of the
+/// configured `estop_sequence`.
+/// If `actions` is aborted by a `WaitUntil` timing out, the configured `estop_sequence` is run in
+/// its place.
+///
+/// # Errors
+///
+/// This function can return an `Err` in the following cases:
+///
+/// * A lock was poisoned.
+/// * We failed to gain control over GPIO.
+/// * Another emergency stop finished before this one did.
+pub fn safe(
+    configuration: &Configuration,
+    driver_lines: &Mutex<Vec<impl GpioPin>>,
+    readings: &Readings,
+    actions: &[Action],
     state: &Guard,
+    user_log: &UserLog<impl Write>,
 ) -> Result<(), Error> {
-    // transition to EStop, and if it's already in EStopping, don't interfere
+    // transition to EStop, and if it's already in EStopping, don't interfere.
+    // moving into EStopping is what preempts any other procedure which is currently running.
     state.move_to(State::EStopping)?;
 
-    perform_actions(driver_lines, &configuration.estop_sequence)?;
+    match perform_actions(
+        configuration,
+        driver_lines,
+        readings,
+        actions,
+        state,
+        State::EStopping,
+        user_log,
+    ) {
+        Err(Error::WaitTimeout(_)) => perform_actions(
+            configuration,
+            driver_lines,
+            readings,
+            &configuration.estop_sequence,
+            state,
+            State::EStopping,
+            user_log,
+        )?,
+        result => result?,
+    }
 
     // done doing the estop sequence, move back to standby
-    state.move_to(State::Standby)?;
+    if !state.advance(State::EStopping, State::Standby)? {
+        // someone else already finished an emergency stop for us
+        return Err(Error::Preempted);
+    }
 
     Ok(())
 }
 
+/// Arm the controller, so that it will accept an ignition command.
+///
+/// If an arm key is configured, it must read high for the controller to be armed.
+/// The controller stays armed until it leaves the `Armed` state (for instance, by starting an
+/// ignition or being disarmed), or until the arming timeout in the configuration passes, in which
+/// case it returns to standby.
+/// This function does not return until the controller is no longer armed.
+///
+/// # Errors
+///
+/// This function can return an `Err` in the following cases:
+///
+/// * The controller was not in standby.
+/// * A lock was poisoned.
+/// * We failed to read the arm key.
+/// * The arm key was not turned.
+/// * The arming timed out before the controller left the `Armed` state.
+fn arm(
+    configuration: &Configuration,
+    arm_key: &Mutex<Option<impl GpioPin>>,
+    state: &Guard,
+) -> Result<(), Error> {
+    if let Some(key) = arm_key.lock()?.as_mut() {
+        if !key.read().map_err(|_| Error::Poison)? {
+            return Err(Error::ArmKeyOff);
+        }
+    }
+
+    // subscribe before arming so that we cannot mistake a later arming for this one
+    let transitions = state.subscribe()?;
+    state.move_to(State::Armed)?;
+
+    let deadline = Instant::now() + Duration::from_millis(u64::from(configuration.arming.timeout));
+    loop {
+        match transitions.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(transition) if transition.from == State::Armed => return Ok(()),
+            Ok(_) => (),
+            Err(RecvTimeoutError::Timeout) => break,
+            Err(RecvTimeoutError::Disconnected) => return Err(Error::Poison),
+        }
+    }
+
+    if state.advance(State::Armed, State::Standby)? {
+        Err(Error::ArmingExpired)
+    } else {
+        // the controller left the `Armed` state just as the arming timed out
+        Ok(())
+    }
+}
+
 /// Attempt to perform an ignition procedure.
 ///
+/// If a countdown is configured, it is run before pre-ignition (see `countdown`).
+/// Just before entering pre-ignition, the go/no-go checks in the configuration are evaluated (see
+/// `go_no_go`); if any of them fail, the ignition is called off and the controller returns to
+/// standby.
+/// If the controller leaves one of the ignition states partway through (for instance, because
+/// of an emergency stop), the ignition will halt and none of its remaining steps will be
+/// performed.
+/// If a `WaitUntil` action in the ignition sequence times out and aborts, or an actuation in the
+/// countdown or ignition sequence would break an interlock, the ignition will halt and an
+/// emergency stop will be performed.
+///
 /// # Errors
 ///
 /// This function can return an `Err` in the following cases:
 ///
-/// * The user attempted to perform an ignition from a state which was not standby.
+/// * The user attempted to perform an ignition while the controller was not armed.
 /// * A lock was poisoned.
 /// * We failed to gain control over GPIO.
+/// * The ignition was preempted by a change in state, including the countdown being recycled.
+/// * A go/no-go check failed.
+/// * The ignition was aborted by a `WaitUntil` action timing out or by an actuation which would
+///   break an interlock.
 fn ignition(
     configuration: &Configuration,
     driver_lines: &Mutex<Vec<impl GpioPin>>,
+    readings: &Readings,
     state: &Guard,
+    dashboard_stream: &DashChannel<impl Write, impl Write>,
+    user_log: &UserLog<impl Write>,
 ) -> Result<(), Error> {
-    state.move_to(State::PreIgnite)?;
-    sleep(Duration::from_millis(u64::from(
-        configuration.pre_ignite_time,
-    )));
+    let go = || {
+        go_no_go(
+            configuration,
+            driver_lines,
+            readings,
+            dashboard_stream,
+            user_log,
+        )
+    };
+    if let Some(clock) = &configuration.countdown {
+        let result = countdown(
+            configuration,
+            clock,
+            driver_lines,
+            readings,
+            state,
+            dashboard_stream,
+            user_log,
+            go,
+        );
+        if let Err(Error::Interlocked(_)) = result {
+            // a countdown event could not be performed, so make the engine safe
+            emergency_stop(configuration, driver_lines, readings, state, user_log)?;
+        }
+        result?;
+    } else {
+        if !go()? {
+            advance(state, State::Armed, State::Standby)?;
+            return Err(Error::NoGo);
+        }
+        advance(state, State::Armed, State::PreIgnite)?;
+    }
+
+    if !state.sleep_in(
+        State::PreIgnite,
+        Duration::from_millis(u64::from(configuration.pre_ignite_time)),
+    )? {
+        return Err(Error::Preempted);
+    }
 
-    state.move_to(State::Ignite)?;
-    perform_actions(driver_lines, &configuration.ignition_sequence)?;
+    advance(state, State::PreIgnite, State::Ignite)?;
+    let result = perform_actions(
+        configuration,
+        driver_lines,
+        readings,
+        &configuration.ignition_sequence,
+        state,
+        State::Ignite,
+        user_log,
+    );
+    if let Err(Error::WaitTimeout(_) | Error::Interlocked(_)) = result {
+        // the ignition could not be completed, so make the engine safe
+        emergency_stop(configuration, driver_lines, readings, state, user_log)?;
+    }
+    result?;
 
-    state.move_to(State::PostIgnite)?;
-    sleep(Duration::from_millis(u64::from(
-        configuration.post_ignite_time,
-    )));
+    advance(state, State::Ignite, State::PostIgnite)?;
+    if !state.sleep_in(
+        State::PostIgnite,
+        Duration::from_millis(u64::from(configuration.post_ignite_time)),
+    )? {
+        return Err(Error::Preempted);
+    }
 
     // done doing the ignition sequence, move back to standby
-    state.move_to(State::Standby)?;
+    advance(state, State::PostIgnite, State::Standby)?;
 
     Ok(())
 }
 
+/// Count down to an ignition, moving from armed into the countdown and then into pre-ignition
+/// once the clock reaches zero.
+///
+/// Each event in the countdown is performed once the clock reaches its T-minus time.
+/// While the controller is in the `Hold` state the clock is frozen and no events are performed;
+/// once it moves back into `Countdown`, the clock picks up where it left off.
+/// The time remaining is broadcast to every dashboard each time it passes a whole second, and
+/// whenever the countdown is held or resumed.
+///
+/// Once the clock reaches zero, `go` is called to decide whether the ignition may proceed.
+/// If it returns `false`, the countdown is called off and the controller returns to standby.
+///
+/// # Errors
+///
+/// This function can return an `Err` in the following cases:
+///
+/// * The controller was not armed.
+/// * A lock was poisoned.
+/// * We failed to gain control over GPIO.
+/// * The countdown was recycled or preempted by an emergency stop.
+/// * `go` returned `false`.
+/// * A countdown event would break an interlock.
+#[allow(clippy::too_many_arguments)]
+fn countdown(
+    configuration: &Configuration,
+    clock: &Countdown,
+    driver_lines: &Mutex<Vec<impl GpioPin>>,
+    readings: &Readings,
+    state: &Guard,
+    dashboard_stream: &DashChannel<impl Write, impl Write>,
+    user_log: &UserLog<impl Write>,
+    go: impl Fn() -> Result<bool, Error>,
+) -> Result<(), Error> {
+    // subscribe before starting so that we cannot miss a hold, resume, or recycle
+    let transitions = state.subscribe()?;
+    state.move_to(State::Countdown)?;
+
+    let mut events: Vec<_> = clock.events.iter().collect();
+    events.sort_by_key(|event| Reverse(event.t_minus));
+    let mut events = events.into_iter().peekable();
+
+    let mut remaining = Duration::from_millis(u64::from(clock.length));
+    let mut held = false;
+    let mut announced = None;
+    loop {
+        let whole_secs = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+        if announced != Some((whole_secs, held)) {
+            announced = Some((whole_secs, held));
+            if let Err(e) = dashboard_stream.send(&Message::Countdown {
+                t_minus: u64::try_from(remaining.as_millis()).unwrap_or(u64::MAX),
+                held,
+            }) {
+                #[allow(unused_must_use)]
+                {
+                    user_log.warn(&format!("Unable to send countdown to dashboard: {e:?}"));
+                }
+            }
+        }
+
+        let next_event = events
+            .peek()
+            .map(|event| Duration::from_millis(u64::from(event.t_minus)));
+        if !held {
+            if matches!(next_event, Some(t_minus) if t_minus >= remaining) {
+                let event = events.peek().unwrap();
+                match perform_actions(
+                    configuration,
+                    driver_lines,
+                    readings,
+                    &event.actions,
+                    state,
+                    State::Countdown,
+                    user_log,
+                ) {
+                    Ok(()) => {
+                        events.next();
+                        continue;
+                    }
+                    // we were held or stopped partway through; the event will be performed again
+                    // if the countdown is resumed, and the transition is handled below
+                    Err(Error::Preempted) => (),
+                    Err(e) => return Err(e),
+                }
+            } else if remaining.is_zero() {
+                // if either advance fails, the countdown was held or stopped at the last moment,
+                // and the transition is handled below
+                if !go()? {
+                    if state.advance(State::Countdown, State::Standby)? {
+                        return Err(Error::NoGo);
+                    }
+                } else if state.advance(State::Countdown, State::PreIgnite)? {
+                    return Ok(());
+                }
+            }
+        }
+
+        // sleep until the next event or the next whole second, whichever is sooner, unless a
+        // transition wakes us up first
+        let start = Instant::now();
+        let transition = if held {
+            Some(transitions.recv().map_err(|_| Error::Poison)?)
+        } else {
+            let until_event = remaining.saturating_sub(next_event.unwrap_or_default());
+            let until_second =
+                remaining.saturating_sub(Duration::from_secs(whole_secs.saturating_sub(1)));
+            match transitions.recv_timeout(until_event.min(until_second)) {
+                Ok(transition) => Some(transition),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => return Err(Error::Poison),
+            }
+        };
+        if !held {
+            remaining = remaining.saturating_sub(start.elapsed());
+        }
+
+        match transition.map(|t| t.to) {
+            None => (),
+            Some(State::Hold) => held = true,
+            Some(State::Countdown) => held = false,
+            Some(_) => return Err(Error::Preempted),
+        }
+    }
+}
+
+/// Evaluate every go/no-go check in the configuration.
+///
+/// The outcome of each check is logged to the user log and broadcast to every dashboard in a
+/// `GoNoGo` message.
+/// Returns `true` if every check passed (or if there are no checks), and `false` otherwise.
+///
+/// # Errors
+///
+/// This function will return an error if a lock is poisoned or if we are unable to read a driver.
+fn go_no_go(
+    configuration: &Configuration,
+    driver_lines: &Mutex<Vec<impl GpioPin>>,
+    readings: &Readings,
+    dashboard_stream: &DashChannel<impl Write, impl Write>,
+    user_log: &UserLog<impl Write>,
+) -> Result<bool, Error> {
+    if configuration.checks.is_empty() {
+        return Ok(true);
+    }
+
+    let mut results = Vec::with_capacity(configuration.checks.len());
+    for check in &configuration.checks {
+        let (go, detail) = match check {
+            Check::SensorInRange { sensor, min, max } => {
+                match readings.get(sensor).map_err(|_| Error::Poison)? {
+                    Some(v) if *min <= v && v <= *max => {
+                        (true, format!("{sensor} reads {v}, within [{min}, {max}]"))
+                    }
+                    Some(v) => (false, format!("{sensor} reads {v}, outside [{min}, {max}]")),
+                    None => (false, format!("{sensor} has never been read")),
+                }
+            }
+            Check::DriverValue { driver_id, value } => {
+                let id = usize::from(resolve_driver(configuration, driver_id)?);
+                let actual = driver_lines.lock()?[id].read().map_err(|_| Error::Poison)?;
+                let label = &configuration.drivers[id].label;
+                (
+                    actual == *value,
+                    format!("{label} is {actual}, expected {value}"),
+                )
+            }
+            Check::NoReadErrors { samples } => {
+                let mut failing = Vec::new();
+                for channel in configuration
+                    .sensor_groups
+                    .iter()
+                    .flat_map(SensorGroup::channels)
+                {
+                    let errors = readings
+                        .read_errors(channel.label, *samples)
+                        .map_err(|_| Error::Poison)?
+                        .unwrap_or(0);
+                    if errors > 0 {
+                        failing.push(format!("{} ({errors})", channel.label));
+                    }
+                }
+                if failing.is_empty() {
+                    (
+                        true,
+                        format!("no read errors in the last {samples} samples"),
+                    )
+                } else {
+                    (
+                        false,
+                        format!(
+                            "read errors in the last {samples} samples: {}",
+                            failing.join(", ")
+                        ),
+                    )
+                }
+            }
+            Check::DashboardConnected => {
+                match dashboard_stream.commander().map_err(|_| Error::Poison)? {
+                    Some(client) => (true, format!("{client} is the commander")),
+                    None => (false, String::from("no commander is connected")),
+                }
+            }
+        };
+
+        #[allow(unused_must_use)]
+        if go {
+            user_log.info(&format!("GO: {detail}"));
+        } else {
+            user_log.warn(&format!("NO-GO: {detail}"));
+        }
+        results.push(CheckResult { check, go, detail });
+    }
+
+    if let Err(e) = dashboard_stream.send(&Message::GoNoGo { results: &results }) {
+        #[allow(unused_must_use)]
+        {
+            user_log.warn(&format!(
+                "Unable to send go/no-go results to dashboard: {e:?}"
+            ));
+        }
+    }
+
+    Ok(results.iter().all(|result| result.go))
+}
+
+/// Attempt to run the named auxiliary sequence `name` from the configuration.
+///
+/// The controller is in the `Sequencing` state while the sequence runs, and returns to `Standby`
+/// once it is done.
+/// If the controller leaves the `Sequencing` state partway through (for instance, because of an
+/// emergency stop), the sequence will halt and none of its remaining steps will be performed.
+/// If a `WaitUntil` action in the sequence times out and aborts, or an actuation in the sequence
+/// would break an interlock, the sequence will halt and an emergency stop will be performed.
+///
+/// # Errors
+///
+/// This function can return an `Err` in the following cases:
+///
+/// * The sequence does not exist.
+/// * The controller was not in standby.
+/// * A lock was poisoned.
+/// * We failed to gain control over GPIO.
+/// * The sequence was preempted by a change in state.
+/// * The sequence was aborted by a `WaitUntil` action timing out or by an actuation which would
+///   break an interlock.
+fn run_sequence(
+    configuration: &Configuration,
+    driver_lines: &Mutex<Vec<impl GpioPin>>,
+    readings: &Readings,
+    name: &str,
+    state: &Guard,
+    user_log: &UserLog<impl Write>,
+) -> Result<(), Error> {
+    let actions = configuration
+        .sequences
+        .get(name)
+        .ok_or_else(|| Error::NoSuchSequence(name.to_string()))?;
+
+    state.move_to(State::Sequencing)?;
+    let result = perform_actions(
+        configuration,
+        driver_lines,
+        readings,
+        actions,
+        state,
+        State::Sequencing,
+        user_log,
+    );
+    if let Err(Error::WaitTimeout(_) | Error::Interlocked(_)) = result {
+        emergency_stop(configuration, driver_lines, readings, state, user_log)?;
+    }
+    result?;
+
+    advance(state, State::Sequencing, State::Standby)
+}
+
+/// Advance the state of a procedure from `from` into `to`.
+///
+/// # Errors
+///
+/// This function will return an `Err(Error::Preempted)` if the state was changed out from under
+/// the procedure, or any error generated by the guard while transitioning.
+fn advance(state: &Guard, from: State, to: State) -> Result<(), Error> {
+    if state.advance(from, to)? {
+        Ok(())
+    } else {
+        Err(Error::Preempted)
+    }
+}
+
 /// Actuate a given driver to a given value using GPIO cdev to interface with OS.
 ///
 /// # Inputs
 ///
 /// * `driver_lines`: A table of GPIO lines for all drivers.
 /// * `driver_id`: The ID of the driver to be actuated.
-///     An ID is an index into `configuration.drivers` for the associated driver.
-///     It is also the same index into `driver_lines`.
+///   An ID is an index into `configuration.drivers` for the associated driver.
+///   It is also the same index into `driver_lines`.
 /// * `value`: The logic level that the driver should be actuated to.
-///     `value` should be `true` to get a high value on the GPIO pin, and `false` for a low value.
+///   `value` should be `true` to get a high value on the GPIO pin, and `false` for a low value.
 ///
 /// # Errors
 ///
@@ -219,40 +1078,485 @@ fn actuate_driver(
         .map_err(|_| Error::Poison)
 }
 
-/// Perform a sequence of actions, such as for emergency stopping or for
-/// ignition.
+/// Find the ID of the driver referred to by `driver` in `configuration`.
 ///
 /// # Errors
 ///
-/// This function will return an error if we are unable to write to GPIO.
-fn perform_actions(
-    driver_lines: &Mutex<Vec<impl GpioPin>>,
-    actions: &[Action],
+/// This function will return `Error::DriverOutOfBounds` if there is no such driver.
+fn resolve_driver(configuration: &Configuration, driver: &DriverRef) -> Result<u8, Error> {
+    configuration
+        .driver_id(driver)
+        .ok_or(Error::DriverOutOfBounds)
+}
+
+/// Determine whether actuating the driver `driver_id` to `value` while in the state `state` would
+/// break any of the interlocks in `configuration`, given the current levels of `driver_lines`.
+///
+/// Deactuating a driver can never break an interlock.
+///
+/// # Errors
+///
+/// This function will return `Error::Interlocked` with the index of the first interlock which
+/// would be broken, or an error if we are unable to read a driver.
+fn check_interlocks(
+    configuration: &Configuration,
+    driver_lines: &mut [impl GpioPin],
+    state: State,
+    driver_id: u8,
+    value: bool,
 ) -> Result<(), Error> {
-    for action in actions {
-        match action {
-            Action::Actuate { driver_id, value } => {
-                driver_lines.lock().map_err(|_| Error::Poison)?[*driver_id as usize]
-                    .write(*value)
-                    .map_err(|_| Error::Poison)?;
+    if !value {
+        return Ok(());
+    }
+
+    for (i, interlock) in configuration.interlocks.iter().enumerate() {
+        let broken = match interlock {
+            Interlock::Exclusive { drivers } => {
+                let mut others_on = false;
+                let ids: Vec<u8> = drivers
+                    .iter()
+                    .filter_map(|driver| configuration.driver_id(driver))
+                    .collect();
+                if ids.contains(&driver_id) {
+                    for &other in ids.iter().filter(|&&other| other != driver_id) {
+                        others_on |= driver_lines[usize::from(other)]
+                            .read()
+                            .map_err(|_| Error::Poison)?;
+                    }
+                }
+                others_on
             }
-            Action::Sleep { duration } => sleep(*duration),
+            Interlock::OnlyInStates {
+                driver_id: guarded,
+                states,
+            } => configuration.driver_id(guarded) == Some(driver_id) && !states.contains(&state),
         };
+
+        if broken {
+            return Err(Error::Interlocked(i));
+        }
     }
 
     Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use std::{io::Cursor, thread::scope};
-
-    use crate::hardware::ListenerPin;
-
-    use super::*;
-
-    #[test]
-    /// Test that state transitions are performed correctly during ignition.
+/// Perform a sequence of actions, such as for emergency stopping or for
+/// ignition.
+///
+/// The sequence is only allowed to run while the controller is in the state `running`.
+/// Before each actuation and during each sleep or wait, the state is checked, and if the controller
+/// has left `running`, the sequence halts immediately without performing any more of its steps.
+///
+/// `WaitUntil` actions are evaluated against the sensor values in `readings`.
+///
+/// Each actuation is checked against the interlocks in `configuration`, except while running in
+/// the `EStopping` state: an emergency stop must always be able to make the engine safe, so it
+/// overrides every interlock.
+///
+/// `Pulse` actions do not block the rest of the sequence.
+/// Each pulsed driver is returned to its previous level once its pulse is over, and the sequence
+/// does not finish until every pulse has ended.
+/// If the sequence halts early for any reason, every pulse is ended immediately.
+///
+/// Every step is scheduled against a single clock which starts when the sequence does, so delays in
+/// performing one step do not push back the steps after it.
+/// The actual and planned time of every actuation are written to `user_log`, so that scheduling
+/// jitter can be measured.
+///
+/// # Errors
+///
+/// This function will return an error if we are unable to write to GPIO, `Error::Preempted` if
+/// the controller left the state `running` before the sequence was completed,
+/// `Error::WaitTimeout` if a `WaitUntil` action timed out and was configured to abort, or
+/// `Error::Interlocked` if an actuation would break an interlock.
+fn perform_actions(
+    configuration: &Configuration,
+    driver_lines: &Mutex<Vec<impl GpioPin>>,
+    readings: &Readings,
+    actions: &[Action],
+    state: &Guard,
+    running: State,
+    user_log: &UserLog<impl Write>,
+) -> Result<(), Error> {
+    let start = Instant::now();
+    let mut procedure = Procedure {
+        configuration,
+        driver_lines,
+        readings,
+        state,
+        running,
+        user_log,
+        start,
+        pulses: Vec::new(),
+    };
+
+    procedure.run_tracks(&[actions], start)?;
+
+    // let every pulse run its course before finishing
+    if let Some(last_end) = procedure.pulses.iter().map(|&(end, _)| end).max() {
+        if !sleep_pulsing(state, running, last_end, &mut procedure.pulses)? {
+            return Err(Error::Preempted);
+        }
+    }
+
+    Ok(())
+}
+
+/// A procedure which is being performed by `perform_actions`.
+struct Procedure<'a, P: GpioPin, W: Write> {
+    /// The configuration, which contains the interlocks to check.
+    configuration: &'a Configuration,
+    /// The lines of every driver.
+    driver_lines: &'a Mutex<Vec<P>>,
+    /// The latest sensor values, which `WaitUntil` steps are evaluated against.
+    readings: &'a Readings,
+    /// The state of the controller.
+    state: &'a Guard,
+    /// The state in which the procedure is allowed to run.
+    running: State,
+    /// The log to which actuation times are written.
+    user_log: &'a UserLog<W>,
+    /// The time at which the procedure began, against which every step is scheduled.
+    start: Instant,
+    /// The pulses which are still going, along with the times at which they end.
+    /// Dropping a pulse ends it, so halting the procedure early ends every pulse.
+    pulses: Vec<(Instant, Pulse<'a, P>)>,
+}
+
+/// One track of steps in a procedure, which may be performed alongside other tracks.
+struct Track<'s> {
+    /// The steps of the track which have not yet been performed.
+    steps: &'s [Action],
+    /// The time at which the next step of the track is planned to be performed.
+    planned: Instant,
+    /// If the track is waiting on a `WaitUntil` step, the time at which the wait began.
+    waiting_since: Option<Instant>,
+}
+
+impl<P: GpioPin, W: Write> Procedure<'_, P, W> {
+    /// Perform every track in `tracks` concurrently, with each track beginning at `begin`.
+    /// Returns the time at which the last track was planned to finish.
+    ///
+    /// # Errors
+    ///
+    /// This function will return the first error encountered by any track, at which point every
+    /// track is halted.
+    fn run_tracks(&mut self, tracks: &[&[Action]], begin: Instant) -> Result<Instant, Error> {
+        let mut tracks: Vec<Track> = tracks
+            .iter()
+            .map(|&steps| Track {
+                steps,
+                planned: begin,
+                waiting_since: None,
+            })
+            .collect();
+        let mut end = begin;
+
+        loop {
+            let now = Instant::now();
+            for track in &mut tracks {
+                self.advance(track, now)?;
+            }
+
+            // a track whose last step is a sleep is not finished until the sleep is over
+            tracks.retain(|track| {
+                let finished = track.steps.is_empty() && track.planned <= now;
+                if finished {
+                    end = end.max(track.planned);
+                }
+                !finished
+            });
+            let Some(wake) = tracks.iter().map(|track| track.planned).min() else {
+                return Ok(end);
+            };
+
+            if !sleep_pulsing(self.state, self.running, wake, &mut self.pulses)? {
+                return Err(Error::Preempted);
+            }
+        }
+    }
+
+    /// Perform every step of `track` which is due at the time `now`, stopping at the first step
+    /// which must wait.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any step fails, as described in `perform_actions`.
+    fn advance(&mut self, track: &mut Track, now: Instant) -> Result<(), Error> {
+        while let Some((step, rest)) = track.steps.split_first() {
+            if track.planned > now {
+                return Ok(());
+            }
+
+            match step {
+                Action::Actuate { driver_id, value } => {
+                    let id = resolve_driver(self.configuration, driver_id)?;
+                    let mut driver_guard = lock_for_actuation(
+                        self.configuration,
+                        self.driver_lines,
+                        self.state,
+                        self.running,
+                        id,
+                        *value,
+                    )?;
+                    driver_guard[usize::from(id)]
+                        .write(*value)
+                        .map_err(|_| Error::Poison)?;
+                    drop(driver_guard);
+                    self.log_actuation(id, *value, track.planned);
+                }
+                Action::Pulse {
+                    driver_id,
+                    duration,
+                } => {
+                    let id = resolve_driver(self.configuration, driver_id)?;
+                    let mut driver_guard = lock_for_actuation(
+                        self.configuration,
+                        self.driver_lines,
+                        self.state,
+                        self.running,
+                        id,
+                        true,
+                    )?;
+                    let pulse = Pulse::start(
+                        self.configuration,
+                        self.driver_lines,
+                        &mut driver_guard[..],
+                        self.state,
+                        self.running,
+                        id,
+                    )?;
+                    drop(driver_guard);
+                    self.pulses.push((track.planned + *duration, pulse));
+                    self.log_actuation(id, true, track.planned);
+                }
+                Action::Sleep { duration } => track.planned += *duration,
+                Action::WaitUntil {
+                    sensor,
+                    condition,
+                    threshold,
+                    timeout,
+                    on_timeout,
+                } => {
+                    let since = *track.waiting_since.get_or_insert(now);
+                    let value = self.readings.get(sensor).map_err(|_| Error::Poison)?;
+                    let holds = value.map_or(false, |v| match condition {
+                        Condition::Above => v > *threshold,
+                        Condition::Below => v < *threshold,
+                    });
+                    if !holds && now.duration_since(since) < *timeout {
+                        track.planned = now + WAIT_POLL_PERIOD;
+                        return Ok(());
+                    }
+                    if !holds && *on_timeout == TimeoutAction::Abort {
+                        return Err(Error::WaitTimeout(sensor.clone()));
+                    }
+
+                    // there is no way to plan for when a wait ends, so the rest of the track is
+                    // scheduled from the moment it did
+                    track.waiting_since = None;
+                    track.planned = now;
+                }
+                Action::Parallel { tracks } => {
+                    let tracks: Vec<&[Action]> = tracks.iter().map(Vec::as_slice).collect();
+                    track.planned = self.run_tracks(&tracks, track.planned)?;
+                }
+            }
+
+            track.steps = rest;
+        }
+
+        Ok(())
+    }
+
+    /// Record that the driver `driver_id` was just actuated to `value` by a step which was planned
+    /// for the time `planned`.
+    fn log_actuation(&self, driver_id: u8, value: bool, planned: Instant) {
+        let actual = self.start.elapsed();
+        let planned = planned.duration_since(self.start);
+
+        #[allow(unused_must_use)]
+        {
+            self.user_log.debug(&format!(
+                "Actuated driver {driver_id} to {value} at T+{:.3} ms (planned T+{:.3} ms)",
+                actual.as_secs_f64() * 1000.0,
+                planned.as_secs_f64() * 1000.0,
+            ));
+        }
+    }
+}
+
+/// Lock the drivers in preparation for a procedure running in the state `running` to actuate the
+/// driver `driver_id` to `value`.
+/// The drivers are held while checking the state, so that an emergency stop cannot begin actuating
+/// between our check and our write.
+///
+/// # Errors
+///
+/// This function will return `Error::Preempted` if the controller is no longer in `running`,
+/// `Error::Interlocked` if the actuation would break an interlock, or an error if a lock is
+/// poisoned.
+fn lock_for_actuation<'a, P: GpioPin>(
+    configuration: &Configuration,
+    driver_lines: &'a Mutex<Vec<P>>,
+    state: &Guard,
+    running: State,
+    driver_id: u8,
+    value: bool,
+) -> Result<MutexGuard<'a, Vec<P>>, Error> {
+    let mut driver_guard = driver_lines.lock().map_err(|_| Error::Poison)?;
+    if state.status()? != running {
+        return Err(Error::Preempted);
+    }
+    if running != State::EStopping {
+        check_interlocks(configuration, &mut driver_guard, running, driver_id, value)?;
+    }
+
+    Ok(driver_guard)
+}
+
+/// Sleep in the state `running` until the instant `until`, ending each pulse in `pulses` as soon as
+/// the time at which it ends has passed.
+///
+/// Returns `true` if the sleep completed, and `false` if it was interrupted by a state change.
+///
+/// # Errors
+///
+/// This function will return an error if the state guard's lock is poisoned.
+fn sleep_pulsing(
+    state: &Guard,
+    running: State,
+    until: Instant,
+    pulses: &mut Vec<(Instant, Pulse<impl GpioPin>)>,
+) -> Result<bool, Error> {
+    loop {
+        let now = Instant::now();
+        pulses.retain(|&(end, _)| end > now);
+        if now >= until {
+            return Ok(true);
+        }
+
+        let wake = pulses.iter().map(|&(end, _)| end).fold(until, Instant::min);
+        if !state.sleep_in(running, wake - now)? {
+            return Ok(false);
+        }
+    }
+}
+
+/// A driver which is being pulsed to `true`.
+///
+/// When a `Pulse` is dropped, the pulse ends, and its driver is returned to the level it was at
+/// before the pulse began.
+/// This means that the driver is returned even if the thread performing the pulse fails or is
+/// preempted partway through.
+///
+/// If anything else deactuates the driver while it is being pulsed, or if an emergency stop whose
+/// sequence actuates the driver begins, the driver is left alone when the pulse ends.
+struct Pulse<'a, P: GpioPin> {
+    /// The configuration, whose emergency stop sequence takes precedence over the pulse.
+    configuration: &'a Configuration,
+    /// The lines of every driver.
+    driver_lines: &'a Mutex<Vec<P>>,
+    /// The state of the controller.
+    state: &'a Guard,
+    /// The state that the controller was in when the pulse began.
+    running: State,
+    /// The identifier (i.e. index) of the pulsed driver.
+    driver_id: u8,
+    /// The level of the driver before the pulse began.
+    previous: bool,
+}
+
+impl<'a, P: GpioPin> Pulse<'a, P> {
+    /// Begin pulsing the driver `driver_id` during a procedure running in the state `running`.
+    /// `driver_guard` must be the locked contents of `driver_lines`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if we are unable to read or write the driver.
+    fn start(
+        configuration: &'a Configuration,
+        driver_lines: &'a Mutex<Vec<P>>,
+        driver_guard: &mut [P],
+        state: &'a Guard,
+        running: State,
+        driver_id: u8,
+    ) -> Result<Self, Error> {
+        let line = &mut driver_guard[usize::from(driver_id)];
+        let previous = line.read().map_err(|_| Error::Poison)?;
+        line.write(true).map_err(|_| Error::Poison)?;
+
+        Ok(Pulse {
+            configuration,
+            driver_lines,
+            state,
+            running,
+            driver_id,
+            previous,
+        })
+    }
+}
+
+impl<P: GpioPin> Drop for Pulse<'_, P> {
+    fn drop(&mut self) {
+        // a poisoned lock only means that another thread panicked while holding it, and the driver
+        // must be returned regardless
+        let mut driver_guard = self
+            .driver_lines
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let line = &mut driver_guard[usize::from(self.driver_id)];
+
+        // something else has deactuated the driver since the pulse began
+        if !matches!(line.read(), Ok(true)) {
+            return;
+        }
+
+        // an emergency stop is making the engine safe, and knows better than we do
+        let preempting_estop = self.running != State::EStopping
+            && matches!(self.state.status(), Ok(State::EStopping))
+            && self
+                .configuration
+                .estop_sequence
+                .iter()
+                .flat_map(Action::with_substeps)
+                .any(|action| {
+                    matches!(
+                        action,
+                        Action::Actuate { driver_id, .. } | Action::Pulse { driver_id, .. }
+                            if self.configuration.driver_id(driver_id) == Some(self.driver_id)
+                    )
+                });
+        if preempting_estop {
+            return;
+        }
+
+        // there is nobody left to report a failure to
+        let _ = line.write(self.previous);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Cursor,
+        thread::{scope, sleep},
+        time::Instant,
+    };
+
+    use crate::{
+        console::LogLevel,
+        data::{sensor_listen, AbortMonitor, Sample},
+        hardware::{ListenerPin, ReturnsNumber},
+        outgoing::DashChannel,
+    };
+
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    /// Test that state transitions are performed correctly during ignition.
     fn ignition_state_transitions() {
         let config = r#"{
             "frequency_status": 1,
@@ -281,14 +1585,25 @@ mod tests {
 
         let mut cfg_cursor = Cursor::new(config);
         let config = Configuration::parse(&mut cfg_cursor).unwrap();
+        let readings = Readings::new(&config);
 
         let driver_lines: Mutex<Vec<ListenerPin>> = Mutex::new(Vec::new());
 
-        let state = Guard::new(State::Standby);
+        let state = Guard::new(State::Armed);
         let state_ref = &state;
 
         scope(|s| {
-            s.spawn(move || ignition(&config, &driver_lines, state_ref).unwrap());
+            s.spawn(move || {
+                ignition(
+                    &config,
+                    &driver_lines,
+                    &readings,
+                    state_ref,
+                    &DashChannel::<Vec<u8>, _>::new(Vec::new()),
+                    &UserLog::new(Vec::new()),
+                )
+                .unwrap();
+            });
 
             sleep(Duration::from_millis(250));
             assert_eq!(state.status().unwrap(), State::PreIgnite);
@@ -343,10 +1658,19 @@ mod tests {
 
         let mut cfg_cursor = Cursor::new(config);
         let config = Configuration::parse(&mut cfg_cursor).unwrap();
+        let readings = Readings::new(&config);
         let driver_lines = Mutex::new(vec![ListenerPin::new(false)]);
-        let state = Guard::new(State::Standby);
+        let state = Guard::new(State::Armed);
 
-        ignition(&config, &driver_lines, &state).unwrap();
+        ignition(
+            &config,
+            &driver_lines,
+            &readings,
+            &state,
+            &DashChannel::<Vec<u8>, _>::new(Vec::new()),
+            &UserLog::new(Vec::new()),
+        )
+        .unwrap();
 
         assert_eq!(
             driver_lines.lock().unwrap()[0].history().as_slice(),
@@ -384,6 +1708,7 @@ mod tests {
 
         let mut cfg_cursor = Cursor::new(config);
         let config = Configuration::parse(&mut cfg_cursor).unwrap();
+        let readings = Readings::new(&config);
 
         let driver_lines = Mutex::new(Vec::<ListenerPin>::new());
 
@@ -391,7 +1716,16 @@ mod tests {
         let state_ref = &state;
 
         scope(|s| {
-            s.spawn(move || emergency_stop(&config, &driver_lines, state_ref).unwrap());
+            s.spawn(move || {
+                emergency_stop(
+                    &config,
+                    &driver_lines,
+                    &readings,
+                    state_ref,
+                    &UserLog::new(Vec::new()),
+                )
+                .unwrap();
+            });
 
             sleep(Duration::from_millis(250));
             assert_eq!(state.status().unwrap(), State::EStopping);
@@ -410,7 +1744,15 @@ mod tests {
             "sensor_groups": [],
             "pre_ignite_time": 0,
             "post_ignite_time": 0,
-            "drivers": [],
+            "drivers": [
+                {
+                    "label": "OXI_FILL",
+                    "label_actuate": "Open",
+                    "label_deactuate": "Close",
+                    "pin": 21,
+                    "protected": false
+                }
+            ],
             "ignition_sequence": [],
             "estop_sequence": [
                 {
@@ -420,7 +1762,7 @@ mod tests {
                 },
                 {
                     "type": "Actuate",
-                    "driver_id": 0,
+                    "driver_id": "OXI_FILL",
                     "value": false
                 }
             ],
@@ -434,14 +1776,1619 @@ mod tests {
 
         let mut cfg_cursor = Cursor::new(config);
         let config = Configuration::parse(&mut cfg_cursor).unwrap();
+        let readings = Readings::new(&config);
         let driver_lines = Mutex::new(vec![ListenerPin::new(false)]);
         let state = Guard::new(State::Standby);
 
-        emergency_stop(&config, &driver_lines, &state).unwrap();
+        emergency_stop(
+            &config,
+            &driver_lines,
+            &readings,
+            &state,
+            &UserLog::new(Vec::new()),
+        )
+        .unwrap();
 
         assert_eq!(
             driver_lines.lock().unwrap()[0].history().as_slice(),
             [false, true, false]
         );
     }
+
+    #[test]
+    /// Test that an emergency stop partway through the ignition sequence halts the sequence
+    /// immediately and that none of the remaining ignition steps are performed.
+    fn estop_preempts_ignition() {
+        let config = r#"{
+            "frequency_status": 1,
+            "log_buffer_size": 1,
+            "sensor_groups": [],
+            "pre_ignite_time": 0,
+            "post_ignite_time": 0,
+            "drivers": [
+                {
+                    "label": "OXI_FILL",
+                    "label_actuate": "Open",
+                    "label_deactuate": "Close",
+                    "pin": 21,
+                    "protected": false
+                },
+                {
+                    "label": "IGNITER",
+                    "label_actuate": "Ignite",
+                    "label_deactuate": "Shutoff",
+                    "pin": 22,
+                    "protected": true
+                }
+            ],
+            "ignition_sequence": [
+                {
+                    "type": "Actuate",
+                    "driver_id": 0,
+                    "value": true
+                },
+                {
+                    "type": "Sleep",
+                    "duration": {
+                        "secs": 1,
+                        "nanos": 0
+                    }
+                },
+                {
+                    "type": "Actuate",
+                    "driver_id": 1,
+                    "value": true
+                }
+            ],
+            "estop_sequence": [
+                {
+                    "type": "Actuate",
+                    "driver_id": 0,
+                    "value": false
+                }
+            ],
+            "spi_mosi": 11,
+            "spi_miso": 12,
+            "spi_clk": 13,
+            "spi_frequency_clk": 50000,
+            "adc_cs": [],
+            "pin_heartbeat": 0
+        }"#;
+
+        let mut cfg_cursor = Cursor::new(config);
+        let config = Configuration::parse(&mut cfg_cursor).unwrap();
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let state = Guard::new(State::Armed);
+
+        scope(|s| {
+            let handle = s.spawn(|| {
+                ignition(
+                    &config,
+                    &driver_lines,
+                    &readings,
+                    &state,
+                    &DashChannel::<Vec<u8>, _>::new(Vec::new()),
+                    &UserLog::new(Vec::new()),
+                )
+            });
+
+            sleep(Duration::from_millis(250));
+            assert_eq!(state.status().unwrap(), State::Ignite);
+
+            let estop_time = Instant::now();
+            emergency_stop(
+                &config,
+                &driver_lines,
+                &readings,
+                &state,
+                &UserLog::new(Vec::new()),
+            )
+            .unwrap();
+
+            // the ignition thread must quit promptly instead of sleeping out its sequence
+            assert!(matches!(handle.join().unwrap(), Err(Error::Preempted)));
+            assert!(estop_time.elapsed() < Duration::from_millis(100));
+        });
+
+        assert_eq!(state.status().unwrap(), State::Standby);
+        let driver_guard = driver_lines.lock().unwrap();
+        assert_eq!(driver_guard[0].history().as_slice(), [false, true, false]);
+        // the igniter was never fired
+        assert_eq!(driver_guard[1].history().as_slice(), [false]);
+    }
+
+    #[test]
+    /// Test that an emergency stop during pre-ignition prevents any of the ignition sequence from
+    /// being performed.
+    fn estop_preempts_pre_ignition() {
+        let config = r#"{
+            "frequency_status": 1,
+            "log_buffer_size": 1,
+            "sensor_groups": [],
+            "pre_ignite_time": 1000,
+            "post_ignite_time": 0,
+            "drivers": [{
+                "label": "OXI_FILL",
+                "label_actuate": "Open",
+                "label_deactuate": "Close",
+                "pin": 21,
+                "protected": false
+            }],
+            "ignition_sequence": [
+                {
+                    "type": "Actuate",
+                    "driver_id": 0,
+                    "value": true
+                }
+            ],
+            "estop_sequence": [],
+            "spi_mosi": 11,
+            "spi_miso": 12,
+            "spi_clk": 13,
+            "spi_frequency_clk": 50000,
+            "adc_cs": [],
+            "pin_heartbeat": 0
+        }"#;
+
+        let mut cfg_cursor = Cursor::new(config);
+        let config = Configuration::parse(&mut cfg_cursor).unwrap();
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false)]);
+        let state = Guard::new(State::Armed);
+
+        scope(|s| {
+            let handle = s.spawn(|| {
+                ignition(
+                    &config,
+                    &driver_lines,
+                    &readings,
+                    &state,
+                    &DashChannel::<Vec<u8>, _>::new(Vec::new()),
+                    &UserLog::new(Vec::new()),
+                )
+            });
+
+            sleep(Duration::from_millis(100));
+            assert_eq!(state.status().unwrap(), State::PreIgnite);
+
+            let estop_time = Instant::now();
+            emergency_stop(
+                &config,
+                &driver_lines,
+                &readings,
+                &state,
+                &UserLog::new(Vec::new()),
+            )
+            .unwrap();
+
+            assert!(matches!(handle.join().unwrap(), Err(Error::Preempted)));
+            assert!(estop_time.elapsed() < Duration::from_millis(100));
+        });
+
+        // ignition never moved on past pre-ignition, and so the state is left alone
+        assert_eq!(state.status().unwrap(), State::Standby);
+        assert_eq!(
+            driver_lines.lock().unwrap()[0].history().as_slice(),
+            [false]
+        );
+    }
+
+    #[test]
+    /// Test that an out-of-range sensor reading during ignition preempts the ignition sequence.
+    fn sensor_estop_preempts_ignition() {
+        let config = r##"{
+            "frequency_status": 1,
+            "log_buffer_size": 1,
+            "sensor_groups": [
+                {
+                    "label": "dummy",
+                    "frequency_standby": 10,
+                    "frequency_ignition": 10,
+                    "frequency_transmission": 10,
+                    "sensors": [
+                        {
+                            "label": "dummy_sensor0",
+                            "color": "#ef3b9e",
+                            "units": "mops",
+                            "calibration": {
+                                "type": "Linear",
+                                "slope": 1,
+                                "intercept": 0
+                            },
+                            "adc": 0,
+                            "channel": 0,
+                            "range": [-5, 5]
+                        }
+                    ]
+                }
+            ],
+            "pre_ignite_time": 1000,
+            "post_ignite_time": 0,
+            "drivers": [{
+                "label": "OXI_FILL",
+                "label_actuate": "Open",
+                "label_deactuate": "Close",
+                "pin": 21,
+                "protected": false
+            }],
+            "ignition_sequence": [
+                {
+                    "type": "Actuate",
+                    "driver_id": 0,
+                    "value": true
+                }
+            ],
+            "estop_sequence": [],
+            "spi_mosi": 11,
+            "spi_miso": 12,
+            "spi_clk": 13,
+            "spi_frequency_clk": 50000,
+            "adc_cs": [14],
+            "pin_heartbeat": 0
+        }"##;
+
+        let mut cfg_cursor = Cursor::new(config);
+        let config = Configuration::parse(&mut cfg_cursor).unwrap();
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false)]);
+        let state = Guard::new(State::Armed);
+        let adcs = [Mutex::new(ReturnsNumber(100))];
+        let mut logs = vec![Cursor::new(Vec::new())];
+        let dash_channel = DashChannel::<Vec<u8>, Vec<u8>>::new(Vec::new());
+
+        let user_log = UserLog::new(Vec::<u8>::new());
+        scope(|s| {
+            let handle = s.spawn(|| {
+                ignition(
+                    &config,
+                    &driver_lines,
+                    &readings,
+                    &state,
+                    &DashChannel::<Vec<u8>, _>::new(Vec::new()),
+                    &UserLog::new(Vec::new()),
+                )
+            });
+            sleep(Duration::from_millis(100));
+
+            // the sensor will immediately read out of range and attempt to estop
+            let estop_time = Instant::now();
+            s.spawn(|| {
+                sensor_listen(
+                    s,
+                    0,
+                    &config,
+                    &driver_lines,
+                    &mut logs,
+                    &user_log,
+                    &adcs,
+                    &readings,
+                    &AbortMonitor::new(&config),
+                    &state,
+                    &dash_channel,
+                )
+            });
+
+            assert!(matches!(handle.join().unwrap(), Err(Error::Preempted)));
+            assert!(estop_time.elapsed() < Duration::from_millis(100));
+
+            // continually attempt to kill the sensor thread
+            while state.move_to(State::Quit).is_err() {
+                sleep(Duration::from_millis(10));
+            }
+        });
+
+        assert_eq!(
+            driver_lines.lock().unwrap()[0].history().as_slice(),
+            [false]
+        );
+    }
+
+    /// A configuration with one unprotected driver and one protected driver.
+    const PROTECTION_CONFIG: &str = r#"{
+        "frequency_status": 1,
+        "log_buffer_size": 1,
+        "sensor_groups": [],
+        "pre_ignite_time": 0,
+        "post_ignite_time": 0,
+        "drivers": [
+            {
+                "label": "OXI_FILL",
+                "label_actuate": "Open",
+                "label_deactuate": "Close",
+                "pin": 21,
+                "protected": false
+            },
+            {
+                "label": "IGNITER",
+                "label_actuate": "Ignite",
+                "label_deactuate": "Shutoff",
+                "pin": 22,
+                "protected": true
+            }
+        ],
+        "ignition_sequence": [],
+        "estop_sequence": [],
+        "sequences": {
+            "test_igniter": [
+                {
+                    "type": "Actuate",
+                    "driver_id": 1,
+                    "value": true
+                },
+                {
+                    "type": "Actuate",
+                    "driver_id": 1,
+                    "value": false
+                }
+            ]
+        },
+        "spi_mosi": 11,
+        "spi_miso": 12,
+        "spi_clk": 13,
+        "spi_frequency_clk": 50000,
+        "adc_cs": [],
+        "pin_heartbeat": 0
+    }"#;
+
+    /// Construct a configuration whose ignition sequence waits for the chamber pressure to rise
+    /// above 50 before opening the main valve (driver 0).
+    /// If the wait times out, `on_timeout` is taken; the emergency stop sequence opens the vent
+    /// valve (driver 1).
+    fn wait_config(on_timeout: &str) -> Configuration {
+        let config = format!(
+            r##"{{
+                "frequency_status": 1,
+                "log_buffer_size": 1,
+                "sensor_groups": [
+                    {{
+                        "label": "FAST",
+                        "frequency_standby": 10,
+                        "frequency_ignition": 10,
+                        "frequency_transmission": 10,
+                        "sensors": [
+                            {{
+                                "label": "PT_CHAMBER",
+                                "color": "#ef3b9e",
+                                "units": "psi",
+                                "calibration": {{
+                                    "type": "Linear",
+                                    "slope": 1,
+                                    "intercept": 0
+                                }},
+                                "adc": 0,
+                                "channel": 0
+                            }}
+                        ]
+                    }}
+                ],
+                "pre_ignite_time": 0,
+                "post_ignite_time": 0,
+                "drivers": [
+                    {{
+                        "label": "MAIN",
+                        "label_actuate": "Open",
+                        "label_deactuate": "Close",
+                        "pin": 21,
+                        "protected": false
+                    }},
+                    {{
+                        "label": "VENT",
+                        "label_actuate": "Open",
+                        "label_deactuate": "Close",
+                        "pin": 22,
+                        "protected": false
+                    }}
+                ],
+                "ignition_sequence": [
+                    {{
+                        "type": "WaitUntil",
+                        "sensor": "PT_CHAMBER",
+                        "condition": "Above",
+                        "threshold": 50,
+                        "timeout": {{
+                            "secs": 0,
+                            "nanos": 300000000
+                        }},
+                        "on_timeout": "{on_timeout}"
+                    }},
+                    {{
+                        "type": "Actuate",
+                        "driver_id": 0,
+                        "value": true
+                    }}
+                ],
+                "estop_sequence": [
+                    {{
+                        "type": "Actuate",
+                        "driver_id": 1,
+                        "value": true
+                    }}
+                ],
+                "spi_mosi": 11,
+                "spi_miso": 12,
+                "spi_clk": 13,
+                "spi_frequency_clk": 50000,
+                "adc_cs": [14],
+                "pin_heartbeat": 0
+            }}"##
+        );
+
+        Configuration::parse(&mut Cursor::new(config)).unwrap()
+    }
+
+    #[test]
+    /// Test that a `WaitUntil` step holds up the ignition sequence until its condition holds.
+    fn wait_until_confirms() {
+        let config = wait_config("Abort");
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let state = Guard::new(State::Armed);
+
+        scope(|s| {
+            let handle = s.spawn(|| {
+                ignition(
+                    &config,
+                    &driver_lines,
+                    &readings,
+                    &state,
+                    &DashChannel::<Vec<u8>, _>::new(Vec::new()),
+                    &UserLog::new(Vec::new()),
+                )
+            });
+
+            readings
+                .update(
+                    0,
+                    &[Some(Sample {
+                        value: 20.0,
+                        filtered: 20.0,
+                        rate: 0.0,
+                    })],
+                )
+                .unwrap();
+            sleep(Duration::from_millis(100));
+            // the pressure is too low, so the main valve must not be open yet
+            assert_eq!(
+                driver_lines.lock().unwrap()[0].history().as_slice(),
+                [false]
+            );
+
+            readings
+                .update(
+                    0,
+                    &[Some(Sample {
+                        value: 60.0,
+                        filtered: 60.0,
+                        rate: 0.0,
+                    })],
+                )
+                .unwrap();
+            handle.join().unwrap().unwrap();
+        });
+
+        let driver_guard = driver_lines.lock().unwrap();
+        assert_eq!(driver_guard[0].history().as_slice(), [false, true]);
+        assert_eq!(driver_guard[1].history().as_slice(), [false]);
+    }
+
+    #[test]
+    /// Test that a `WaitUntil` step which times out aborts the ignition into an emergency stop.
+    fn wait_until_timeout_aborts() {
+        let config = wait_config("Abort");
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let state = Guard::new(State::Armed);
+
+        let result = ignition(
+            &config,
+            &driver_lines,
+            &readings,
+            &state,
+            &DashChannel::<Vec<u8>, _>::new(Vec::new()),
+            &UserLog::new(Vec::new()),
+        );
+        assert!(matches!(result, Err(Error::WaitTimeout(label)) if label == "PT_CHAMBER"));
+
+        assert_eq!(state.status().unwrap(), State::Standby);
+        let driver_guard = driver_lines.lock().unwrap();
+        assert_eq!(driver_guard[0].history().as_slice(), [false]);
+        assert_eq!(driver_guard[1].history().as_slice(), [false, true]);
+    }
+
+    #[test]
+    /// Test that a `WaitUntil` step which times out may be configured to carry on.
+    fn wait_until_timeout_continues() {
+        let config = wait_config("Continue");
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let state = Guard::new(State::Armed);
+
+        ignition(
+            &config,
+            &driver_lines,
+            &readings,
+            &state,
+            &DashChannel::<Vec<u8>, _>::new(Vec::new()),
+            &UserLog::new(Vec::new()),
+        )
+        .unwrap();
+
+        let driver_guard = driver_lines.lock().unwrap();
+        assert_eq!(driver_guard[0].history().as_slice(), [false, true]);
+        assert_eq!(driver_guard[1].history().as_slice(), [false]);
+    }
+
+    /// Construct a dashboard channel with one connected dashboard, which is the commander.
+    fn commander_channel() -> (DashChannel<Vec<u8>, Vec<u8>>, ClientId) {
+        let dash_channel = DashChannel::new(Vec::new());
+        let client = dash_channel.add_client(Vec::new()).unwrap();
+        dash_channel.set_features(client, Vec::new()).unwrap();
+        assert!(dash_channel.take_control(client).unwrap());
+        (dash_channel, client)
+    }
+
+    /// Construct a request, with no ID, to actuate a driver.
+    fn actuate_request(driver_id: u8, value: bool) -> Request {
+        Request {
+            id: None,
+            command: Command::Actuate {
+                driver_id: DriverRef::Id(driver_id),
+                value,
+            },
+        }
+    }
+
+    #[test]
+    /// Test that an unprotected driver can be actuated from the dashboard during standby.
+    fn actuate_unprotected() {
+        let config = Configuration::parse(&mut Cursor::new(PROTECTION_CONFIG)).unwrap();
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let state = Guard::new(State::Standby);
+        let log_file = Mutex::new(Vec::new());
+
+        let (dash_channel, client) = commander_channel();
+
+        handle_command(
+            &actuate_request(0, true),
+            client,
+            &log_file,
+            &UserLog::new(Vec::new()),
+            &config,
+            &driver_lines,
+            &Mutex::new(None::<ListenerPin>),
+            &readings,
+            &state,
+            &dash_channel,
+        )
+        .unwrap();
+
+        assert_eq!(
+            driver_lines.lock().unwrap()[0].history().as_slice(),
+            [false, true]
+        );
+    }
+
+    #[test]
+    /// Test that a protected driver cannot be actuated from the dashboard, and that the rejection
+    /// is logged.
+    fn actuate_protected_rejected() {
+        let config = Configuration::parse(&mut Cursor::new(PROTECTION_CONFIG)).unwrap();
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let state = Guard::new(State::Standby);
+        let log_file = Mutex::new(Vec::new());
+
+        let (dash_channel, client) = commander_channel();
+
+        let result = handle_command(
+            &actuate_request(1, true),
+            client,
+            &log_file,
+            &UserLog::new(Vec::new()),
+            &config,
+            &driver_lines,
+            &Mutex::new(None::<ListenerPin>),
+            &readings,
+            &state,
+            &dash_channel,
+        );
+
+        assert!(matches!(result, Err(Error::ProtectedDriver(1))));
+        assert_eq!(
+            driver_lines.lock().unwrap()[1].history().as_slice(),
+            [false]
+        );
+
+        let log = String::from_utf8(log_file.into_inner().unwrap()).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(",request,actuate 1 true"));
+        assert!(lines[1].ends_with(",reject,actuate 1 true,driver 1 is protected"));
+    }
+
+    #[test]
+    /// Test that drivers cannot be manually actuated during ignition.
+    fn actuate_forbidden_during_ignite() {
+        let config = Configuration::parse(&mut Cursor::new(PROTECTION_CONFIG)).unwrap();
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let state = Guard::new(State::Ignite);
+        let log_file = Mutex::new(Vec::new());
+
+        let (dash_channel, client) = commander_channel();
+
+        let result = handle_command(
+            &actuate_request(0, true),
+            client,
+            &log_file,
+            &UserLog::new(Vec::new()),
+            &config,
+            &driver_lines,
+            &Mutex::new(None::<ListenerPin>),
+            &readings,
+            &state,
+            &dash_channel,
+        );
+
+        assert!(matches!(result, Err(Error::Forbidden(State::Ignite))));
+        assert_eq!(
+            driver_lines.lock().unwrap()[0].history().as_slice(),
+            [false]
+        );
+    }
+
+    #[test]
+    /// Test that actuating a driver which does not exist is rejected.
+    fn actuate_out_of_bounds() {
+        let config = Configuration::parse(&mut Cursor::new(PROTECTION_CONFIG)).unwrap();
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let state = Guard::new(State::Standby);
+
+        let (dash_channel, client) = commander_channel();
+
+        let result = handle_command(
+            &actuate_request(2, true),
+            client,
+            &Mutex::new(Vec::new()),
+            &UserLog::new(Vec::new()),
+            &config,
+            &driver_lines,
+            &Mutex::new(None::<ListenerPin>),
+            &readings,
+            &state,
+            &dash_channel,
+        );
+
+        assert!(matches!(result, Err(Error::DriverOutOfBounds)));
+    }
+
+    #[test]
+    /// Test that the dashboard is told when commands are accepted, finished, or rejected.
+    fn command_acknowledgements() {
+        let config = Configuration::parse(&mut Cursor::new(PROTECTION_CONFIG)).unwrap();
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let state = Guard::new(State::Standby);
+        let (dash_channel, client) = commander_channel();
+
+        for (id, driver_id) in [(7, 0), (8, 1)] {
+            #[allow(unused_must_use)]
+            {
+                handle_command(
+                    &Request {
+                        id: Some(id),
+                        command: Command::Actuate {
+                            driver_id: DriverRef::Id(driver_id),
+                            value: true,
+                        },
+                    },
+                    client,
+                    &Mutex::new(Vec::new()),
+                    &UserLog::new(Vec::new()),
+                    &config,
+                    &driver_lines,
+                    &Mutex::new(None::<ListenerPin>),
+                    &readings,
+                    &state,
+                    &dash_channel,
+                );
+            }
+        }
+
+        let messages: Vec<(String, u64)> = dash_channel
+            .sent(client)
+            .into_iter()
+            .map(|m| {
+                (
+                    m["type"].as_str().unwrap().to_string(),
+                    m["id"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            messages,
+            [
+                (String::from("Ack"), 7),
+                (String::from("Done"), 7),
+                (String::from("Nack"), 8)
+            ]
+        );
+    }
+
+    #[test]
+    /// Test that only the commander may actuate drivers, and that only one dashboard may be the
+    /// commander at a time.
+    fn commander_only() {
+        let config = Configuration::parse(&mut Cursor::new(PROTECTION_CONFIG)).unwrap();
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let state = Guard::new(State::Standby);
+        let (dash_channel, commander) = commander_channel();
+        let observer = dash_channel.add_client(Vec::new()).unwrap();
+        dash_channel.set_features(observer, Vec::new()).unwrap();
+
+        let run = |client, command| {
+            handle_command(
+                &Request { id: None, command },
+                client,
+                &Mutex::new(Vec::new()),
+                &UserLog::new(Vec::new()),
+                &config,
+                &driver_lines,
+                &Mutex::new(None::<ListenerPin>),
+                &readings,
+                &state,
+                &dash_channel,
+            )
+        };
+
+        assert!(matches!(
+            run(
+                observer,
+                Command::Actuate {
+                    driver_id: DriverRef::Id(0),
+                    value: true
+                }
+            ),
+            Err(Error::NotCommander)
+        ));
+        assert!(matches!(
+            run(observer, Command::TakeControl),
+            Err(Error::ControlHeld)
+        ));
+        assert!(matches!(
+            run(observer, Command::ReleaseControl),
+            Err(Error::NotCommander)
+        ));
+
+        run(commander, Command::ReleaseControl).unwrap();
+        run(observer, Command::TakeControl).unwrap();
+        assert_eq!(dash_channel.commander().unwrap(), Some(observer));
+        run(
+            observer,
+            Command::Actuate {
+                driver_id: DriverRef::Id(0),
+                value: true,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            driver_lines.lock().unwrap()[0].history().as_slice(),
+            [false, true]
+        );
+    }
+
+    #[test]
+    /// Test that any dashboard may perform an emergency stop, even if it is not the commander.
+    fn observer_estop() {
+        let config = Configuration::parse(&mut Cursor::new(PROTECTION_CONFIG)).unwrap();
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let state = Guard::new(State::Standby);
+        let (dash_channel, _) = commander_channel();
+        let observer = dash_channel.add_client(Vec::new()).unwrap();
+        dash_channel.set_features(observer, Vec::new()).unwrap();
+
+        handle_command(
+            &Request {
+                id: None,
+                command: Command::EmergencyStop,
+            },
+            observer,
+            &Mutex::new(Vec::new()),
+            &UserLog::new(Vec::new()),
+            &config,
+            &driver_lines,
+            &Mutex::new(None::<ListenerPin>),
+            &readings,
+            &state,
+            &dash_channel,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    /// Test that a named sequence can be run by the commander, even if it actuates a protected
+    /// driver, and that the controller returns to standby afterward.
+    fn run_named_sequence() {
+        let config = Configuration::parse(&mut Cursor::new(PROTECTION_CONFIG)).unwrap();
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let state = Guard::new(State::Standby);
+        let transitions = state.subscribe().unwrap();
+        let (dash_channel, client) = commander_channel();
+
+        handle_command(
+            &Request {
+                id: None,
+                command: Command::RunSequence {
+                    name: String::from("test_igniter"),
+                },
+            },
+            client,
+            &Mutex::new(Vec::new()),
+            &UserLog::new(Vec::new()),
+            &config,
+            &driver_lines,
+            &Mutex::new(None::<ListenerPin>),
+            &readings,
+            &state,
+            &dash_channel,
+        )
+        .unwrap();
+
+        assert_eq!(
+            driver_lines.lock().unwrap()[1].history().as_slice(),
+            [false, true, false]
+        );
+        assert_eq!(
+            transitions
+                .try_iter()
+                .map(|t| (t.from, t.to))
+                .collect::<Vec<_>>(),
+            [
+                (State::Standby, State::Sequencing),
+                (State::Sequencing, State::Standby)
+            ]
+        );
+    }
+
+    #[test]
+    /// Test that a command to run a sequence which does not exist is rejected.
+    fn run_no_such_sequence() {
+        let config = Configuration::parse(&mut Cursor::new(PROTECTION_CONFIG)).unwrap();
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let state = Guard::new(State::Standby);
+        let (dash_channel, client) = commander_channel();
+
+        let result = handle_command(
+            &Request {
+                id: None,
+                command: Command::RunSequence {
+                    name: String::from("fill"),
+                },
+            },
+            client,
+            &Mutex::new(Vec::new()),
+            &UserLog::new(Vec::new()),
+            &config,
+            &driver_lines,
+            &Mutex::new(None::<ListenerPin>),
+            &readings,
+            &state,
+            &dash_channel,
+        );
+
+        assert!(matches!(result, Err(Error::NoSuchSequence(name)) if name == "fill"));
+        assert_eq!(state.status().unwrap(), State::Standby);
+    }
+
+    /// A configuration with a 400 millisecond countdown, which opens the only driver at T-200 ms.
+    const COUNTDOWN_CONFIG: &str = r#"{
+        "frequency_status": 1,
+        "log_buffer_size": 1,
+        "sensor_groups": [],
+        "pre_ignite_time": 0,
+        "post_ignite_time": 0,
+        "drivers": [
+            {
+                "label": "PURGE",
+                "label_actuate": "Open",
+                "label_deactuate": "Close",
+                "pin": 21,
+                "protected": true
+            }
+        ],
+        "countdown": {
+            "length": 400,
+            "events": [
+                {
+                    "t_minus": 200,
+                    "actions": [
+                        {
+                            "type": "Actuate",
+                            "driver_id": 0,
+                            "value": true
+                        }
+                    ]
+                }
+            ]
+        },
+        "ignition_sequence": [],
+        "estop_sequence": [],
+        "spi_mosi": 11,
+        "spi_miso": 12,
+        "spi_clk": 13,
+        "spi_frequency_clk": 50000,
+        "adc_cs": [],
+        "pin_heartbeat": 0
+    }"#;
+
+    #[test]
+    /// Test that holding a countdown freezes the clock and its events, and that resuming it picks
+    /// up where it left off.
+    fn countdown_hold_resume() {
+        let config = Configuration::parse(&mut Cursor::new(COUNTDOWN_CONFIG)).unwrap();
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false)]);
+        let state = Guard::new(State::Armed);
+        let transitions = state.subscribe().unwrap();
+        let (dash_channel, client) = commander_channel();
+        let send = |command| {
+            handle_command(
+                &Request { id: None, command },
+                client,
+                &Mutex::new(Vec::new()),
+                &UserLog::new(Vec::new()),
+                &config,
+                &driver_lines,
+                &Mutex::new(None::<ListenerPin>),
+                &readings,
+                &state,
+                &dash_channel,
+            )
+        };
+
+        scope(|s| {
+            let handle = s.spawn(|| send(Command::Ignition));
+
+            sleep(Duration::from_millis(100));
+            send(Command::Hold).unwrap();
+            assert_eq!(state.status().unwrap(), State::Hold);
+
+            // the event would have happened by now if the clock were still running
+            sleep(Duration::from_millis(300));
+            assert_eq!(state.status().unwrap(), State::Hold);
+            assert_eq!(
+                driver_lines.lock().unwrap()[0].history().as_slice(),
+                [false]
+            );
+
+            send(Command::Resume).unwrap();
+            sleep(Duration::from_millis(50));
+            assert_eq!(state.status().unwrap(), State::Countdown);
+            assert_eq!(
+                driver_lines.lock().unwrap()[0].history().as_slice(),
+                [false]
+            );
+
+            handle.join().unwrap().unwrap();
+        });
+
+        assert_eq!(
+            driver_lines.lock().unwrap()[0].history().as_slice(),
+            [false, true]
+        );
+        assert_eq!(
+            transitions
+                .try_iter()
+                .map(|t| (t.from, t.to))
+                .collect::<Vec<_>>(),
+            [
+                (State::Armed, State::Countdown),
+                (State::Countdown, State::Hold),
+                (State::Hold, State::Countdown),
+                (State::Countdown, State::PreIgnite),
+                (State::PreIgnite, State::Ignite),
+                (State::Ignite, State::PostIgnite),
+                (State::PostIgnite, State::Standby),
+            ]
+        );
+
+        let clock: Vec<(u64, bool)> = dash_channel
+            .sent(client)
+            .into_iter()
+            .filter(|message| message["type"] == "Countdown")
+            .map(|message| {
+                (
+                    message["t_minus"].as_u64().unwrap(),
+                    message["held"].as_bool().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(clock.first(), Some(&(400, false)));
+        assert_eq!(clock.last().map(|&(t_minus, _)| t_minus), Some(0));
+        assert_eq!(clock.iter().filter(|&&(_, held)| held).count(), 1);
+    }
+
+    #[test]
+    /// Test that recycling a countdown returns to standby without performing any more events.
+    fn countdown_recycle() {
+        let config = Configuration::parse(&mut Cursor::new(COUNTDOWN_CONFIG)).unwrap();
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false)]);
+        let state = Guard::new(State::Armed);
+        let (dash_channel, client) = commander_channel();
+        let send = |command| {
+            handle_command(
+                &Request { id: None, command },
+                client,
+                &Mutex::new(Vec::new()),
+                &UserLog::new(Vec::new()),
+                &config,
+                &driver_lines,
+                &Mutex::new(None::<ListenerPin>),
+                &readings,
+                &state,
+                &dash_channel,
+            )
+        };
+
+        scope(|s| {
+            let handle = s.spawn(|| send(Command::Ignition));
+
+            sleep(Duration::from_millis(100));
+            send(Command::Recycle).unwrap();
+            assert!(matches!(handle.join().unwrap(), Err(Error::Preempted)));
+        });
+
+        assert_eq!(state.status().unwrap(), State::Standby);
+        assert_eq!(
+            driver_lines.lock().unwrap()[0].history().as_slice(),
+            [false]
+        );
+        // there is no countdown to hold anymore
+        assert!(matches!(
+            send(Command::Hold),
+            Err(Error::Forbidden(State::Standby))
+        ));
+    }
+
+    /// Construct a configuration which requires an arm key on pin 5, and which disarms after 100
+    /// milliseconds.
+    fn arming_config() -> Configuration {
+        let config_str = PROTECTION_CONFIG.replace(
+            r#""sequences""#,
+            r#""arming": { "timeout": 100, "key_pin": 5 }, "sequences""#,
+        );
+        Configuration::parse(&mut Cursor::new(config_str)).unwrap()
+    }
+
+    #[test]
+    /// Test that arming times out if no ignition is started, and that an ignition cannot be started
+    /// afterward.
+    fn arming_expires() {
+        let config = arming_config();
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let arm_key = Mutex::new(Some(ListenerPin::new(true)));
+        let state = Guard::new(State::Standby);
+        let (dash_channel, client) = commander_channel();
+        let send = |command| {
+            handle_command(
+                &Request { id: None, command },
+                client,
+                &Mutex::new(Vec::new()),
+                &UserLog::new(Vec::new()),
+                &config,
+                &driver_lines,
+                &arm_key,
+                &readings,
+                &state,
+                &dash_channel,
+            )
+        };
+
+        let start = Instant::now();
+        assert!(matches!(send(Command::Arm), Err(Error::ArmingExpired)));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(state.status().unwrap(), State::Standby);
+
+        assert!(matches!(
+            send(Command::Ignition),
+            Err(Error::Forbidden(State::Standby))
+        ));
+    }
+
+    #[test]
+    /// Test that the controller cannot be armed while the arm key is not turned.
+    fn arm_key_off() {
+        let config = arming_config();
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let state = Guard::new(State::Standby);
+        let (dash_channel, client) = commander_channel();
+
+        let result = handle_command(
+            &Request {
+                id: None,
+                command: Command::Arm,
+            },
+            client,
+            &Mutex::new(Vec::new()),
+            &UserLog::new(Vec::new()),
+            &config,
+            &driver_lines,
+            &Mutex::new(Some(ListenerPin::new(false))),
+            &readings,
+            &state,
+            &dash_channel,
+        );
+
+        assert!(matches!(result, Err(Error::ArmKeyOff)));
+        assert_eq!(state.status().unwrap(), State::Standby);
+    }
+
+    #[test]
+    /// Test that an ignition may be started once the controller is armed, and that doing so ends
+    /// the arming.
+    fn arm_then_ignite() {
+        let config = arming_config();
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let arm_key = Mutex::new(Some(ListenerPin::new(true)));
+        let state = Guard::new(State::Standby);
+        let transitions = state.subscribe().unwrap();
+        let (dash_channel, client) = commander_channel();
+        let send = |command| {
+            handle_command(
+                &Request { id: None, command },
+                client,
+                &Mutex::new(Vec::new()),
+                &UserLog::new(Vec::new()),
+                &config,
+                &driver_lines,
+                &arm_key,
+                &readings,
+                &state,
+                &dash_channel,
+            )
+        };
+
+        scope(|s| {
+            let handle = s.spawn(|| send(Command::Arm));
+
+            sleep(Duration::from_millis(20));
+            assert_eq!(state.status().unwrap(), State::Armed);
+            send(Command::Ignition).unwrap();
+
+            handle.join().unwrap().unwrap();
+        });
+
+        assert_eq!(
+            transitions
+                .try_iter()
+                .map(|t| (t.from, t.to))
+                .collect::<Vec<_>>(),
+            [
+                (State::Standby, State::Armed),
+                (State::Armed, State::PreIgnite),
+                (State::PreIgnite, State::Ignite),
+                (State::Ignite, State::PostIgnite),
+                (State::PostIgnite, State::Standby),
+            ]
+        );
+    }
+
+    #[test]
+    /// Test that an ignition is called off if a go/no-go check fails, and that every check is
+    /// reported to the dashboard.
+    fn no_go() {
+        let config_str = PROTECTION_CONFIG.replace(
+            r#""sequences""#,
+            r#""checks": [
+                { "type": "DriverValue", "driver_id": 0, "value": true },
+                { "type": "DashboardConnected" }
+            ],
+            "sequences""#,
+        );
+        let config = Configuration::parse(&mut Cursor::new(config_str)).unwrap();
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let state = Guard::new(State::Armed);
+        let (dash_channel, client) = commander_channel();
+
+        let result = handle_command(
+            &Request {
+                id: None,
+                command: Command::Ignition,
+            },
+            client,
+            &Mutex::new(Vec::new()),
+            &UserLog::new(Vec::new()),
+            &config,
+            &driver_lines,
+            &Mutex::new(None::<ListenerPin>),
+            &readings,
+            &state,
+            &dash_channel,
+        );
+
+        assert!(matches!(result, Err(Error::NoGo)));
+        assert_eq!(state.status().unwrap(), State::Standby);
+
+        let reports: Vec<Value> = dash_channel
+            .sent(client)
+            .into_iter()
+            .filter(|message| message["type"] == "GoNoGo")
+            .collect();
+        assert_eq!(reports.len(), 1);
+        let go: Vec<bool> = reports[0]["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["go"].as_bool().unwrap())
+            .collect();
+        assert_eq!(go, [false, true]);
+    }
+
+    /// Construct a configuration with the drivers from `PROTECTION_CONFIG`, whose first driver may
+    /// not be on at the same time as the second, and whose second driver (the igniter) may only
+    /// be on during ignition.
+    fn interlock_config() -> Configuration {
+        let config_str = PROTECTION_CONFIG.replace(
+            r#""sequences""#,
+            r#""interlocks": [
+                { "type": "Exclusive", "drivers": [0, 1] },
+                { "type": "OnlyInStates", "driver_id": 1, "states": ["Ignite"] }
+            ],
+            "sequences""#,
+        );
+        Configuration::parse(&mut Cursor::new(config_str)).unwrap()
+    }
+
+    #[test]
+    /// Test that a manual actuation which would break an interlock is rejected.
+    fn interlock_rejects_actuation() {
+        let config = interlock_config();
+        let readings = Readings::new(&config);
+        // the igniter is already on, so the other driver may not be turned on
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(true)]);
+        let state = Guard::new(State::Standby);
+        let (dash_channel, client) = commander_channel();
+
+        let result = handle_command(
+            &actuate_request(0, true),
+            client,
+            &Mutex::new(Vec::new()),
+            &UserLog::new(Vec::new()),
+            &config,
+            &driver_lines,
+            &Mutex::new(None::<ListenerPin>),
+            &readings,
+            &state,
+            &dash_channel,
+        );
+
+        assert!(matches!(result, Err(Error::Interlocked(0))));
+        assert_eq!(
+            driver_lines.lock().unwrap()[0].history().as_slice(),
+            [false]
+        );
+        assert!(dash_channel
+            .sent(client)
+            .iter()
+            .any(|message| message["type"] == "Nack" && message["reason"]["Interlocked"] == 0));
+    }
+
+    #[test]
+    /// Test that a sequence which would break an interlock is halted and the engine is made safe,
+    /// but that the emergency stop sequence overrides interlocks.
+    fn interlock_halts_sequence() {
+        let config_str = PROTECTION_CONFIG
+            .replace(
+                r#""sequences""#,
+                r#""interlocks": [
+                    { "type": "OnlyInStates", "driver_id": 1, "states": ["Ignite"] }
+                ],
+                "sequences""#,
+            )
+            .replace(
+                r#""estop_sequence": []"#,
+                r#""estop_sequence": [{ "type": "Actuate", "driver_id": 1, "value": true }]"#,
+            );
+        let config = Configuration::parse(&mut Cursor::new(config_str)).unwrap();
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let state = Guard::new(State::Standby);
+        let transitions = state.subscribe().unwrap();
+
+        let result = run_sequence(
+            &config,
+            &driver_lines,
+            &readings,
+            "test_igniter",
+            &state,
+            &UserLog::new(Vec::new()),
+        );
+
+        assert!(matches!(result, Err(Error::Interlocked(0))));
+        assert_eq!(
+            transitions
+                .try_iter()
+                .map(|t| (t.from, t.to))
+                .collect::<Vec<_>>(),
+            [
+                (State::Standby, State::Sequencing),
+                (State::Sequencing, State::EStopping),
+                (State::EStopping, State::Standby),
+            ]
+        );
+        // only the emergency stop was able to actuate the igniter
+        assert_eq!(
+            driver_lines.lock().unwrap()[1].history().as_slice(),
+            [false, true]
+        );
+    }
+
+    /// Construct a configuration with the drivers from `PROTECTION_CONFIG` and an auxiliary
+    /// sequence named `"burp"` with the steps `steps`.
+    /// The emergency stop sequence turns on the igniter (driver 1), then waits for 100
+    /// milliseconds.
+    fn pulse_config(steps: &str) -> Configuration {
+        let config_str = PROTECTION_CONFIG
+            .replace(
+                r#""sequences": {"#,
+                &format!(r#""sequences": {{ "burp": {steps},"#),
+            )
+            .replace(
+                r#""estop_sequence": []"#,
+                r#""estop_sequence": [
+                    { "type": "Actuate", "driver_id": 1, "value": true },
+                    { "type": "Sleep", "duration": { "secs": 0, "nanos": 100000000 } }
+                ]"#,
+            );
+        Configuration::parse(&mut Cursor::new(config_str)).unwrap()
+    }
+
+    #[test]
+    /// Test that a pulse in a sequence returns its driver to its previous level without blocking
+    /// the rest of the sequence.
+    fn pulse_in_sequence() {
+        let config = pulse_config(
+            r#"[
+                {
+                    "type": "Pulse",
+                    "driver_id": 0,
+                    "duration": { "secs": 0, "nanos": 200000000 }
+                },
+                { "type": "Actuate", "driver_id": 1, "value": true },
+                { "type": "Sleep", "duration": { "secs": 0, "nanos": 100000000 } }
+            ]"#,
+        );
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let state = Guard::new(State::Standby);
+
+        let start = Instant::now();
+        run_sequence(
+            &config,
+            &driver_lines,
+            &readings,
+            "burp",
+            &state,
+            &UserLog::new(Vec::new()),
+        )
+        .unwrap();
+        let elapsed = start.elapsed();
+
+        // the sequence waited for the pulse to end, but slept during the pulse
+        assert!(elapsed >= Duration::from_millis(200));
+        assert!(elapsed < Duration::from_millis(290));
+        let driver_guard = driver_lines.lock().unwrap();
+        assert_eq!(driver_guard[0].history().as_slice(), [false, true, false]);
+        assert_eq!(driver_guard[1].history().as_slice(), [false, true]);
+        assert_eq!(state.status().unwrap(), State::Standby);
+    }
+
+    #[test]
+    /// Test that pulses are ended immediately when an emergency stop preempts them, except on
+    /// drivers which the emergency stop sequence actuates.
+    fn pulse_preempted_by_estop() {
+        let config = pulse_config(
+            r#"[
+                {
+                    "type": "Pulse",
+                    "driver_id": 0,
+                    "duration": { "secs": 10, "nanos": 0 }
+                },
+                {
+                    "type": "Pulse",
+                    "driver_id": 1,
+                    "duration": { "secs": 10, "nanos": 0 }
+                }
+            ]"#,
+        );
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let state = Guard::new(State::Standby);
+
+        let start = Instant::now();
+        scope(|s| {
+            let handle = s.spawn(|| {
+                run_sequence(
+                    &config,
+                    &driver_lines,
+                    &readings,
+                    "burp",
+                    &state,
+                    &UserLog::new(Vec::new()),
+                )
+            });
+
+            sleep(Duration::from_millis(50));
+            emergency_stop(
+                &config,
+                &driver_lines,
+                &readings,
+                &state,
+                &UserLog::new(Vec::new()),
+            )
+            .unwrap();
+            assert!(matches!(handle.join().unwrap(), Err(Error::Preempted)));
+        });
+
+        assert!(start.elapsed() < Duration::from_secs(1));
+        let driver_guard = driver_lines.lock().unwrap();
+        assert_eq!(driver_guard[0].history().as_slice(), [false, true, false]);
+        // the emergency stop sequence turned the igniter on, so the pulse left it on
+        assert_eq!(driver_guard[1].history().as_slice(), [false, true, true]);
+    }
+
+    #[test]
+    /// Test that a pulse from the dashboard returns its driver to its previous level.
+    fn pulse_command() {
+        let config = Configuration::parse(&mut Cursor::new(PROTECTION_CONFIG)).unwrap();
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let state = Guard::new(State::Standby);
+        let (dash_channel, client) = commander_channel();
+        let send = |driver_id| {
+            handle_command(
+                &Request {
+                    id: None,
+                    command: Command::Pulse {
+                        driver_id: DriverRef::Id(driver_id),
+                        duration: Duration::from_millis(50),
+                    },
+                },
+                client,
+                &Mutex::new(Vec::new()),
+                &UserLog::new(Vec::new()),
+                &config,
+                &driver_lines,
+                &Mutex::new(None::<ListenerPin>),
+                &readings,
+                &state,
+                &dash_channel,
+            )
+        };
+
+        send(0).unwrap();
+        // the igniter is protected
+        assert!(matches!(send(1), Err(Error::ProtectedDriver(1))));
+
+        let driver_guard = driver_lines.lock().unwrap();
+        assert_eq!(driver_guard[0].history().as_slice(), [false, true, false]);
+        assert_eq!(driver_guard[1].history().as_slice(), [false]);
+    }
+
+    #[test]
+    /// Test that parallel tracks are performed concurrently against the same clock, and that
+    /// planned and actual actuation times are logged.
+    fn parallel_tracks() {
+        let config = pulse_config(
+            r#"[
+                {
+                    "type": "Parallel",
+                    "tracks": [
+                        [
+                            { "type": "Actuate", "driver_id": 0, "value": true },
+                            { "type": "Sleep", "duration": { "secs": 0, "nanos": 100000000 } },
+                            { "type": "Actuate", "driver_id": 0, "value": false }
+                        ],
+                        [
+                            { "type": "Sleep", "duration": { "secs": 0, "nanos": 50000000 } },
+                            { "type": "Actuate", "driver_id": 1, "value": true },
+                            { "type": "Sleep", "duration": { "secs": 0, "nanos": 100000000 } }
+                        ]
+                    ]
+                },
+                { "type": "Actuate", "driver_id": 1, "value": false }
+            ]"#,
+        );
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let state = Guard::new(State::Standby);
+        let user_log = UserLog::new(Vec::new());
+        let entries = user_log.forward(LogLevel::Debug);
+
+        let start = Instant::now();
+        run_sequence(&config, &driver_lines, &readings, "burp", &state, &user_log).unwrap();
+        let elapsed = start.elapsed();
+
+        // the tracks overlapped, and the sequence waited for the longer one
+        assert!(elapsed >= Duration::from_millis(150));
+        assert!(elapsed < Duration::from_millis(240));
+        let driver_guard = driver_lines.lock().unwrap();
+        assert_eq!(driver_guard[0].history().as_slice(), [false, true, false]);
+        assert_eq!(driver_guard[1].history().as_slice(), [false, true, false]);
+
+        let planned: Vec<String> = entries
+            .try_iter()
+            .filter_map(|entry| {
+                let (_, planned) = entry.message.split_once("planned ")?;
+                Some(planned.to_string())
+            })
+            .collect();
+        assert_eq!(
+            planned,
+            [
+                "T+0.000 ms)",
+                "T+50.000 ms)",
+                "T+100.000 ms)",
+                "T+150.000 ms)"
+            ]
+        );
+    }
+
+    #[test]
+    /// Test that any dashboard may preview a sequence, and that the preview does not actuate any
+    /// drivers.
+    fn preview_sequence() {
+        let config = Configuration::parse(&mut Cursor::new(PROTECTION_CONFIG)).unwrap();
+        let readings = Readings::new(&config);
+        let driver_lines = Mutex::new(vec![ListenerPin::new(false), ListenerPin::new(false)]);
+        let state = Guard::new(State::Standby);
+        let dash_channel = DashChannel::new(Vec::new());
+        let observer = dash_channel.add_client(Vec::new()).unwrap();
+        dash_channel.set_features(observer, Vec::new()).unwrap();
+
+        handle_command(
+            &Request {
+                id: None,
+                command: Command::Preview {
+                    sequence: String::from("test_igniter"),
+                },
+            },
+            observer,
+            &Mutex::new(Vec::new()),
+            &UserLog::new(Vec::new()),
+            &config,
+            &driver_lines,
+            &Mutex::new(None::<ListenerPin>),
+            &readings,
+            &state,
+            &dash_channel,
+        )
+        .unwrap();
+
+        let sent = dash_channel.sent(observer);
+        let timeline = sent
+            .iter()
+            .find(|message| message["type"] == "Timeline")
+            .unwrap();
+        assert_eq!(timeline["sequence"], "test_igniter");
+        assert_eq!(timeline["events"][0]["label"], "IGNITER");
+        assert_eq!(timeline["events"][0]["description"], "Ignite");
+        assert_eq!(timeline["events"][1]["description"], "Shutoff");
+        let driver_guard = driver_lines.lock().unwrap();
+        assert_eq!(driver_guard[1].history().as_slice(), [false]);
+    }
 }
@@ -0,0 +1,490 @@
+/*
+  slonk, a rocket engine controller.
+  Copyright (C) 2022 Rice Eclipse.
+
+  slonk is free software: you can redistribute it and/or modify
+  it under the terms of the GNU General Public License as published by
+  the Free Software Foundation, either version 3 of the License, or
+  (at your option) any later version.
+
+  slonk is distributed in the hope that it will be useful,
+  but WITHOUT ANY WARRANTY; without even the implied warranty of
+  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+  GNU General Public License for more details.
+
+  You should have received a copy of the GNU General Public License
+  along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Arithmetic expressions over the values of sensors, which define derived channels.
+
+use std::{fmt::Display, iter::Peekable, str::CharIndices};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(try_from = "String", into = "String")]
+/// An arithmetic expression over the calibrated values of sensors, such as
+/// `PT_FEED - PT_CHAMBER`.
+///
+/// Expressions are written as strings, and are parsed as soon as they are read.
+/// They support numbers, sensor labels, the operators `+`, `-`, `*`, `/` and `^`, parentheses, and
+/// the functions `abs`, `sqrt`, `min` and `max`.
+/// A label which is not a plain identifier may be written between single quotes, as in
+/// `'PT2: Oxidizer feedline'`.
+pub struct Expression {
+    /// The text of the expression, exactly as it was written.
+    source: String,
+    /// The parsed form of the expression.
+    root: Node,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+/// A single node in the syntax tree of an expression.
+enum Node {
+    /// A constant number.
+    Constant(f64),
+    /// The calibrated value of the sensor with the given label.
+    Sensor(String),
+    /// The negation of the inner node.
+    Negate(Box<Node>),
+    /// A binary operator applied to two nodes.
+    Binary(Operator, Box<Node>, Box<Node>),
+    /// A function applied to its arguments.
+    Call(Function, Vec<Node>),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+/// The binary operators which may appear in an expression.
+enum Operator {
+    /// Addition, `+`.
+    Add,
+    /// Subtraction, `-`.
+    Subtract,
+    /// Multiplication, `*`.
+    Multiply,
+    /// Division, `/`.
+    Divide,
+    /// Exponentiation, `^`.
+    Power,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+/// The functions which may be called in an expression.
+enum Function {
+    /// The absolute value of its single argument.
+    Abs,
+    /// The square root of its single argument.
+    Sqrt,
+    /// The least of its arguments.
+    Min,
+    /// The greatest of its arguments.
+    Max,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+/// The errors which can occur when parsing an expression.
+pub enum Error {
+    /// The expression ended when more was expected.
+    UnexpectedEnd,
+    /// An unexpected character was found.
+    /// The value is the byte offset of the character in the expression.
+    Unexpected(char, usize),
+    /// A number could not be read.
+    /// The value is the text of the number.
+    BadNumber(String),
+    /// A function which does not exist was called.
+    /// The value is the name of the function.
+    UnknownFunction(String),
+    /// A function was called with the wrong number of arguments.
+    /// The value is the name of the function.
+    WrongArguments(String),
+}
+
+impl Expression {
+    /// Parse the expression written in `source`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `source` is not a well-formed expression.
+    pub fn parse(source: &str) -> Result<Expression, Error> {
+        let mut parser = Parser {
+            chars: source.char_indices().peekable(),
+        };
+        let root = parser.sum()?;
+        parser.skip_whitespace();
+        if let Some((idx, c)) = parser.chars.next() {
+            return Err(Error::Unexpected(c, idx));
+        }
+
+        Ok(Expression {
+            source: source.to_string(),
+            root,
+        })
+    }
+
+    /// Get the labels of every sensor which this expression refers to.
+    /// A label may appear more than once.
+    pub fn inputs(&self) -> Vec<&str> {
+        let mut inputs = Vec::new();
+        self.root.inputs(&mut inputs);
+        inputs
+    }
+
+    /// Evaluate this expression, using `lookup` to find the value of each sensor from its label.
+    /// Returns `None` if `lookup` gave `None` for any sensor which was needed.
+    ///
+    /// The result may be infinite or NaN, for instance after dividing by zero.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error only if `lookup` does.
+    pub fn evaluate<E>(
+        &self,
+        lookup: &mut impl FnMut(&str) -> Result<Option<f64>, E>,
+    ) -> Result<Option<f64>, E> {
+        self.root.evaluate(lookup)
+    }
+}
+
+impl Node {
+    /// Add the labels of every sensor in this node to `inputs`.
+    fn inputs<'a>(&'a self, inputs: &mut Vec<&'a str>) {
+        match self {
+            Node::Constant(_) => (),
+            Node::Sensor(label) => inputs.push(label),
+            Node::Negate(inner) => inner.inputs(inputs),
+            Node::Binary(_, lhs, rhs) => {
+                lhs.inputs(inputs);
+                rhs.inputs(inputs);
+            }
+            Node::Call(_, args) => {
+                for arg in args {
+                    arg.inputs(inputs);
+                }
+            }
+        }
+    }
+
+    /// Evaluate this node, as described in `Expression::evaluate`.
+    fn evaluate<E>(
+        &self,
+        lookup: &mut impl FnMut(&str) -> Result<Option<f64>, E>,
+    ) -> Result<Option<f64>, E> {
+        Ok(Some(match self {
+            Node::Constant(x) => *x,
+            Node::Sensor(label) => match lookup(label)? {
+                Some(x) => x,
+                None => return Ok(None),
+            },
+            Node::Negate(inner) => match inner.evaluate(lookup)? {
+                Some(x) => -x,
+                None => return Ok(None),
+            },
+            Node::Binary(op, lhs, rhs) => {
+                let (Some(x), Some(y)) = (lhs.evaluate(lookup)?, rhs.evaluate(lookup)?) else {
+                    return Ok(None);
+                };
+                match op {
+                    Operator::Add => x + y,
+                    Operator::Subtract => x - y,
+                    Operator::Multiply => x * y,
+                    Operator::Divide => x / y,
+                    Operator::Power => x.powf(y),
+                }
+            }
+            Node::Call(function, args) => {
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    match arg.evaluate(lookup)? {
+                        Some(x) => values.push(x),
+                        None => return Ok(None),
+                    }
+                }
+                match function {
+                    Function::Abs => values[0].abs(),
+                    Function::Sqrt => values[0].sqrt(),
+                    Function::Min => values.into_iter().fold(f64::INFINITY, f64::min),
+                    Function::Max => values.into_iter().fold(f64::NEG_INFINITY, f64::max),
+                }
+            }
+        }))
+    }
+}
+
+impl Function {
+    /// Find the function named `name`, if there is one.
+    fn named(name: &str) -> Option<Function> {
+        match name {
+            "abs" => Some(Function::Abs),
+            "sqrt" => Some(Function::Sqrt),
+            "min" => Some(Function::Min),
+            "max" => Some(Function::Max),
+            _ => None,
+        }
+    }
+
+    /// Determine whether this function can be called with `n` arguments.
+    fn accepts(self, n: usize) -> bool {
+        match self {
+            Function::Abs | Function::Sqrt => n == 1,
+            Function::Min | Function::Max => n >= 1,
+        }
+    }
+}
+
+/// A recursive-descent parser for expressions.
+///
+/// From loosest to tightest, the precedence of operators is: `+` and `-`; `*` and `/`; unary `-`;
+/// and `^`, which is right-associative.
+struct Parser<'a> {
+    /// The remaining characters of the expression, each with its byte offset.
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl Parser<'_> {
+    /// Skip past any whitespace.
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+    }
+
+    /// Skip past any whitespace, then look at the next character without consuming it.
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    /// Consume the next character, which must be `expected`.
+    fn expect(&mut self, expected: char) -> Result<(), Error> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some((_, c)) if c == expected => Ok(()),
+            Some((idx, c)) => Err(Error::Unexpected(c, idx)),
+            None => Err(Error::UnexpectedEnd),
+        }
+    }
+
+    /// Parse a sum or difference of products.
+    fn sum(&mut self) -> Result<Node, Error> {
+        let mut node = self.product()?;
+        loop {
+            let op = match self.peek() {
+                Some('+') => Operator::Add,
+                Some('-') => Operator::Subtract,
+                _ => return Ok(node),
+            };
+            self.chars.next();
+            node = Node::Binary(op, Box::new(node), Box::new(self.product()?));
+        }
+    }
+
+    /// Parse a product or quotient of unary terms.
+    fn product(&mut self) -> Result<Node, Error> {
+        let mut node = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some('*') => Operator::Multiply,
+                Some('/') => Operator::Divide,
+                _ => return Ok(node),
+            };
+            self.chars.next();
+            node = Node::Binary(op, Box::new(node), Box::new(self.unary()?));
+        }
+    }
+
+    /// Parse a term which may be negated.
+    fn unary(&mut self) -> Result<Node, Error> {
+        if self.peek() == Some('-') {
+            self.chars.next();
+            return Ok(Node::Negate(Box::new(self.unary()?)));
+        }
+        let base = self.atom()?;
+        if self.peek() == Some('^') {
+            self.chars.next();
+            return Ok(Node::Binary(
+                Operator::Power,
+                Box::new(base),
+                Box::new(self.unary()?),
+            ));
+        }
+        Ok(base)
+    }
+
+    /// Parse a number, a sensor label, a function call, or a parenthesized expression.
+    fn atom(&mut self) -> Result<Node, Error> {
+        match self.peek() {
+            None => Err(Error::UnexpectedEnd),
+            Some('(') => {
+                self.chars.next();
+                let node = self.sum()?;
+                self.expect(')')?;
+                Ok(node)
+            }
+            Some('\'') => {
+                self.chars.next();
+                let mut label = String::new();
+                loop {
+                    match self.chars.next() {
+                        Some((_, '\'')) => return Ok(Node::Sensor(label)),
+                        Some((_, c)) => label.push(c),
+                        None => return Err(Error::UnexpectedEnd),
+                    }
+                }
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => self.number(),
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                let mut name = String::new();
+                while let Some((_, c)) = self
+                    .chars
+                    .next_if(|&(_, c)| c.is_alphanumeric() || c == '_')
+                {
+                    name.push(c);
+                }
+                if self.peek() == Some('(') {
+                    self.call(name)
+                } else {
+                    Ok(Node::Sensor(name))
+                }
+            }
+            Some(c) => Err(Error::Unexpected(c, self.chars.peek().unwrap().0)),
+        }
+    }
+
+    /// Parse a number, such as `12`, `0.5` or `1e-3`.
+    fn number(&mut self) -> Result<Node, Error> {
+        let mut text = String::new();
+        while let Some((_, c)) = self.chars.next_if(|&(_, c)| c.is_ascii_digit() || c == '.') {
+            text.push(c);
+        }
+        if let Some((_, e)) = self.chars.next_if(|&(_, c)| c == 'e' || c == 'E') {
+            text.push(e);
+            if let Some((_, sign)) = self.chars.next_if(|&(_, c)| c == '+' || c == '-') {
+                text.push(sign);
+            }
+            while let Some((_, c)) = self.chars.next_if(|&(_, c)| c.is_ascii_digit()) {
+                text.push(c);
+            }
+        }
+        text.parse()
+            .map(Node::Constant)
+            .map_err(|_| Error::BadNumber(text))
+    }
+
+    /// Parse the arguments of a call to the function named `name`, starting from the opening
+    /// parenthesis.
+    fn call(&mut self, name: String) -> Result<Node, Error> {
+        let Some(function) = Function::named(&name) else {
+            return Err(Error::UnknownFunction(name));
+        };
+        self.expect('(')?;
+        let mut args = vec![self.sum()?];
+        while self.peek() == Some(',') {
+            self.chars.next();
+            args.push(self.sum()?);
+        }
+        self.expect(')')?;
+        if !function.accepts(args.len()) {
+            return Err(Error::WrongArguments(name));
+        }
+
+        Ok(Node::Call(function, args))
+    }
+}
+
+impl TryFrom<String> for Expression {
+    type Error = Error;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        Expression::parse(&source)
+    }
+}
+
+impl From<Expression> for String {
+    fn from(expression: Expression) -> Self {
+        expression.source
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnexpectedEnd => write!(f, "The expression ended unexpectedly"),
+            Error::Unexpected(c, idx) => {
+                write!(
+                    f,
+                    "Unexpected character {c:?} at offset {idx} of the expression"
+                )
+            }
+            Error::BadNumber(text) => write!(f, "{text:?} is not a number"),
+            Error::UnknownFunction(name) => write!(f, "There is no function named {name:?}"),
+            Error::WrongArguments(name) => {
+                write!(
+                    f,
+                    "The function {name:?} was given the wrong number of arguments"
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, convert::Infallible};
+
+    use super::*;
+
+    /// Parse and evaluate `source`, with the sensors `A = 2`, `B = 3` and `PT 2 = 10`.
+    fn eval(source: &str) -> Option<f64> {
+        let values = HashMap::from([("A", 2.0), ("B", 3.0), ("PT 2", 10.0)]);
+        Expression::parse(source)
+            .unwrap()
+            .evaluate(&mut |label| Ok::<_, Infallible>(values.get(label).copied()))
+            .unwrap()
+    }
+
+    #[test]
+    /// Test that operators are applied with the usual precedence.
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Some(7.0));
+        assert_eq!(eval("(1 + 2) * 3"), Some(9.0));
+        assert_eq!(eval("10 - 4 - 3"), Some(3.0));
+        assert_eq!(eval("2 ^ 3 ^ 2"), Some(512.0));
+        assert_eq!(eval("-2 ^ 2"), Some(-4.0));
+        assert_eq!(eval("2 ^ -1"), Some(0.5));
+        assert_eq!(eval("1.5e2 / 3"), Some(50.0));
+    }
+
+    #[test]
+    /// Test that sensors and functions are evaluated, and that a missing sensor makes the whole
+    /// expression missing.
+    fn sensors_and_functions() {
+        assert_eq!(eval("A + B"), Some(5.0));
+        assert_eq!(eval("'PT 2' - A"), Some(8.0));
+        assert_eq!(eval("0.5 * sqrt(abs(A - 'PT 2') * 2)"), Some(2.0));
+        assert_eq!(eval("max(A, B, 1) - min(A, B)"), Some(1.0));
+        assert_eq!(eval("A + C"), None);
+
+        let expression = Expression::parse("max(A, 'PT 2') / A").unwrap();
+        assert_eq!(expression.inputs(), ["A", "PT 2", "A"]);
+    }
+
+    #[test]
+    /// Test that malformed expressions are rejected.
+    fn malformed() {
+        assert_eq!(Expression::parse("A +"), Err(Error::UnexpectedEnd));
+        assert_eq!(Expression::parse("A B"), Err(Error::Unexpected('B', 2)));
+        assert_eq!(Expression::parse("(A"), Err(Error::UnexpectedEnd));
+        assert_eq!(
+            Expression::parse("1.2.3"),
+            Err(Error::BadNumber("1.2.3".into()))
+        );
+        assert_eq!(
+            Expression::parse("log(A)"),
+            Err(Error::UnknownFunction("log".into()))
+        );
+        assert_eq!(
+            Expression::parse("sqrt(A, B)"),
+            Err(Error::WrongArguments("sqrt".into()))
+        );
+    }
+}
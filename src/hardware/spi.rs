@@ -82,7 +82,7 @@ impl<'a, P: GpioPin> Device<'a, P> {
     ///
     /// * `outgoing`: The buffer of bytes which will be sent out to the device.
     /// * `incoming`: The buffer that will be populated with bytes from the
-    ///     device.
+    ///   device.
     ///
     /// # Panics
     ///
@@ -18,28 +18,115 @@
 
 //! Functions for handling incoming messages to the controller from the dashboard.
 
-use serde::Deserialize;
-use std::{fmt::Display, io::Read};
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, fmt::Display, io::BufRead, time::Duration};
+
+use crate::{config::DriverRef, outgoing::Feature};
+
+/// The maximum length, in bytes, of a single message from the dashboard.
+/// Any message longer than this is discarded.
+pub const MAX_MESSAGE_SIZE: usize = 4096;
 
 #[non_exhaustive]
-#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "type")]
 /// A parsed command received from the controller, which is now ready to be executed.
 pub enum Command {
+    /// The dashboard is introducing itself.
+    /// This must be the first message sent by the dashboard.
+    Hello {
+        /// The version of the protocol spoken by the dashboard.
+        version: u32,
+        /// The optional features supported by the dashboard.
+        #[serde(default)]
+        features: Vec<Feature>,
+    },
     /// The dashboard requested that the driver be actuated to a logic level.
     Actuate {
-        /// A string identifying the driver.
-        /// The controller must verify that this string is a real driver.
-        driver_id: u8,
+        /// The driver to be actuated, given either by its ID or by its label.
+        /// The controller must verify that this is a real driver.
+        driver_id: DriverRef,
         /// The logic level that the driver must be actuated to.
         /// `true` corresponds to powered (i.e. connected to 12V), while `false` corresponds to
         /// unpowered (high-Z connection or grounding; hardware-decided).
         value: bool,
     },
-    /// The dashboard requested to begin an ignition procedure immediately.
+    /// The dashboard requested that the driver be actuated to `true` for a given amount of time,
+    /// and then returned to its previous level.
+    Pulse {
+        /// The driver to be pulsed, given either by its ID or by its label.
+        /// The controller must verify that this is a real driver.
+        driver_id: DriverRef,
+        /// The amount of time for which the driver is actuated.
+        duration: Duration,
+    },
+    /// The dashboard requested to arm the controller, so that an ignition may be started.
+    Arm,
+    /// The dashboard requested to disarm the controller without starting an ignition.
+    Disarm,
+    /// The dashboard requested to begin an ignition procedure.
+    /// The controller must already be armed.
+    /// If a countdown is configured, the countdown begins immediately; otherwise, the ignition
+    /// does.
     Ignition,
+    /// The dashboard requested to freeze the countdown, leaving the drivers as they are.
+    Hold,
+    /// The dashboard requested to restart a held countdown from where it was held.
+    Resume,
+    /// The dashboard requested to abandon the countdown and return to standby.
+    Recycle,
     /// The dashboard requested to begin an emergency stop immediately.
     EmergencyStop,
+    /// The dashboard requested to run one of the named auxiliary sequences from the configuration.
+    RunSequence {
+        /// The name of the sequence to be run.
+        name: String,
+    },
+    /// The dashboard requested a dry run of a sequence, to see what it would do without
+    /// performing it.
+    Preview {
+        /// The name of the sequence: `"ignition_sequence"`, `"estop_sequence"`, or the name of one
+        /// of the named auxiliary sequences.
+        sequence: String,
+    },
+    /// The dashboard requested to become the commander.
+    /// Only the commander may issue commands which change the state of the engine.
+    TakeControl,
+    /// The dashboard requested to stop being the commander.
+    ReleaseControl,
+    /// The dashboard is letting the controller know that it is still alive.
+    /// Has no effect other than to keep the connection from timing out.
+    Ping,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// A request from the dashboard, containing a command and an optional identifier.
+///
+/// The identifier is chosen by the dashboard, and is echoed back in every message the controller
+/// sends about the progress of the command.
+pub struct Request {
+    /// The identifier for this request, if the dashboard supplied one.
+    #[serde(default)]
+    pub id: Option<u64>,
+    /// The command to be executed.
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+/// A framing layer which splits a stream of bytes from the dashboard into requests.
+///
+/// Unlike a plain JSON stream parser, a `Framer` can recover from garbage in the stream.
+/// Whenever it finds that the message it is reading cannot possibly be valid (for instance,
+/// because it contains a raw newline inside a string, or because a new object begins where a
+/// comma was expected), it discards everything up to the next `{` and tries again from there.
+/// This means that a single corrupted or truncated message can only destroy itself, and not
+/// the messages after it.
+pub struct Framer<R: BufRead> {
+    /// The source of bytes from the dashboard.
+    src: R,
+    /// Bytes which have already been read from `src`, but which must be scanned again after
+    /// resynchronizing.
+    pending: VecDeque<u8>,
 }
 
 #[non_exhaustive]
@@ -47,9 +134,13 @@ pub enum Command {
 /// The ways in which parsing an incoming command can fail.
 pub enum Error {
     /// The message was malformed or illegal JSON.
-    /// The value inside this variant is the sequence of bytes which contained the malformed
-    /// message.
+    /// The value inside this variant is the sequence of bytes which were discarded.
+    /// Parsing will resume after those bytes.
     Malformed(Vec<u8>),
+    /// The message was longer than `MAX_MESSAGE_SIZE`.
+    /// The value inside this variant is the number of bytes which were discarded.
+    /// Parsing will resume after those bytes.
+    Oversized(usize),
     /// There was an I/O error in parsing the message.
     Io(std::io::Error),
 }
@@ -62,65 +153,157 @@ impl From<std::io::Error> for Error {
     }
 }
 
-impl Command {
-    /// Parse an incoming stream and extract the next command.
-    /// In the `Ok()` case, this will return a pair containing the command and the instant that the
-    /// command was sent.
+impl<R: BufRead> Framer<R> {
+    /// Construct a new `Framer` which reads from `src`.
+    pub fn new(src: R) -> Framer<R> {
+        Framer {
+            src,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Read the next request from the stream.
+    ///
+    /// Whitespace between messages is ignored.
+    /// Anything else which cannot be part of a message is discarded and reported as an error;
+    /// calling this function again will continue reading after the discarded bytes.
     ///
     /// # Errors
     ///
-    /// This function will return an `Err` in the cases described in `ParseError`.
+    /// This function will return an `Err` in the cases described in `Error`.
+    pub fn next_request(&mut self) -> Result<Request, Error> {
+        // bytes before the start of the next message which are not whitespace
+        let mut garbage = Vec::new();
+        loop {
+            let c = self.next_byte()?;
+            if c == b'{' {
+                if !garbage.is_empty() {
+                    // report the garbage now, and read the message on the next call
+                    self.pending.push_front(c);
+                    return Err(Error::Malformed(garbage));
+                }
+                return self.read_object();
+            }
+            if !c.is_ascii_whitespace() {
+                garbage.push(c);
+                if garbage.len() >= MAX_MESSAGE_SIZE {
+                    return Err(Error::Malformed(garbage));
+                }
+            }
+        }
+    }
+
+    /// Read the remainder of a message whose opening `{` has just been read, and parse it.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// This function will only panic in case of an internal logic error.
-    pub fn parse(src: &mut dyn Read) -> Result<Command, Error> {
-        let mut buffer = Vec::new();
-        let mut bytes = src.bytes();
-        let mut depth = 0;
+    /// This function will return an `Err` if the message is malformed, too long, or fails to be
+    /// parsed as a request, or if reading from the stream fails.
+    fn read_object(&mut self) -> Result<Request, Error> {
+        let mut buffer = vec![b'{'];
+        let mut depth = 1u32;
         // whether we are inside of a string literal
         let mut in_string = false;
-        // whether the previous character was the escape character `\`
+        // whether the previous character was an unescaped escape character `\`
         let mut escaped = false;
+        // the most recent byte outside of a string which was not whitespace
+        let mut last = b'{';
+
         loop {
-            let c = bytes.next().ok_or_else(|| {
-                std::io::Error::new(
-                    std::io::ErrorKind::UnexpectedEof,
-                    "require more characters to fill out JSON body",
-                )
-            })??;
+            let c = self.next_byte()?;
             buffer.push(c);
-            match c {
-                b'{' => {
-                    if !in_string {
-                        depth += 1;
-                    }
+            if buffer.len() > MAX_MESSAGE_SIZE {
+                let discarded = self.resync(buffer);
+                return Err(Error::Oversized(discarded.len()));
+            }
+
+            if in_string {
+                if escaped {
+                    escaped = false;
+                } else if c == b'\\' {
+                    escaped = true;
+                } else if c == b'"' {
+                    in_string = false;
+                    last = c;
+                } else if c < 0x20 {
+                    // control characters may not appear raw inside a string, so this is most
+                    // likely the start of a new message after a truncated one
+                    return Err(Error::Malformed(self.resync(buffer)));
+                }
+                continue;
+            }
+
+            if c.is_ascii_whitespace() {
+                continue;
+            }
+
+            let legal = match c {
+                b'{' | b'[' => matches!(last, b':' | b',' | b'['),
+                b'"' => matches!(last, b'{' | b'[' | b':' | b','),
+                b'}' | b']' | b',' | b':' => !matches!(last, b':' | b','),
+                b'-' | b'+' | b'.' | b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' => {
+                    !matches!(last, b'"' | b'}' | b']' | b'{')
                 }
+                _ => false,
+            };
+            if !legal {
+                return Err(Error::Malformed(self.resync(buffer)));
+            }
+            last = c;
+
+            match c {
+                b'{' => depth += 1,
                 b'}' => {
-                    if !in_string {
-                        if depth == 0 {
-                            // prevent underflow in the case of a message starting with closing
-                            // brace
-                            return Err(Error::Malformed(buffer));
-                        }
-                        depth -= 1;
-                        // check if this is the end of the outermost object
-                        if depth == 0 {
-                            break;
-                        }
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
                     }
                 }
-                // if we encounter an unescaped quote, toggle whether we are in a string
-                b'"' => in_string ^= !escaped,
+                b'"' => in_string = true,
                 _ => (),
-            };
-            escaped = c == b'\\' && !escaped;
+            }
         }
 
-        let result = serde_json::from_slice(&buffer);
-        let cmd = result.map_err(|_| Error::Malformed(buffer))?;
+        match serde_json::from_slice(&buffer) {
+            Ok(request) => Ok(request),
+            Err(_) => Err(Error::Malformed(self.resync(buffer))),
+        }
+    }
 
-        Ok(cmd)
+    /// Give up on the message contained in `buffer`.
+    /// Everything in `buffer` from the first `{` after its start will be scanned again, in case a
+    /// valid message begins there.
+    ///
+    /// Returns the bytes which were discarded.
+    fn resync(&mut self, mut buffer: Vec<u8>) -> Vec<u8> {
+        if let Some(next_start) = buffer.iter().skip(1).position(|&c| c == b'{') {
+            for &c in buffer[next_start + 1..].iter().rev() {
+                self.pending.push_front(c);
+            }
+            buffer.truncate(next_start + 1);
+        }
+        buffer
+    }
+
+    /// Get the next byte, either from the bytes which must be scanned again or from the stream.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an `Err` if reading from the stream fails, or if the stream has
+    /// ended.
+    fn next_byte(&mut self) -> Result<u8, Error> {
+        if let Some(c) = self.pending.pop_front() {
+            return Ok(c);
+        }
+
+        let c = *self.src.fill_buf()?.first().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "require more characters to fill out JSON body",
+            )
+        })?;
+        self.src.consume(1);
+        Ok(c)
     }
 }
 
@@ -128,8 +311,23 @@ impl Display for Command {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Command::Actuate { driver_id, value } => write!(f, "actuate {driver_id} {value}"),
+            Command::Pulse {
+                driver_id,
+                duration,
+            } => write!(f, "pulse {driver_id} {}", duration.as_millis()),
+            Command::Arm => write!(f, "arm"),
+            Command::Disarm => write!(f, "disarm"),
             Command::Ignition => write!(f, "ignition"),
+            Command::Hold => write!(f, "hold"),
+            Command::Resume => write!(f, "resume"),
+            Command::Recycle => write!(f, "recycle"),
             Command::EmergencyStop => write!(f, "estop"),
+            Command::RunSequence { name } => write!(f, "run sequence {name}"),
+            Command::Preview { sequence } => write!(f, "preview {sequence}"),
+            Command::TakeControl => write!(f, "take control"),
+            Command::ReleaseControl => write!(f, "release control"),
+            Command::Ping => write!(f, "ping"),
+            Command::Hello { version, .. } => write!(f, "hello {version}"),
         }
     }
 }
@@ -140,11 +338,25 @@ mod tests {
     use std::io::Cursor;
 
     /// Helper function to construct cursors and save some boilerplate on other tests.
-    /// Creates a cursor of `message` and uses it to call `Command::parse`.
-    /// Ignores the extracted time from the parser.
+    /// Creates a framer reading from `message` and uses it to read one request.
+    /// Ignores the ID of the request.
     fn parse_helper(message: &str) -> Result<Command, Error> {
-        let mut cursor = Cursor::new(message);
-        Command::parse(&mut cursor)
+        Framer::new(Cursor::new(message))
+            .next_request()
+            .map(|request| request.command)
+    }
+
+    /// Read every request from `stream` until it ends, skipping over any errors.
+    fn parse_all(stream: &[u8]) -> Vec<Command> {
+        let mut framer = Framer::new(Cursor::new(stream));
+        let mut commands = Vec::new();
+        loop {
+            match framer.next_request() {
+                Ok(request) => commands.push(request.command),
+                Err(Error::Io(_)) => return commands,
+                Err(_) => (),
+            }
+        }
     }
 
     #[test]
@@ -154,7 +366,9 @@ mod tests {
             "type": "GARBAGE"
         }"#;
 
-        let Err(Error::Malformed(s)) = parse_helper(message) else {panic!()};
+        let Err(Error::Malformed(s)) = parse_helper(message) else {
+            panic!()
+        };
         let slice: &[u8] = message.as_ref();
 
         assert_eq!(&s, slice);
@@ -171,7 +385,7 @@ mod tests {
         assert_eq!(
             parse_helper(message).unwrap(),
             Command::Actuate {
-                driver_id: 0,
+                driver_id: DriverRef::Id(0),
                 value: true
             }
         );
@@ -194,4 +408,270 @@ mod tests {
         }"#;
         assert_eq!(parse_helper(message).unwrap(), Command::EmergencyStop);
     }
+
+    #[test]
+    /// Test that a pulse command is parsed correctly.
+    fn pulse() {
+        let message = r#"{
+            "type": "Pulse",
+            "driver_id": 2,
+            "duration": {
+                "secs": 0,
+                "nanos": 250000000
+            }
+        }"#;
+        assert_eq!(
+            parse_helper(message).unwrap(),
+            Command::Pulse {
+                driver_id: DriverRef::Id(2),
+                duration: Duration::from_millis(250)
+            }
+        );
+    }
+
+    #[test]
+    /// Test that a command to run a named sequence is parsed correctly.
+    fn run_sequence() {
+        let message = r#"{
+            "type": "RunSequence",
+            "name": "purge"
+        }"#;
+        assert_eq!(
+            parse_helper(message).unwrap(),
+            Command::RunSequence {
+                name: String::from("purge")
+            }
+        );
+    }
+
+    #[test]
+    /// Test that a command to preview a sequence is parsed correctly.
+    fn preview() {
+        let message = r#"{
+            "type": "Preview",
+            "sequence": "ignition_sequence"
+        }"#;
+        assert_eq!(
+            parse_helper(message).unwrap(),
+            Command::Preview {
+                sequence: String::from("ignition_sequence")
+            }
+        );
+    }
+
+    #[test]
+    /// Test that the arming commands are parsed correctly.
+    fn arming() {
+        let stream = br#"{"type": "Arm"} {"type": "Disarm"}"#;
+        assert_eq!(parse_all(stream), [Command::Arm, Command::Disarm]);
+    }
+
+    #[test]
+    /// Test that the countdown commands are parsed correctly.
+    fn countdown() {
+        let stream = br#"{"type": "Hold"}{"type": "Resume"}{"type": "Recycle"}"#;
+        assert_eq!(
+            parse_all(stream),
+            [Command::Hold, Command::Resume, Command::Recycle]
+        );
+    }
+
+    #[test]
+    /// Test that the commands for taking and releasing control are parsed correctly.
+    fn control() {
+        let message = r#"{
+            "type": "TakeControl"
+        }"#;
+        assert_eq!(parse_helper(message).unwrap(), Command::TakeControl);
+
+        let message = r#"{
+            "type": "ReleaseControl"
+        }"#;
+        assert_eq!(parse_helper(message).unwrap(), Command::ReleaseControl);
+    }
+
+    #[test]
+    /// Test that a ping command is parsed correctly.
+    fn ping() {
+        let message = r#"{
+            "type": "Ping"
+        }"#;
+        assert_eq!(parse_helper(message).unwrap(), Command::Ping);
+    }
+
+    #[test]
+    /// Test that a request ID is parsed if it is given.
+    fn request_id() {
+        let message = r#"{
+            "type": "Actuate",
+            "id": 1234,
+            "driver_id": 0,
+            "value": false
+        }"#;
+        assert_eq!(
+            Framer::new(Cursor::new(message)).next_request().unwrap(),
+            Request {
+                id: Some(1234),
+                command: Command::Actuate {
+                    driver_id: DriverRef::Id(0),
+                    value: false
+                }
+            }
+        );
+    }
+
+    #[test]
+    /// Test that a request ID is optional.
+    fn no_request_id() {
+        let message = r#"{
+            "type": "Ignition"
+        }"#;
+        assert_eq!(
+            Framer::new(Cursor::new(message)).next_request().unwrap(),
+            Request {
+                id: None,
+                command: Command::Ignition
+            }
+        );
+    }
+
+    #[test]
+    /// Test that a hello command is parsed correctly, even if it has features we don't know about.
+    fn hello() {
+        let message = r#"{
+            "type": "Hello",
+            "version": 1,
+            "features": ["Logs", "TimeTravel"]
+        }"#;
+        assert_eq!(
+            parse_helper(message).unwrap(),
+            Command::Hello {
+                version: 1,
+                features: vec![Feature::Logs, Feature::Unknown]
+            }
+        );
+    }
+
+    #[test]
+    /// Test that garbage between messages is discarded without destroying the messages around it.
+    fn garbage_between() {
+        let stream = br#"{"type": "Ping"} }}] garbage {"type": "EmergencyStop"}"#;
+        assert_eq!(
+            parse_all(stream),
+            vec![Command::Ping, Command::EmergencyStop]
+        );
+    }
+
+    #[test]
+    /// Test that a truncated message does not swallow the message after it.
+    fn truncated() {
+        for stream in [
+            // cut off in the middle of a string
+            "{\"type\": \"Actu\n{\"type\": \"EmergencyStop\"}".as_bytes(),
+            // cut off after a key
+            br#"{"type": "Actuate", "driver_id" {"type": "EmergencyStop"}"#,
+            // cut off after a value
+            br#"{"type": "Actuate", "driver_id": 3 {"type": "EmergencyStop"}"#,
+            // cut off before a nested value, so the next message looks like part of this one
+            br#"{"type": "Actuate", "driver_id": {"type": "EmergencyStop"} {"type": "Ping"}"#,
+        ] {
+            let commands = parse_all(stream);
+            assert_eq!(
+                commands[0],
+                Command::EmergencyStop,
+                "{}",
+                String::from_utf8_lossy(stream)
+            );
+        }
+    }
+
+    #[test]
+    /// Test that braces, quotes, escapes and multi-byte characters inside strings do not confuse
+    /// the framer.
+    fn string_edge_cases() {
+        let stream = r#"{"type": "Ping", "note": "}{ \" \\ ünïcødé 🚀"}{"type": "Ignition"}"#;
+        assert_eq!(
+            parse_all(stream.as_bytes()),
+            vec![Command::Ping, Command::Ignition]
+        );
+    }
+
+    #[test]
+    /// Test that an overly long message is discarded.
+    fn oversized() {
+        let mut stream = br#"{"type": "Ping", "padding": ""#.to_vec();
+        stream.extend(std::iter::repeat(b'a').take(MAX_MESSAGE_SIZE));
+        stream.extend(br#""}{"type": "EmergencyStop"}"#);
+
+        let mut framer = Framer::new(Cursor::new(stream));
+        assert!(matches!(framer.next_request(), Err(Error::Oversized(_))));
+        assert_eq!(parse_all_from(&mut framer), vec![Command::EmergencyStop]);
+    }
+
+    /// Read every request remaining in `framer` until it ends, skipping over any errors.
+    fn parse_all_from(framer: &mut Framer<impl BufRead>) -> Vec<Command> {
+        let mut commands = Vec::new();
+        loop {
+            match framer.next_request() {
+                Ok(request) => commands.push(request.command),
+                Err(Error::Io(_)) => return commands,
+                Err(_) => (),
+            }
+        }
+    }
+
+    #[test]
+    /// Test that, no matter how a stream of messages is corrupted, an emergency stop sent after
+    /// the corruption is always received.
+    fn fuzz_corpus() {
+        let valid = br#"{"type": "Actuate", "id": 4, "driver_id": 0, "value": true}
+{"type": "Hello", "version": 1, "features": ["Logs", "x\"y"]}
+{
+    "type": "Ignition"
+}
+{"type": "Ping"}
+"#;
+        // a simple linear congruential generator, so that the corpus is the same every run
+        let mut seed = 0x2707_u64;
+        let mut random = move |bound: usize| {
+            seed = seed
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            #[allow(clippy::cast_possible_truncation)]
+            let r = (seed >> 33) as usize;
+            r % bound
+        };
+
+        for _ in 0..2000 {
+            let mut stream = valid.to_vec();
+            for _ in 0..=random(4) {
+                if stream.is_empty() {
+                    break;
+                }
+                let idx = random(stream.len());
+                match random(4) {
+                    // flip a byte
+                    0 => stream[idx] = u8::try_from(random(256)).unwrap(),
+                    // delete a byte
+                    1 => {
+                        stream.remove(idx);
+                    }
+                    // insert a structural byte
+                    2 => {
+                        let structural = b"{}[]\":,\\\n";
+                        stream.insert(idx, structural[random(structural.len())]);
+                    }
+                    // truncate the stream
+                    _ => stream.truncate(idx),
+                }
+            }
+            stream.extend(b"\n{\"type\": \"EmergencyStop\"}\n{\"type\": \"Ping\"}\n");
+
+            assert!(
+                parse_all(&stream).contains(&Command::EmergencyStop),
+                "{}",
+                String::from_utf8_lossy(&stream)
+            );
+        }
+    }
 }
@@ -20,14 +20,18 @@
 
 use std::sync::PoisonError;
 
+mod calibration;
 mod config;
 mod console;
 mod data;
+mod deadman;
 mod execution;
+mod expression;
 pub mod hardware;
 mod heartbeat;
 mod incoming;
 mod outgoing;
+mod preview;
 pub mod server;
 pub mod state;
 
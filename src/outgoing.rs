@@ -20,23 +20,91 @@
 //! to dashboard.
 
 use std::{
+    collections::VecDeque,
+    fmt::Display,
     io::Write,
-    sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
-    time::SystemTime,
+    mem::take,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::{Duration, SystemTime},
 };
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Configuration,
+    console::{LogLevel, UserLog},
+    data::AlarmLevel,
+    execution,
+    incoming::Command,
+    preview::Timeline,
+    state::{Guard, State},
+    ControllerError,
+};
+
+/// The version of the protocol spoken by this controller.
+/// A dashboard must speak exactly this version to be allowed to connect.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The optional features of the protocol which this controller supports.
+pub const FEATURES: &[Feature] = &[Feature::Logs];
 
-use crate::config::Configuration;
+/// The maximum number of messages which may be waiting to be written to a dashboard before sensor
+/// values start being dropped.
+/// Other messages are still queued once the outbox is this long, up to `QUEUE_LIMIT`.
+const QUEUE_CAPACITY: usize = 256;
+
+/// The maximum number of messages which may be waiting to be written to a dashboard at all.
+/// A dashboard whose outbox is this long is hopelessly behind, and is disconnected rather than
+/// having messages which may not be dropped withheld from it.
+const QUEUE_LIMIT: usize = 4096;
+
+/// The period at which the writer thread checks whether the controller is quitting.
+const WRITE_POLL_PERIOD: Duration = Duration::from_millis(100);
+
+/// The minimum amount of time between reports to the user that messages have been dropped.
+const DROP_REPORT_PERIOD: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// An optional feature of the protocol.
+/// Messages which belong to a feature are only sent if both the controller and the dashboard
+/// declared support for that feature during the handshake.
+pub enum Feature {
+    /// Forwarding of the user log to the dashboard as `Log` messages.
+    Logs,
+    /// A feature which this controller does not know about.
+    /// Dashboards may declare support for features from newer versions of the controller; those
+    /// features are simply never used.
+    #[serde(other)]
+    Unknown,
+}
 
 #[derive(Serialize)]
 #[serde(tag = "type")]
 /// The set of messages which can be sent from the controller to the dashboard.
 pub enum Message<'a> {
+    /// A greeting message.
+    /// Sent as soon as the dashboard connects, before anything else.
+    Hello {
+        /// The version of the protocol spoken by the controller.
+        version: u32,
+        /// The optional features supported by the controller.
+        features: &'a [Feature],
+    },
+    /// A refusal message.
+    /// Sent just before the controller closes the connection to a dashboard which it cannot talk to.
+    Refused {
+        /// A human-readable description of why the dashboard was refused.
+        reason: &'a str,
+    },
     /// A configuration message.
     Config {
         /// A reference to the entire configuration object for this controller.
         config: &'a Configuration,
+        /// The state of the controller at the time that the dashboard connected.
+        state: State,
     },
     /// A sensor valuation message.
     /// Each key in the map corresponds to a sensor.
@@ -48,6 +116,19 @@ pub enum Message<'a> {
         /// The readings which were created.
         readings: &'a [SensorReading],
     },
+    /// A sensor alarm message.
+    /// Sent every time a sensor's alarm level changes, and to each dashboard when it connects for
+    /// every sensor which is not nominal.
+    Alarm {
+        /// The group containing the sensor.
+        group_id: u8,
+        /// The ID of the sensor within its group.
+        sensor_id: u8,
+        /// The new alarm level of the sensor.
+        level: AlarmLevel,
+        /// The filtered value of the sensor which caused the change.
+        value: f64,
+    },
     /// A driver values message.
     /// Describes the logic levels of the drivers on the controller.
     DriverValue {
@@ -56,6 +137,78 @@ pub enum Message<'a> {
         /// original configuration object.
         values: &'a [bool],
     },
+    /// A state change message.
+    /// Sent every time the controller moves from one state to another.
+    State {
+        /// The state that the controller was in before the transition.
+        from: State,
+        /// The state that the controller is in after the transition.
+        to: State,
+        /// The time at which the transition occurred.
+        time: SystemTime,
+    },
+    /// A countdown clock message.
+    /// Sent to every dashboard each time the countdown passes a whole second, and whenever it is
+    /// held or resumed.
+    Countdown {
+        /// The time remaining in the countdown, in milliseconds.
+        t_minus: u64,
+        /// Whether the countdown is currently held.
+        held: bool,
+    },
+    /// A go/no-go report.
+    /// Sent to every dashboard each time the go/no-go checks are evaluated before an ignition.
+    GoNoGo {
+        /// The outcome of each check, in the order that they are given in the configuration.
+        results: &'a [execution::CheckResult<'a>],
+    },
+    /// The timeline of a sequence, worked out by a dry run.
+    /// Sent only to the dashboard which asked for the preview.
+    Timeline {
+        /// The name of the sequence.
+        sequence: &'a str,
+        /// The timeline of the sequence.
+        #[serde(flatten)]
+        timeline: &'a Timeline<'a>,
+    },
+    /// A user log message.
+    /// Sent for every entry in the user log which is at or above the configured level.
+    Log {
+        /// The level of the entry.
+        level: LogLevel,
+        /// The time at which the entry was logged.
+        time: SystemTime,
+        /// The text of the entry.
+        message: &'a str,
+    },
+    /// A command acknowledgement message.
+    /// Sent when a command from the dashboard has been accepted and is about to be executed.
+    Ack {
+        /// The ID of the request containing the command, if one was given.
+        id: Option<u64>,
+        /// The command which was accepted.
+        command: &'a Command,
+    },
+    /// A negative command acknowledgement message.
+    /// Sent when a command from the dashboard was rejected and will not be executed.
+    Nack {
+        /// The ID of the request containing the command, if one was given.
+        id: Option<u64>,
+        /// The command which was rejected.
+        command: &'a Command,
+        /// The reason that the command was rejected.
+        reason: &'a execution::Error,
+    },
+    /// A command completion message.
+    /// Sent when a command which was previously acknowledged has finished executing.
+    Done {
+        /// The ID of the request containing the command, if one was given.
+        id: Option<u64>,
+        /// The command which finished.
+        command: &'a Command,
+        /// The error which stopped the command from completing, if there was one.
+        error: Option<&'a execution::Error>,
+    },
 }
 
 #[derive(Serialize)]
@@ -63,26 +216,116 @@ pub enum Message<'a> {
 pub struct SensorReading {
     /// The ID of the sensor withing the group that created this reading.
     pub sensor_id: u8,
-    /// The value read on the sensor.
-    pub reading: u16,
+    /// The raw value read on the sensor, or `None` if the reading is of a derived channel.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reading: Option<u16>,
+    /// The calibrated value of the reading.
+    pub value: f64,
     /// The time at which the sensor reading was created.
     pub time: SystemTime,
 }
 
-/// A channel which can write to the dashboard.
-/// It contains a writer for a channel to the dashboard and to a message log.
+/// A channel which can write to every connected dashboard.
+/// It contains an outbox for each dashboard and a message log.
+///
+/// Any number of dashboards may be connected at once, and all of them receive telemetry.
+/// At most one of them is the commander, which is the only dashboard allowed to issue commands
+/// which change the state of the engine.
+///
+/// Sending a message only places it in the outbox of each dashboard it is for, so that a slow
+/// dashboard can never stall the thread which sent it.
+/// The messages in each outbox are actually written by `dash_write`, which should run in its own
+/// thread for each dashboard, so that a slow dashboard can only delay itself.
+/// If an outbox backs up, stale `SensorValue` messages are replaced by newer ones or dropped, but
+/// every other message is always delivered.
+/// A dashboard which falls hopelessly far behind is disconnected instead.
 ///
 /// # Types
 ///
-/// * `C`: the type of the channel to the dashboard.
+/// * `C`: the type of the channel to each dashboard.
 /// * `M`: the type of the log file to be written to.
 pub struct DashChannel<C: Write, M: Write> {
-    /// A channel for the dashboard.
-    /// If writing to this channel fails, it will be immediately overwritten with `None`.
-    /// When `dash_channel` is `None`, nothing will be written.
-    pub dash_channel: Arc<RwLock<Option<C>>>,
+    /// The dashboards which are currently connected.
+    clients: Mutex<Vec<Arc<Client<C>>>>,
+    /// The number of dashboards in `clients`.
+    /// This is kept separately so that it can be checked without taking the lock on `clients`.
+    client_count: AtomicUsize,
+    /// The total number of messages which have ever been sent to at least one dashboard.
+    queued: AtomicU64,
+    /// The total number of messages which were dropped or replaced before being written.
+    dropped: AtomicU64,
     /// The log file for all messages that are sent.
     message_log: Mutex<M>,
+    /// The dashboard which currently holds the commander role, if any.
+    commander: Mutex<Option<ClientId>>,
+    /// The ID to be given to the next dashboard which connects.
+    next_id: AtomicU32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// An identifier for a single dashboard connection.
+/// No two connections made during the lifetime of a controller will have the same ID.
+pub struct ClientId(u32);
+
+/// A handle through which `dash_write` writes to a single dashboard.
+/// It stays usable even after the dashboard has been removed, so that the messages sent to the
+/// dashboard before then can still be written.
+pub struct Writer<C: Write>(Arc<Client<C>>);
+
+/// A single connected dashboard.
+struct Client<C: Write> {
+    /// The identifier for this dashboard.
+    id: ClientId,
+    /// The channel on which messages can be sent to this dashboard.
+    channel: Mutex<C>,
+    /// The optional features which were agreed upon with this dashboard, or `None` if its
+    /// handshake has not yet succeeded.
+    features: Mutex<Option<Vec<Feature>>>,
+    /// The messages which have been sent to this dashboard, but not yet written.
+    outbox: Mutex<Outbox>,
+    /// A condition variable which is notified whenever `outbox` changes.
+    outbox_changed: Condvar,
+    /// The number of messages for this dashboard which were dropped or replaced before being
+    /// written.
+    dropped: AtomicU64,
+}
+
+#[derive(Default)]
+/// The messages waiting to be written to a single dashboard.
+struct Outbox {
+    /// The messages, in the order they were sent.
+    messages: VecDeque<Queued>,
+    /// The reason that no more messages will be added, or `None` if more may still arrive.
+    ended: Option<End>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The reason that no more messages will be written to a dashboard.
+enum End {
+    /// The dashboard was removed from the channel.
+    Removed,
+    /// The dashboard fell so far behind that its outbox could not hold a message which may not be
+    /// dropped.
+    Overflowed,
+}
+
+/// A message which has been sent, but not yet written to a dashboard.
+struct Queued {
+    /// If this is a `SensorValue` message, the group which generated it.
+    /// Only such messages may be dropped.
+    group_id: Option<u8>,
+    /// The serialized message, which is shared by every dashboard it was sent to.
+    serialized: Arc<[u8]>,
+}
+
+/// The outcome of writing the messages in a dashboard's outbox.
+enum Written {
+    /// Every message was written, and more may follow.
+    Open,
+    /// Every message was written, and no more will follow.
+    Ended(End),
+    /// Writing to the dashboard failed.
+    Failed(std::io::Error),
 }
 
 #[derive(Debug)]
@@ -95,89 +338,532 @@ pub enum Error {
 }
 
 impl<C: Write, M: Write> DashChannel<C, M> {
-    /// Construct a new `DashChannel` with no outgoing channel.
+    /// Construct a new `DashChannel` with no dashboards connected.
     pub fn new(message_log: M) -> DashChannel<C, M> {
         DashChannel {
-            dash_channel: Arc::new(RwLock::new(None)),
+            clients: Mutex::new(Vec::new()),
+            client_count: AtomicUsize::new(0),
+            queued: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
             message_log: Mutex::new(message_log),
+            commander: Mutex::new(None),
+            next_id: AtomicU32::new(0),
         }
     }
 
-    /// Write a message to the dashboard.
-    /// After writing the message, log that the message was written.
+    /// Queue a message to be written to every connected dashboard whose handshake has succeeded.
+    ///
+    /// Messages which belong to an optional feature will only be written to the dashboards which
+    /// agreed upon that feature.
     ///
-    /// If writing the message to the dashboard
-    ///     
     /// # Errors
     ///
-    /// This function will return an `Err` if we are unable to write to the message log.
+    /// This function may return an `Err` if we are unable to write to the message log or if an
+    /// internal lock is poisoned.
     ///
     /// # Panics
     ///
     /// This function will panic if the current time is before the UNIX epoch.
     pub fn send(&self, message: &Message) -> Result<(), Error> {
-        let mut channel_guard = self.dash_channel.write().map_err(|_| Error::Poison)?;
-        let mut message_log_guard = self.message_log.lock().map_err(|_| Error::Poison)?;
-        if let Some(ref mut writer) = *channel_guard {
-            match serde_json::to_writer(&mut *writer, message) {
-                Ok(()) => {
-                    // log that we sent this message to the dashboard
-                    // first, mark the time
-                    write!(
-                        message_log_guard,
-                        "{},",
-                        SystemTime::now()
-                            .duration_since(SystemTime::UNIX_EPOCH)
-                            .unwrap()
-                            .as_nanos()
-                    )
-                    .map_err(Error::LogFile)?;
-                    // then, the message
-                    serde_json::to_writer(&mut *message_log_guard, message)
-                        .map_err(|e| Error::LogFile(std::io::Error::from(e)))?;
-                    // then a trailing newline
-                    writeln!(message_log_guard).map_err(Error::LogFile)?;
-                }
-                Err(e) => {
-                    // we should expect that the only thing preventing us from interfacing with the
-                    // dashboard is I/O.
-                    // Anything else is a sign of a critical logic error.
-                    assert_eq!(e.classify(), serde_json::error::Category::Io);
-                    // the connection was closed
-                    *channel_guard = None;
+        self.enqueue(message, None)
+    }
+
+    /// Queue a message to be written to only the dashboard identified by `client`.
+    ///
+    /// If the dashboard is no longer connected, the message will be discarded.
+    ///
+    /// # Errors
+    ///
+    /// This function may return an `Err` if we are unable to write to the message log or if an
+    /// internal lock is poisoned.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the current time is before the UNIX epoch.
+    pub fn send_to(&self, client: ClientId, message: &Message) -> Result<(), Error> {
+        self.enqueue(message, Some(client))
+    }
+
+    /// Add a message to the outbox of the dashboard identified by `recipient`, or of every
+    /// dashboard if `recipient` is `None`, and log that it was sent.
+    ///
+    /// # Errors
+    ///
+    /// This function may return an `Err` if we are unable to write to the message log or if an
+    /// internal lock is poisoned.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the current time is before the UNIX epoch.
+    fn enqueue(&self, message: &Message, recipient: Option<ClientId>) -> Result<(), Error> {
+        if self.client_count.load(Ordering::Acquire) == 0 {
+            return Ok(());
+        }
+
+        let group_id = match message {
+            Message::SensorValue { group_id, .. } => Some(*group_id),
+            _ => None,
+        };
+        let feature = message.feature();
+        // Anything other than an I/O error here is a sign of a critical logic error, and
+        // serializing to a buffer cannot cause an I/O error.
+        let serialized: Arc<[u8]> = serde_json::to_vec(message).unwrap().into();
+
+        let mut sent = false;
+        {
+            // hold onto the clients while delivering, so that concurrent sends cannot reach
+            // different dashboards in different orders
+            let clients_guard = self.clients.lock().map_err(|_| Error::Poison)?;
+            for client in clients_guard
+                .iter()
+                .filter(|client| recipient.map_or(true, |id| id == client.id))
+            {
+                match (
+                    &*client.features.lock().map_err(|_| Error::Poison)?,
+                    feature,
+                ) {
+                    // broadcasts only reach dashboards which have completed their handshake
+                    (None, _) if recipient.is_none() => continue,
+                    (None, Some(_)) => continue,
+                    (Some(features), Some(feature)) if !features.contains(&feature) => continue,
+                    _ => (),
                 }
+                self.deliver(
+                    client,
+                    Queued {
+                        group_id,
+                        serialized: Arc::clone(&serialized),
+                    },
+                )?;
+                sent = true;
+            }
+        }
+        if !sent {
+            return Ok(());
+        }
+        self.queued.fetch_add(1, Ordering::Relaxed);
+
+        // log that we sent this message to the dashboard
+        let mut message_log_guard = self.message_log.lock().map_err(|_| Error::Poison)?;
+        // first, mark the time
+        write!(
+            message_log_guard,
+            "{},",
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        )
+        .map_err(Error::LogFile)?;
+        // then, the message
+        message_log_guard
+            .write_all(&serialized)
+            .map_err(Error::LogFile)?;
+        // then a trailing newline
+        writeln!(message_log_guard).map_err(Error::LogFile)?;
+
+        Ok(())
+    }
+
+    /// Add `queued` to the outbox of `client`.
+    ///
+    /// A `SensorValue` message replaces any unwritten `SensorValue` message from the same group,
+    /// and is dropped if the outbox is full.
+    /// Any other message makes room for itself in a full outbox by dropping the oldest
+    /// `SensorValue` message.
+    /// If there is none to drop and the outbox has reached `QUEUE_LIMIT`, the outbox is ended so
+    /// that the dashboard will be disconnected.
+    ///
+    /// # Errors
+    ///
+    /// This function may return an `Err` if an internal lock is poisoned.
+    fn deliver(&self, client: &Client<C>, queued: Queued) -> Result<(), Error> {
+        let mut outbox_guard = client.outbox.lock().map_err(|_| Error::Poison)?;
+        if outbox_guard.ended.is_some() {
+            return Ok(());
+        }
+
+        let messages = &mut outbox_guard.messages;
+        if queued.group_id.is_some() {
+            if let Some(stale) = messages.iter_mut().find(|q| q.group_id == queued.group_id) {
+                // the old readings are out of date, so only the newest ones need to be written
+                *stale = queued;
+                self.count_drop(client);
+            } else if messages.len() >= QUEUE_CAPACITY {
+                self.count_drop(client);
+            } else {
+                messages.push_back(queued);
             }
+        } else if messages.len() < QUEUE_CAPACITY {
+            messages.push_back(queued);
+        } else if let Some(idx) = messages.iter().position(|q| q.group_id.is_some()) {
+            messages.remove(idx);
+            self.count_drop(client);
+            messages.push_back(queued);
+        } else if messages.len() < QUEUE_LIMIT {
+            messages.push_back(queued);
+        } else {
+            outbox_guard.ended = Some(End::Overflowed);
         }
+        drop(outbox_guard);
+        client.outbox_changed.notify_one();
 
         Ok(())
     }
 
-    /// Determine whether this channel actually has a target to send messages to.
+    /// Record that a message for `client` was dropped or replaced.
+    fn count_drop(&self, client: &Client<C>) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        client.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Get the total number of messages which have ever been queued to be written.
+    pub fn queued(&self) -> u64 {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Get the total number of `SensorValue` messages which were dropped, or replaced by newer
+    /// ones, before they could be written.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Determine whether this channel actually has any dashboards to send messages to.
+    pub fn has_target(&self) -> bool {
+        self.client_count.load(Ordering::Acquire) > 0
+    }
+
+    /// Add a newly-connected dashboard, which can be written to via `channel`.
+    /// If `channel` can block, it should time out, since a dashboard which stops reading will
+    /// otherwise never be noticed.
+    /// The new dashboard will only receive messages sent directly to it, with no optional features
+    /// enabled, until `set_features` is called.
+    ///
+    /// Returns the ID of the new dashboard.
+    /// Nothing will be written to the dashboard until `dash_write` is run with the handle returned
+    /// by `writer`.
+    ///
+    /// # Errors
+    ///
+    /// This function may return an `Err` if an internal lock is poisoned.
+    pub fn add_client(&self, channel: C) -> Result<ClientId, Error> {
+        let id = ClientId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let mut clients_guard = self.clients.lock().map_err(|_| Error::Poison)?;
+        clients_guard.push(Arc::new(Client {
+            id,
+            channel: Mutex::new(channel),
+            features: Mutex::new(None),
+            outbox: Mutex::new(Outbox::default()),
+            outbox_changed: Condvar::new(),
+            dropped: AtomicU64::new(0),
+        }));
+        self.client_count
+            .store(clients_guard.len(), Ordering::Release);
+        Ok(id)
+    }
+
+    /// Forget about the dashboard identified by `client`.
+    /// Any messages already sent to it will still be written by `dash_write`, so that it is told
+    /// why it was disconnected, but no more will be.
+    /// If that dashboard was the commander, there will no longer be a commander.
+    ///
+    /// Returns `true` if the dashboard was the commander.
+    ///
+    /// # Errors
+    ///
+    /// This function may return an `Err` if an internal lock is poisoned.
+    pub fn remove_client(&self, client: ClientId) -> Result<bool, Error> {
+        if let Some(removed) = self.forget(client)? {
+            let mut outbox_guard = removed.outbox.lock().map_err(|_| Error::Poison)?;
+            outbox_guard.ended.get_or_insert(End::Removed);
+            drop(outbox_guard);
+            removed.outbox_changed.notify_one();
+        }
+        self.release_control(client)
+    }
+
+    /// Remove the dashboard identified by `client` from the set of connected dashboards, so that
+    /// no more messages will be sent to it.
+    ///
+    /// Returns the dashboard, if it was still connected.
+    ///
+    /// # Errors
+    ///
+    /// This function may return an `Err` if an internal lock is poisoned.
+    fn forget(&self, client: ClientId) -> Result<Option<Arc<Client<C>>>, Error> {
+        let mut clients_guard = self.clients.lock().map_err(|_| Error::Poison)?;
+        let removed = clients_guard
+            .iter()
+            .position(|c| c.id == client)
+            .map(|idx| clients_guard.remove(idx));
+        self.client_count
+            .store(clients_guard.len(), Ordering::Release);
+        Ok(removed)
+    }
+
+    /// Get a handle through which `dash_write` can write to the dashboard identified by `client`.
+    /// This should be taken as soon as the dashboard is added, since it cannot be taken once the
+    /// dashboard has been removed.
+    ///
+    /// Returns `None` if the dashboard is not connected.
     ///
     /// # Errors
     ///
     /// This function may return an `Err` if an internal lock is poisoned.
-    pub fn has_target(&self) -> Result<bool, PoisonError<RwLockReadGuard<Option<C>>>> {
-        Ok(self.dash_channel.read()?.is_some())
+    pub fn writer(&self, client: ClientId) -> Result<Option<Writer<C>>, Error> {
+        Ok(self.client(client)?.map(Writer))
     }
 
-    /// Set the outgoing channel for this stream to be `channel`.
+    /// Get the dashboard identified by `client`, if it is still connected.
     ///
     /// # Errors
     ///
     /// This function may return an `Err` if an internal lock is poisoned.
-    pub fn set_channel(
-        &self,
-        channel: Option<C>,
-    ) -> Result<(), PoisonError<RwLockWriteGuard<Option<C>>>> {
-        *self.dash_channel.write()? = channel;
+    fn client(&self, client: ClientId) -> Result<Option<Arc<Client<C>>>, Error> {
+        Ok(self
+            .clients
+            .lock()
+            .map_err(|_| Error::Poison)?
+            .iter()
+            .find(|c| c.id == client)
+            .cloned())
+    }
+
+    /// Set the optional features which were agreed upon with the dashboard identified by
+    /// `client`, marking its handshake as complete.
+    /// From then on, the dashboard also receives messages which are sent to all dashboards.
+    ///
+    /// # Errors
+    ///
+    /// This function may return an `Err` if an internal lock is poisoned.
+    pub fn set_features(&self, client: ClientId, features: Vec<Feature>) -> Result<(), Error> {
+        if let Some(c) = self.client(client)? {
+            *c.features.lock().map_err(|_| Error::Poison)? = Some(features);
+        }
         Ok(())
     }
+
+    /// Get the ID of the dashboard which is currently the commander, if there is one.
+    ///
+    /// # Errors
+    ///
+    /// This function may return an `Err` if an internal lock is poisoned.
+    pub fn commander(&self) -> Result<Option<ClientId>, Error> {
+        Ok(*self.commander.lock().map_err(|_| Error::Poison)?)
+    }
+
+    /// Make the dashboard identified by `client` the commander, but only if no other dashboard is
+    /// already the commander.
+    ///
+    /// Returns `true` if `client` is now the commander.
+    ///
+    /// # Errors
+    ///
+    /// This function may return an `Err` if an internal lock is poisoned.
+    pub fn take_control(&self, client: ClientId) -> Result<bool, Error> {
+        let mut commander_guard = self.commander.lock().map_err(|_| Error::Poison)?;
+        match *commander_guard {
+            Some(id) if id != client => Ok(false),
+            _ => {
+                *commander_guard = Some(client);
+                Ok(true)
+            }
+        }
+    }
+
+    /// Strip the dashboard identified by `client` of the commander role, if it has it.
+    ///
+    /// Returns `true` if `client` was the commander.
+    ///
+    /// # Errors
+    ///
+    /// This function may return an `Err` if an internal lock is poisoned.
+    pub fn release_control(&self, client: ClientId) -> Result<bool, Error> {
+        let mut commander_guard = self.commander.lock().map_err(|_| Error::Poison)?;
+        if *commander_guard == Some(client) {
+            *commander_guard = None;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
+impl<C: Write> Client<C> {
+    /// Write every message waiting in this dashboard's outbox to its channel.
+    ///
+    /// # Errors
+    ///
+    /// This function may return an `Err` if an internal lock is poisoned.
+    fn write_outbox(&self) -> Result<Written, Error> {
+        // hold onto the channel while taking the messages, so that concurrent writes cannot
+        // reorder them
+        let mut channel_guard = self.channel.lock().map_err(|_| Error::Poison)?;
+        let (messages, ended) = {
+            let mut outbox_guard = self.outbox.lock().map_err(|_| Error::Poison)?;
+            (take(&mut outbox_guard.messages), outbox_guard.ended)
+        };
+        for queued in messages {
+            if let Err(e) = channel_guard.write_all(&queued.serialized) {
+                return Ok(Written::Failed(e));
+            }
+        }
+        Ok(ended.map_or(Written::Open, Written::Ended))
+    }
+}
+
+/// Write the messages sent to the dashboard behind `writer` as they are sent.
+/// One thread should run this function for each connected dashboard.
+/// The dashboard's channel is dropped once this function returns, unless it is still connected.
+///
+/// If messages to the dashboard have been dropped because it could not keep up, the user will be
+/// told periodically.
+///
+/// # Inputs
+///
+/// * `dashboard_stream`: The channel whose messages should be written.
+/// * `writer`: The handle to the dashboard to write to, as returned by `DashChannel::writer`.
+/// * `user_log`: The log to which dropped messages and failures will be reported.
+/// * `state`: The overall system state.
+///
+/// This function returns once the dashboard has been removed and every message sent to it before
+/// then has been written, once writing to it fails or it falls too far behind (in which case it is
+/// forgotten), or once `state` transitions to `State::Quit` and every message sent to it so far
+/// has been written.
+/// After that, nothing more will be written to the dashboard, so its connection should be closed.
+///
+/// # Errors
+///
+/// This function will return an error if a lock is poisoned or if we are unable to write to the
+/// user log.
+pub fn dash_write<C: Write>(
+    dashboard_stream: &DashChannel<C, impl Write>,
+    writer: Writer<C>,
+    user_log: &UserLog<impl Write>,
+    state: &Guard,
+) -> Result<(), ControllerError> {
+    let Writer(dashboard) = writer;
+    let client = dashboard.id;
+    let mut reported_drops = 0;
+    let mut last_report_time = SystemTime::now();
+    loop {
+        // the messages sent while quitting, such as the final state change, must still be
+        // written
+        let quitting = state.status()? == State::Quit;
+        if !quitting {
+            let outbox_guard = dashboard.outbox.lock()?;
+            let (outbox_guard, _) = dashboard.outbox_changed.wait_timeout_while(
+                outbox_guard,
+                WRITE_POLL_PERIOD,
+                |outbox| outbox.messages.is_empty() && outbox.ended.is_none(),
+            )?;
+            drop(outbox_guard);
+        }
+
+        match dashboard.write_outbox()? {
+            Written::Open => (),
+            Written::Ended(End::Removed) => return Ok(()),
+            Written::Ended(End::Overflowed) => {
+                user_log.warn(&format!(
+                    "Disconnecting {client} because it fell too far behind to be sent any more messages"
+                ))?;
+                dashboard_stream.forget(client)?;
+                return Ok(());
+            }
+            Written::Failed(e) => {
+                user_log.warn(&format!("Unable to write to {client}, disconnecting: {e}"))?;
+                dashboard_stream.forget(client)?;
+                return Ok(());
+            }
+        }
+        if quitting {
+            return Ok(());
+        }
+
+        let dropped = dashboard.dropped.load(Ordering::Relaxed);
+        if dropped > reported_drops
+            && last_report_time.elapsed().unwrap_or_default() > DROP_REPORT_PERIOD
+        {
+            user_log.warn(&format!(
+                "Dropped {} stale sensor value messages because {client} could not keep up ({} of {} messages dropped in total)",
+                dropped - reported_drops,
+                dashboard_stream.dropped(),
+                dashboard_stream.queued()
+            ))?;
+            reported_drops = dropped;
+            last_report_time = SystemTime::now();
+        }
+    }
+}
+
+impl Display for ClientId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "client {}", self.0)
+    }
+}
+
+#[cfg(test)]
+impl<C: Write, M: Write> DashChannel<C, M> {
+    /// Immediately write every message waiting to be written to every connected dashboard.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if an internal lock is poisoned.
+    pub fn flush(&self) {
+        for client in self.clients.lock().unwrap().iter() {
+            client.write_outbox().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+impl<M: Write> DashChannel<Vec<u8>, M> {
+    /// Get every message which has been sent to the dashboard identified by `client`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the dashboard is not connected or if it was sent anything
+    /// other than JSON.
+    pub fn sent(&self, client: ClientId) -> Vec<serde_json::Value> {
+        let dashboard = self.client(client).unwrap().unwrap();
+        dashboard.write_outbox().unwrap();
+        let channel_guard = dashboard.channel.lock().unwrap();
+        serde_json::Deserializer::from_slice(&channel_guard)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    /// Determine whether `feature` was agreed upon with the dashboard identified by `client`.
+    pub fn has_feature(&self, client: ClientId, feature: Feature) -> bool {
+        self.client(client).unwrap().is_some_and(|c| {
+            c.features
+                .lock()
+                .unwrap()
+                .as_ref()
+                .is_some_and(|f| f.contains(&feature))
+        })
+    }
+}
+
+impl Message<'_> {
+    /// Get the optional feature that this message belongs to, if any.
+    fn feature(&self) -> Option<Feature> {
+        match self {
+            Message::Log { .. } => Some(Feature::Logs),
+            _ => None,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
+    use std::{
+        io::Read,
+        net::{TcpListener, TcpStream},
+        sync::mpsc::{channel as channel_pair, Receiver, Sender},
+        thread,
+        time::Duration,
+    };
 
     use serde_json::Value;
 
@@ -192,6 +878,22 @@ mod tests {
         assert_eq!(message_value, expected_value);
     }
 
+    #[test]
+    /// Test that a countdown message is serialized correctly.
+    fn serialize_countdown() {
+        serialize_helper(
+            r#"{
+                "type": "Countdown",
+                "t_minus": 10000,
+                "held": true
+            }"#,
+            &Message::Countdown {
+                t_minus: 10_000,
+                held: true,
+            },
+        );
+    }
+
     #[test]
     /// Test that a sensor value message is serialized correctly.
     fn serialize_sensor_value() {
@@ -203,6 +905,7 @@ mod tests {
                     {
                         "sensor_id": 0,
                         "reading": 3456,
+                        "value": 1.25,
                         "time": {
                             "secs_since_epoch": 1651355351,
                             "nanos_since_epoch": 534000000
@@ -214,7 +917,8 @@ mod tests {
                 group_id: 0,
                 readings: &[SensorReading {
                     sensor_id: 0,
-                    reading: 3456,
+                    reading: Some(3456),
+                    value: 1.25,
                     time: SystemTime::UNIX_EPOCH + Duration::from_millis(1_651_355_351_534),
                 }],
             },
@@ -238,4 +942,340 @@ mod tests {
             },
         );
     }
+
+    #[test]
+    /// Test that a state change message is serialized correctly.
+    fn serialize_state() {
+        serialize_helper(
+            r#"{
+                "type": "State",
+                "from": "PreIgnite",
+                "to": "EStopping",
+                "time": {
+                    "secs_since_epoch": 1651355351,
+                    "nanos_since_epoch": 534000000
+                }
+            }"#,
+            &Message::State {
+                from: State::PreIgnite,
+                to: State::EStopping,
+                time: SystemTime::UNIX_EPOCH + Duration::from_millis(1_651_355_351_534),
+            },
+        );
+    }
+
+    #[test]
+    /// Test that messages belonging to a feature are only sent to dashboards which enabled that
+    /// feature.
+    fn feature_gated() {
+        let channel = DashChannel::<Vec<u8>, Vec<u8>>::new(Vec::new());
+        let without_logs = channel.add_client(Vec::new()).unwrap();
+        channel.set_features(without_logs, Vec::new()).unwrap();
+        let with_logs = channel.add_client(Vec::new()).unwrap();
+        channel
+            .set_features(with_logs, vec![Feature::Logs])
+            .unwrap();
+
+        channel
+            .send(&Message::Log {
+                level: LogLevel::Warn,
+                time: SystemTime::UNIX_EPOCH,
+                message: "hi",
+            })
+            .unwrap();
+
+        assert!(channel.sent(without_logs).is_empty());
+        assert_eq!(channel.sent(with_logs).len(), 1);
+    }
+
+    #[test]
+    /// Test that messages can be sent to every dashboard or to just one.
+    fn multiple_clients() {
+        let channel = DashChannel::<Vec<u8>, Vec<u8>>::new(Vec::new());
+        let a = channel.add_client(Vec::new()).unwrap();
+        channel.set_features(a, Vec::new()).unwrap();
+        let b = channel.add_client(Vec::new()).unwrap();
+        channel.set_features(b, Vec::new()).unwrap();
+        assert_ne!(a, b);
+
+        channel
+            .send(&Message::DriverValue { values: &[true] })
+            .unwrap();
+        channel
+            .send_to(b, &Message::Refused { reason: "b" })
+            .unwrap();
+
+        assert_eq!(channel.sent(a).len(), 1);
+        assert_eq!(channel.sent(b).len(), 2);
+        assert_eq!(channel.sent(b)[1]["type"], "Refused");
+    }
+
+    #[test]
+    /// Test that only one dashboard can be the commander at a time, and that the commander role
+    /// is freed when the commander disconnects.
+    fn commander_role() {
+        let channel = DashChannel::<Vec<u8>, Vec<u8>>::new(Vec::new());
+        let a = channel.add_client(Vec::new()).unwrap();
+        channel.set_features(a, Vec::new()).unwrap();
+        let b = channel.add_client(Vec::new()).unwrap();
+        channel.set_features(b, Vec::new()).unwrap();
+
+        assert!(channel.take_control(a).unwrap());
+        assert!(!channel.take_control(b).unwrap());
+        assert!(!channel.release_control(b).unwrap());
+        assert_eq!(channel.commander().unwrap(), Some(a));
+
+        assert!(channel.remove_client(a).unwrap());
+        assert_eq!(channel.commander().unwrap(), None);
+        assert!(channel.take_control(b).unwrap());
+    }
+
+    #[test]
+    /// Test that a stale sensor value is replaced by a newer one from the same group, but that
+    /// driver values are never dropped.
+    fn coalesce_sensor_values() {
+        let channel = DashChannel::<Vec<u8>, Vec<u8>>::new(Vec::new());
+        let client = channel.add_client(Vec::new()).unwrap();
+        channel.set_features(client, Vec::new()).unwrap();
+
+        for reading in [1, 2] {
+            channel
+                .send(&Message::SensorValue {
+                    group_id: 0,
+                    readings: &[SensorReading {
+                        sensor_id: 0,
+                        reading: Some(reading),
+                        value: f64::from(reading),
+                        time: SystemTime::UNIX_EPOCH,
+                    }],
+                })
+                .unwrap();
+            channel
+                .send(&Message::DriverValue { values: &[true] })
+                .unwrap();
+        }
+
+        let messages = channel.sent(client);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0]["type"], "SensorValue");
+        assert_eq!(messages[0]["readings"][0]["reading"], 2);
+        assert_eq!(messages[1]["type"], "DriverValue");
+        assert_eq!(messages[2]["type"], "DriverValue");
+        assert_eq!(channel.queued(), 4);
+        assert_eq!(channel.dropped(), 1);
+    }
+
+    #[test]
+    /// Test that sensor values are dropped once the queue is full, but that other messages are
+    /// still queued.
+    fn full_queue() {
+        let channel = DashChannel::<Vec<u8>, Vec<u8>>::new(Vec::new());
+        let client = channel.add_client(Vec::new()).unwrap();
+        channel.set_features(client, Vec::new()).unwrap();
+
+        for _ in 0..QUEUE_CAPACITY {
+            channel
+                .send(&Message::DriverValue { values: &[false] })
+                .unwrap();
+        }
+        channel
+            .send(&Message::SensorValue {
+                group_id: 0,
+                readings: &[],
+            })
+            .unwrap();
+        channel
+            .send(&Message::State {
+                from: State::Standby,
+                to: State::PreIgnite,
+                time: SystemTime::UNIX_EPOCH,
+            })
+            .unwrap();
+
+        let messages = channel.sent(client);
+        assert_eq!(messages.len(), QUEUE_CAPACITY + 1);
+        assert_eq!(messages[QUEUE_CAPACITY]["type"], "State");
+        assert_eq!(channel.dropped(), 1);
+    }
+
+    #[test]
+    /// Test that a full outbox of sensor values makes room for messages which may not be dropped.
+    fn sensor_values_evicted() {
+        let channel = DashChannel::<Vec<u8>, Vec<u8>>::new(Vec::new());
+        let client = channel.add_client(Vec::new()).unwrap();
+        channel.set_features(client, Vec::new()).unwrap();
+
+        for group_id in 0..=u8::MAX {
+            channel
+                .send(&Message::SensorValue {
+                    group_id,
+                    readings: &[],
+                })
+                .unwrap();
+        }
+        channel
+            .send(&Message::DriverValue { values: &[true] })
+            .unwrap();
+        channel
+            .send(&Message::State {
+                from: State::Standby,
+                to: State::PreIgnite,
+                time: SystemTime::UNIX_EPOCH,
+            })
+            .unwrap();
+
+        let messages = channel.sent(client);
+        assert_eq!(messages.len(), QUEUE_CAPACITY);
+        // the oldest sensor values made way
+        assert_eq!(messages[0]["group_id"], 2);
+        assert_eq!(messages[QUEUE_CAPACITY - 2]["type"], "DriverValue");
+        assert_eq!(messages[QUEUE_CAPACITY - 1]["type"], "State");
+        assert_eq!(channel.dropped(), 2);
+    }
+
+    #[test]
+    /// Test that a dashboard is disconnected, rather than having messages withheld, once its
+    /// outbox reaches its limit.
+    fn queue_limit() {
+        let channel = DashChannel::<Vec<u8>, Vec<u8>>::new(Vec::new());
+        let client = channel.add_client(Vec::new()).unwrap();
+        channel.set_features(client, Vec::new()).unwrap();
+        let user_log = UserLog::new(Vec::new());
+        let state = Guard::new(State::Standby);
+
+        for _ in 0..=QUEUE_LIMIT {
+            channel
+                .send(&Message::DriverValue { values: &[false] })
+                .unwrap();
+        }
+        assert_eq!(channel.dropped(), 0);
+
+        let writer = channel.writer(client).unwrap().unwrap();
+        dash_write(&channel, writer, &user_log, &state).unwrap();
+        assert!(!channel.has_target());
+    }
+
+    /// A connection to a dashboard which passes on everything written to it, but which can be
+    /// made to stall until it is released.
+    struct Pipe {
+        /// The channel on which written bytes are passed on.
+        tx: Sender<Vec<u8>>,
+        /// If the pipe is stalled, the channel which releases it once its sender is dropped.
+        stall: Option<Receiver<()>>,
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if let Some(stall) = &self.stall {
+                let _ = stall.recv();
+            }
+            let _ = self.tx.send(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    /// Test that a dashboard which stops reading does not delay the others.
+    fn stalled_dashboard() {
+        let channel = DashChannel::new(Vec::new());
+        let (stalled_tx, stalled_rx) = channel_pair();
+        let (release_tx, release_rx) = channel_pair();
+        let stalled = channel
+            .add_client(Pipe {
+                tx: stalled_tx,
+                stall: Some(release_rx),
+            })
+            .unwrap();
+        channel.set_features(stalled, Vec::new()).unwrap();
+        let (healthy_tx, healthy_rx) = channel_pair();
+        let healthy = channel
+            .add_client(Pipe {
+                tx: healthy_tx,
+                stall: None,
+            })
+            .unwrap();
+        channel.set_features(healthy, Vec::new()).unwrap();
+        let user_log = UserLog::new(Vec::new());
+        let state = Guard::new(State::Standby);
+
+        thread::scope(|s| {
+            for client in [stalled, healthy] {
+                let writer = channel.writer(client).unwrap().unwrap();
+                let (channel, user_log, state) = (&channel, &user_log, &state);
+                s.spawn(move || dash_write(channel, writer, user_log, state).unwrap());
+            }
+
+            channel
+                .send(&Message::DriverValue { values: &[true] })
+                .unwrap();
+            let written = healthy_rx.recv_timeout(Duration::from_secs(1)).unwrap();
+            let message: Value = serde_json::from_slice(&written).unwrap();
+            assert_eq!(message["type"], "DriverValue");
+            assert!(stalled_rx.try_recv().is_err());
+
+            drop(release_tx);
+            state.move_to(State::Quit).unwrap();
+        });
+        assert!(stalled_rx.try_recv().is_ok());
+    }
+
+    #[test]
+    /// Test that the writer thread delivers a message sent to a dashboard just before it is
+    /// removed, before its connection is closed.
+    fn written_before_removal() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut dashboard = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let channel = DashChannel::new(Vec::new());
+        let client = channel.add_client(stream).unwrap();
+        let writer = channel.writer(client).unwrap().unwrap();
+        let user_log = UserLog::new(Vec::new());
+        let state = Guard::new(State::Standby);
+
+        thread::scope(|s| {
+            channel
+                .send_to(client, &Message::Refused { reason: "go away" })
+                .unwrap();
+            channel.remove_client(client).unwrap();
+            // the writer may only get to run after the dashboard has been removed
+            s.spawn(|| dash_write(&channel, writer, &user_log, &state).unwrap());
+
+            // the controller's end of the connection is closed once the message is written
+            let mut received = Vec::new();
+            dashboard.read_to_end(&mut received).unwrap();
+            let message: Value = serde_json::from_slice(&received).unwrap();
+            assert_eq!(message["type"], "Refused");
+            assert_eq!(message["reason"], "go away");
+        });
+    }
+
+    #[test]
+    /// Test that the writer thread writes the messages which were sent as the controller quit.
+    fn written_on_quit() {
+        let channel = DashChannel::<Vec<u8>, Vec<u8>>::new(Vec::new());
+        let client = channel.add_client(Vec::new()).unwrap();
+        channel.set_features(client, Vec::new()).unwrap();
+        let user_log = UserLog::new(Vec::new());
+        let state = Guard::new(State::Standby);
+
+        state.move_to(State::Quit).unwrap();
+        channel
+            .send(&Message::State {
+                from: State::Standby,
+                to: State::Quit,
+                time: SystemTime::UNIX_EPOCH,
+            })
+            .unwrap();
+        let writer = channel.writer(client).unwrap().unwrap();
+        dash_write(&channel, writer, &user_log, &state).unwrap();
+
+        let dashboard = channel.client(client).unwrap().unwrap();
+        let channel_guard = dashboard.channel.lock().unwrap();
+        let message: Value = serde_json::from_slice(&channel_guard).unwrap();
+        assert_eq!(message["type"], "State");
+    }
 }
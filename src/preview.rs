@@ -0,0 +1,443 @@
+/*
+  slonk, a rocket engine controller.
+  Copyright (C) 2022 Rice Eclipse.
+
+  slonk is free software: you can redistribute it and/or modify
+  it under the terms of the GNU General Public License as published by
+  the Free Software Foundation, either version 3 of the License, or
+  (at your option) any later version.
+
+  slonk is distributed in the hope that it will be useful,
+  but WITHOUT ANY WARRANTY; without even the implied warranty of
+  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+  GNU General Public License for more details.
+
+  You should have received a copy of the GNU General Public License
+  along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Dry runs of sequences, which work out what a sequence would do without touching any hardware.
+
+use std::{
+    fs::File,
+    io::{BufReader, Write},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::{
+    config::{Action, Configuration},
+    ControllerError,
+};
+
+#[derive(Debug, PartialEq, Serialize)]
+/// The planned timeline of a sequence, as worked out by a dry run.
+pub struct Timeline<'a> {
+    /// Every actuation the sequence would perform, in the order that they would happen.
+    pub events: Vec<TimelineEvent<'a>>,
+    /// The time the sequence would take if every `WaitUntil` step were satisfied immediately.
+    pub duration: Duration,
+    /// The time the sequence would take if every `WaitUntil` step ran until its timeout.
+    pub max_duration: Duration,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+/// A single actuation in a timeline.
+pub struct TimelineEvent<'a> {
+    /// The time after the start of the sequence at which the actuation would happen, assuming that
+    /// every `WaitUntil` step before it were satisfied immediately.
+    pub offset: Duration,
+    /// The identifier (i.e. index) of the driver which would be actuated.
+    pub driver_id: u8,
+    /// The label of the driver which would be actuated.
+    pub label: &'a str,
+    /// The level that the driver would be actuated to.
+    pub value: bool,
+    /// The description of the actuation, which is either the driver's `label_actuate` or its
+    /// `label_deactuate`.
+    pub description: &'a str,
+}
+
+/// A change to a virtual driver, before it has been replayed to work out the driver's new level.
+enum Change {
+    /// The driver is set to a level.
+    Set(bool),
+    /// The driver is pulsed to `true`, remembering its previous level in the slot with the given
+    /// index.
+    PulseStart(usize),
+    /// A pulse ends, returning the driver to the level remembered in the slot with the given index.
+    PulseEnd(usize),
+}
+
+/// Find the sequence which a dashboard or user refers to as `name`.
+///
+/// The names `"ignition_sequence"` and `"estop_sequence"` refer to the ignition and emergency stop
+/// sequences; any other name refers to one of the named auxiliary sequences.
+pub fn find_sequence<'a>(configuration: &'a Configuration, name: &str) -> Option<&'a [Action]> {
+    match name {
+        "ignition_sequence" => Some(&configuration.ignition_sequence),
+        "estop_sequence" => Some(&configuration.estop_sequence),
+        _ => configuration.sequences.get(name).map(Vec::as_slice),
+    }
+}
+
+#[must_use]
+/// Work out what performing `actions` would do, without touching any hardware.
+///
+/// The sequence is simulated against a set of virtual drivers, which begin at the levels in
+/// `levels`.
+/// Steps which would leave a driver at the level it is already at are left out of the timeline.
+/// Since there is no way to know how long a `WaitUntil` step will take, the offsets of events are
+/// worked out as if every wait were satisfied immediately.
+///
+/// # Panics
+///
+/// This function will panic if `levels` has fewer entries than there are drivers in
+/// `configuration`.
+pub fn dry_run<'a>(
+    configuration: &'a Configuration,
+    actions: &[Action],
+    levels: &[bool],
+) -> Timeline<'a> {
+    let mut planner = Planner {
+        configuration,
+        changes: Vec::new(),
+        pulses: 0,
+        pulse_end: Duration::ZERO,
+        max_pulse_end: Duration::ZERO,
+    };
+    let (end, max_end) = planner.plan(actions, Duration::ZERO, Duration::ZERO);
+    // a sequence is not over until all of its pulses are
+    let duration = end.max(planner.pulse_end);
+    let max_duration = max_end.max(planner.max_pulse_end);
+
+    // replay the changes in order onto the virtual drivers
+    let Planner {
+        mut changes,
+        pulses,
+        ..
+    } = planner;
+    changes.sort_by_key(|&(offset, _, _)| offset);
+    let mut levels = levels.to_vec();
+    let mut previous = vec![false; pulses];
+    let mut events = Vec::new();
+    for (offset, driver_id, change) in changes {
+        let level = &mut levels[usize::from(driver_id)];
+        let value = match change {
+            Change::Set(value) => value,
+            Change::PulseStart(slot) => {
+                previous[slot] = *level;
+                true
+            }
+            Change::PulseEnd(slot) => {
+                // the driver was deactuated during the pulse, so the pulse leaves it alone
+                if !*level {
+                    continue;
+                }
+                previous[slot]
+            }
+        };
+        // a change which leaves the driver where it was (such as pulsing a driver which is
+        // already on) does not actuate anything
+        if value == *level {
+            continue;
+        }
+        *level = value;
+
+        let driver = &configuration.drivers[usize::from(driver_id)];
+        events.push(TimelineEvent {
+            offset,
+            driver_id,
+            label: &driver.label,
+            value,
+            description: if value {
+                &driver.label_actuate
+            } else {
+                &driver.label_deactuate
+            },
+        });
+    }
+
+    Timeline {
+        events,
+        duration,
+        max_duration,
+    }
+}
+
+/// A plan of the changes a sequence would make to its drivers, which is built up one step at a
+/// time.
+struct Planner<'a> {
+    /// The configuration, which is used to find the drivers which steps refer to.
+    configuration: &'a Configuration,
+    /// Every change planned so far, along with its offset and the driver which it changes.
+    changes: Vec<(Duration, u8, Change)>,
+    /// The number of pulses planned so far.
+    pulses: usize,
+    /// The latest offset at which a pulse ends, if every wait is satisfied immediately.
+    pulse_end: Duration,
+    /// The latest offset at which a pulse ends, if every wait runs until its timeout.
+    max_pulse_end: Duration,
+}
+
+impl Planner<'_> {
+    /// Plan out the changes made by `actions`, beginning at the offset `start` (or `max_start`, if
+    /// every wait so far has run until its timeout).
+    ///
+    /// Returns the offsets at which the steps would end if every wait were satisfied immediately,
+    /// and if every wait ran until its timeout.
+    fn plan(
+        &mut self,
+        actions: &[Action],
+        start: Duration,
+        max_start: Duration,
+    ) -> (Duration, Duration) {
+        let mut now = start;
+        let mut max_now = max_start;
+        for action in actions {
+            match action {
+                Action::Actuate { driver_id, value } => {
+                    if let Some(id) = self.configuration.driver_id(driver_id) {
+                        self.changes.push((now, id, Change::Set(*value)));
+                    }
+                }
+                Action::Pulse {
+                    driver_id,
+                    duration,
+                } => {
+                    let Some(id) = self.configuration.driver_id(driver_id) else {
+                        continue;
+                    };
+                    let slot = self.pulses;
+                    self.pulses += 1;
+                    self.changes.push((now, id, Change::PulseStart(slot)));
+                    self.changes
+                        .push((now + *duration, id, Change::PulseEnd(slot)));
+                    self.pulse_end = self.pulse_end.max(now + *duration);
+                    self.max_pulse_end = self.max_pulse_end.max(max_now + *duration);
+                }
+                Action::Sleep { duration } => {
+                    now += *duration;
+                    max_now += *duration;
+                }
+                Action::WaitUntil { timeout, .. } => max_now += *timeout,
+                Action::Parallel { tracks } => {
+                    let (mut end, mut max_end) = (now, max_now);
+                    for track in tracks {
+                        let (track_end, track_max_end) = self.plan(track, now, max_now);
+                        end = end.max(track_end);
+                        max_end = max_end.max(track_max_end);
+                    }
+                    now = end;
+                    max_now = max_end;
+                }
+            }
+        }
+
+        (now, max_now)
+    }
+}
+
+/// Write a timeline for the sequence named `name` in a human-readable form.
+///
+/// # Errors
+///
+/// This function will return an error if we are unable to write to `out`.
+pub fn write_timeline(
+    out: &mut impl Write,
+    name: &str,
+    timeline: &Timeline,
+) -> std::io::Result<()> {
+    writeln!(out, "{name}:")?;
+    for event in &timeline.events {
+        writeln!(
+            out,
+            "  T+{:>9.3} s  {} ({}) -> {} [{}]",
+            event.offset.as_secs_f64(),
+            event.label,
+            event.driver_id,
+            event.value,
+            event.description
+        )?;
+    }
+    writeln!(
+        out,
+        "  total duration: {:.3} s (up to {:.3} s if every wait times out)",
+        timeline.duration.as_secs_f64(),
+        timeline.max_duration.as_secs_f64()
+    )
+}
+
+/// The entry point for the `dry-run` subcommand of the controller executables.
+///
+/// The first element of `args` is the path to a configuration file, and the rest are the names of
+/// the sequences to dry-run (as understood by `find_sequence`).
+/// If no sequences are named, the ignition and emergency stop sequences are dry-run.
+/// The timeline of each sequence is printed to standard output, with every driver starting off.
+///
+/// # Errors
+///
+/// This function will return an error if the configuration cannot be read, if a sequence does not
+/// exist, or if we are unable to write to standard output.
+pub fn dry_run_command(args: &[String]) -> Result<(), ControllerError> {
+    let json_path = args
+        .first()
+        .ok_or(ControllerError::Args("No configuration JSON path given"))?;
+    let configuration = Configuration::parse(&mut BufReader::new(File::open(json_path)?))?;
+
+    let names: Vec<&str> = if args.len() > 1 {
+        args[1..].iter().map(String::as_str).collect()
+    } else {
+        vec!["ignition_sequence", "estop_sequence"]
+    };
+
+    let levels = vec![false; configuration.drivers.len()];
+    let mut stdout = std::io::stdout().lock();
+    for name in names {
+        let actions = find_sequence(&configuration, name)
+            .ok_or(ControllerError::Args("No sequence with the given name"))?;
+        write_timeline(
+            &mut stdout,
+            name,
+            &dry_run(&configuration, actions, &levels),
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// A configuration with a main valve (driver 0) and an igniter (driver 1).
+    const CONFIG: &str = r##"{
+        "frequency_status": 1,
+        "log_buffer_size": 1,
+        "sensor_groups": [
+            {
+                "label": "FAST",
+                "frequency_standby": 10,
+                "frequency_ignition": 10,
+                "frequency_transmission": 10,
+                "sensors": [
+                    {
+                        "label": "PT_CHAMBER",
+                        "color": "#000000",
+                        "units": "psi",
+                        "calibration": {
+                            "type": "Linear",
+                            "slope": 1,
+                            "intercept": 0
+                        },
+                        "adc": 0,
+                        "channel": 0
+                    }
+                ]
+            }
+        ],
+        "pre_ignite_time": 0,
+        "post_ignite_time": 0,
+        "drivers": [
+            {
+                "label": "MAIN",
+                "label_actuate": "Open",
+                "label_deactuate": "Close",
+                "pin": 21,
+                "protected": false
+            },
+            {
+                "label": "IGNITER",
+                "label_actuate": "Ignite",
+                "label_deactuate": "Shutoff",
+                "pin": 22,
+                "protected": true
+            }
+        ],
+        "ignition_sequence": [
+            {
+                "type": "Pulse",
+                "driver_id": 1,
+                "duration": { "secs": 0, "nanos": 250000000 }
+            },
+            {
+                "type": "Parallel",
+                "tracks": [
+                    [
+                        { "type": "Sleep", "duration": { "secs": 0, "nanos": 100000000 } },
+                        { "type": "Actuate", "driver_id": 0, "value": true }
+                    ],
+                    [
+                        {
+                            "type": "WaitUntil",
+                            "sensor": "PT_CHAMBER",
+                            "condition": "Above",
+                            "threshold": 50,
+                            "timeout": { "secs": 2, "nanos": 0 }
+                        }
+                    ]
+                ]
+            },
+            { "type": "Sleep", "duration": { "secs": 1, "nanos": 0 } },
+            { "type": "Actuate", "driver_id": 0, "value": false }
+        ],
+        "estop_sequence": [],
+        "spi_mosi": 11,
+        "spi_miso": 12,
+        "spi_clk": 13,
+        "spi_frequency_clk": 50000,
+        "adc_cs": [14],
+        "pin_heartbeat": 0
+    }"##;
+
+    #[test]
+    /// Test that a dry run lays out every actuation of a sequence in order, with the drivers'
+    /// labels.
+    fn ignition_timeline() {
+        let config = Configuration::parse(&mut Cursor::new(CONFIG)).unwrap();
+        let actions = find_sequence(&config, "ignition_sequence").unwrap();
+        let timeline = dry_run(&config, actions, &[false, false]);
+
+        let events: Vec<(u128, &str, bool, &str)> = timeline
+            .events
+            .iter()
+            .map(|e| (e.offset.as_millis(), e.label, e.value, e.description))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (0, "IGNITER", true, "Ignite"),
+                (100, "MAIN", true, "Open"),
+                (250, "IGNITER", false, "Shutoff"),
+                (1100, "MAIN", false, "Close"),
+            ]
+        );
+        assert_eq!(timeline.duration, Duration::from_millis(1100));
+        // the wait could take up to two seconds before the final sleep
+        assert_eq!(timeline.max_duration, Duration::from_secs(3));
+
+        let mut out = Vec::new();
+        write_timeline(&mut out, "ignition_sequence", &timeline).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("IGNITER (1) -> true [Ignite]"));
+        assert!(out.contains("total duration: 1.100 s (up to 3.000 s"));
+    }
+
+    #[test]
+    /// Test that a pulse returns its virtual driver to the level it started at, and that a pulse
+    /// which would not change its driver is left out of the timeline.
+    fn pulse_returns() {
+        let config = Configuration::parse(&mut Cursor::new(CONFIG)).unwrap();
+        let actions = find_sequence(&config, "ignition_sequence").unwrap();
+        let timeline = dry_run(&config, actions, &[false, true]);
+
+        // the igniter was already on, so the pulse does nothing
+        let labels: Vec<&str> = timeline.events.iter().map(|e| e.label).collect();
+        assert_eq!(labels, ["MAIN", "MAIN"]);
+        assert_eq!(timeline.duration, Duration::from_millis(1100));
+        assert!(find_sequence(&config, "no_such_sequence").is_none());
+    }
+}
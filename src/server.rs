@@ -18,8 +18,8 @@
 
 use std::{
     fs::{create_dir_all, File},
-    io::{self, BufReader, Read, Write},
-    net::TcpListener,
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    net::{Shutdown, TcpListener},
     path::{Path, PathBuf},
     sync::Mutex,
     thread::Scope,
@@ -30,20 +30,28 @@ use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
 
 use crate::{
     config::Configuration,
-    console::UserLog,
-    data::{driver_status_listen, sensor_listen},
-    execution::handle_command,
+    console::{log_forward, UserLog},
+    data::{driver_status_listen, sensor_listen, state_listen, AbortMonitor, AlarmLevel, Readings},
+    deadman::{deadman_listen, Deadman},
+    execution::{self, handle_command},
     hardware::{
         spi::{Bus, Device},
         Adc, GpioPin, ListenerPin, Mcp3208, ReturnsNumber,
     },
     heartbeat::heartbeat,
-    incoming::{self, Command},
-    outgoing::{DashChannel, Message},
+    incoming::{self, Command, Framer, Request},
+    outgoing::{dash_write, ClientId, DashChannel, Message, FEATURES, PROTOCOL_VERSION},
+    preview,
     state::{Guard, State},
     ControllerError,
 };
 
+/// How long a newly-connected dashboard has to send its `Hello` message before it is refused.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long writing to a dashboard may block before the dashboard is considered dead and dropped.
+const DASH_WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
 /// A trait for functions which can create the necessary hardware for the server to run.
 ///
 /// This exists to allow us to "spoof" hardware for the main process so we don't have to test
@@ -106,6 +114,16 @@ pub trait MakeHardware {
         config: &Configuration,
         chip: &mut Self::Chip,
     ) -> Result<Self::Pin, ControllerError>;
+
+    /// Get the arm-key GPIO input from the configuration, if there is one.
+    ///
+    /// # Errors
+    ///
+    /// This function may return an error if it is unable to acquire the GPIO needed.
+    fn arm_key(
+        config: &Configuration,
+        chip: &mut Self::Chip,
+    ) -> Result<Option<Self::Pin>, ControllerError>;
 }
 
 /// A hardware maker for actually interfacing with the Raspberry Pi.
@@ -187,6 +205,21 @@ impl MakeHardware for RaspberryPi {
             "slonk",
         )?)
     }
+
+    fn arm_key(
+        config: &Configuration,
+        chip: &mut Self::Chip,
+    ) -> Result<Option<Self::Pin>, ControllerError> {
+        config
+            .arming
+            .key_pin
+            .map(|pin| {
+                Ok(chip
+                    .get_line(u32::from(pin))?
+                    .request(LineRequestFlags::INPUT, 0, "slonk")?)
+            })
+            .transpose()
+    }
 }
 
 /// A dummy hardware maker for testing on any Linux computer.
@@ -204,15 +237,15 @@ impl MakeHardware for Dummy {
         Ok(())
     }
 
-    fn bus(_: &Configuration, _: &mut Self::Chip) -> Result<Self::Bus, ControllerError> {
+    fn bus(_: &Configuration, (): &mut Self::Chip) -> Result<Self::Bus, ControllerError> {
         Ok(())
     }
 
     #[allow(clippy::cast_possible_truncation)]
     fn adcs<'a>(
         config: &Configuration,
-        _: &mut Self::Chip,
-        _: &'a Self::Bus,
+        (): &mut Self::Chip,
+        (): &'a Self::Bus,
     ) -> Result<Vec<Mutex<Self::Reader<'a>>>, ControllerError> {
         Ok((0..config.adc_cs.len())
             .map(|i| Mutex::new(ReturnsNumber(i as u16)))
@@ -221,16 +254,24 @@ impl MakeHardware for Dummy {
 
     fn drivers(
         config: &Configuration,
-        _: &mut Self::Chip,
+        (): &mut Self::Chip,
     ) -> Result<Vec<Self::Pin>, ControllerError> {
         Ok((0..config.drivers.len())
             .map(|_| ListenerPin::new(false))
             .collect())
     }
 
-    fn heartbeat(_: &Configuration, _: &mut Self::Chip) -> Result<Self::Pin, ControllerError> {
+    fn heartbeat(_: &Configuration, (): &mut Self::Chip) -> Result<Self::Pin, ControllerError> {
         Ok(ListenerPin::new(false))
     }
+
+    fn arm_key(
+        config: &Configuration,
+        (): &mut Self::Chip,
+    ) -> Result<Option<Self::Pin>, ControllerError> {
+        // there is no key to turn, so pretend that it is always turned
+        Ok(config.arming.key_pin.map(|_| ListenerPin::new(true)))
+    }
 }
 
 #[allow(clippy::too_many_lines, clippy::cast_possible_truncation)]
@@ -238,6 +279,9 @@ impl MakeHardware for Dummy {
 ///
 /// `M` is a dependency-injector for creating hardware.
 ///
+/// If the first argument is `dry-run`, the controller instead prints the timelines of sequences
+/// without touching any hardware, as described in `preview::dry_run_command`.
+///
 /// # Errors
 ///
 /// This function can return any of the possible errors in `ControllerError`.
@@ -247,21 +291,26 @@ impl MakeHardware for Dummy {
 /// This function may panic if it is unable to correctly set up the controller.
 pub fn run<M: MakeHardware>() -> Result<(), ControllerError> {
     let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("dry-run") {
+        return preview::dry_run_command(&args[1..]);
+    }
+
     // Use arguments to get configuration file
     let json_path = args
-        .get(0)
+        .first()
         .ok_or(ControllerError::Args("No configuration JSON path given"))?;
     let logs_path = args
         .get(1)
         .ok_or(ControllerError::Args("No logs path given"))?;
 
     create_dir_all(logs_path)?;
-    let Ok(console_log_file) = file_create_new(PathBuf::from_iter([
-        logs_path,
-        "console.txt",
-    ])) else {
+    let Ok(console_log_file) = file_create_new(PathBuf::from_iter([logs_path, "console.txt"]))
+    else {
         println!("Console log file location already exists. Please delete that file or specify a different log file path.");
-        return Err(ControllerError::Console(std::io::Error::new(std::io::ErrorKind::AlreadyExists, "file already exists")));
+        return Err(ControllerError::Console(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            "file already exists",
+        )));
     };
     let user_log = UserLog::new(console_log_file);
     let user_log_ref = &user_log;
@@ -282,6 +331,9 @@ pub fn run<M: MakeHardware>() -> Result<(), ControllerError> {
     };
     let config_ref = &config;
     user_log.debug("Successfully parsed configuration file")?;
+    let log_entries = config
+        .log_level_dashboard
+        .map(|min_level| user_log.forward(min_level));
 
     user_log.debug("Creating log files")?;
 
@@ -296,10 +348,10 @@ pub fn run<M: MakeHardware>() -> Result<(), ControllerError> {
             sensor_group_path.display()
         ))?;
 
-        for sensor in &sensor_group.sensors {
-            // create file for this specific sensor
+        for channel in sensor_group.channels() {
+            // create file for this specific sensor or derived channel
             let mut sensor_file_path = sensor_group_path.clone();
-            sensor_file_path.push(&format!("{}.csv", sensor.label));
+            sensor_file_path.push(format!("{}.csv", channel.label));
             group_files.push(file_create_new(&sensor_file_path)?);
 
             user_log.info(&format!("Created log file {:}", sensor_file_path.display()))?;
@@ -317,8 +369,10 @@ pub fn run<M: MakeHardware>() -> Result<(), ControllerError> {
 
     let mut drivers_file = file_create_new(PathBuf::from_iter([logs_path, "drivers.csv"]))?;
 
-    // when a client connects, the inner value of this mutex will be `Some` containing a TCP stream
-    // to the dashboard
+    let mut states_file = file_create_new(PathBuf::from_iter([logs_path, "states.csv"]))?;
+
+    // every connected dashboard is given a TCP stream in here, and messages to them are written
+    // by a dedicated writer thread
     let to_dash = DashChannel::new(file_create_new(PathBuf::from_iter([
         logs_path, "sent.csv",
     ]))?);
@@ -328,6 +382,16 @@ pub fn run<M: MakeHardware>() -> Result<(), ControllerError> {
 
     let state = Guard::new(State::Standby);
     let state_ref = &state;
+    let transitions = state.subscribe()?;
+
+    let deadman = Deadman::new();
+    let deadman_ref = &deadman;
+
+    let readings = Readings::new(&config);
+    let readings_ref = &readings;
+
+    let aborts = AbortMonitor::new(&config);
+    let aborts_ref = &aborts;
 
     user_log.debug("Now acquiring GPIO")?;
 
@@ -340,6 +404,9 @@ pub fn run<M: MakeHardware>() -> Result<(), ControllerError> {
     let driver_lines = Mutex::new(M::drivers(&config, &mut gpio_chip)?);
     let driver_lines_ref = &driver_lines;
 
+    let arm_key = Mutex::new(M::arm_key(&config, &mut gpio_chip)?);
+    let arm_key_ref = &arm_key;
+
     user_log.debug("Successfully acquired GPIO handles")?;
     user_log.debug("Now spawning sensor listener threads...")?;
 
@@ -354,6 +421,8 @@ pub fn run<M: MakeHardware>() -> Result<(), ControllerError> {
                     log_file_group,
                     user_log_ref,
                     adcs_ref,
+                    readings_ref,
+                    aborts_ref,
                     state_ref,
                     to_dash_ref,
                 )
@@ -371,8 +440,25 @@ pub fn run<M: MakeHardware>() -> Result<(), ControllerError> {
             )
         });
 
+        s.spawn(|| state_listen(transitions, &mut states_file, &user_log, &to_dash));
+
+        if let Some(log_entries) = log_entries {
+            s.spawn(move || log_forward(&log_entries, state_ref, to_dash_ref, user_log_ref));
+        }
+
         s.spawn(|| heartbeat(&mut pin_heartbeat, state_ref));
 
+        s.spawn(|| {
+            deadman_listen(
+                &config,
+                &deadman,
+                &driver_lines,
+                &readings,
+                &user_log,
+                &state,
+            )
+        });
+
         user_log.debug("Successfully spawned sensor listener threads.")?;
         user_log.debug("Opening network...")?;
 
@@ -387,32 +473,63 @@ pub fn run<M: MakeHardware>() -> Result<(), ControllerError> {
         user_log.debug("Handling clients...")?;
 
         for client_res in listener.incoming() {
-            let mut stream = match client_res {
+            let stream = match client_res {
                 Ok(i) => i,
                 Err(e) => {
                     user_log.warn(&format!("failed to collect incoming client: {e}"))?;
                     continue;
                 }
             };
-            user_log.info(&format!("Accepted client {:?}", stream.peer_addr()?))?;
-            to_dash.set_channel(Some(stream.try_clone()?))?;
+            // a dashboard which stops reading must be noticed
+            stream.set_write_timeout(Some(DASH_WRITE_TIMEOUT))?;
+            let client = to_dash.add_client(stream.try_clone()?)?;
+            user_log.info(&format!("Accepted {client} from {:?}", stream.peer_addr()?))?;
+            // a dashboard which never says hello must not be able to wait forever
+            stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+            let timeout_stream = stream.try_clone()?;
+            let write_stream = stream.try_clone()?;
+            // take the writer now, so that the dashboard cannot be removed before it exists
+            let Some(writer) = to_dash.writer(client)? else {
+                continue;
+            };
 
-            user_log.debug("Overwrote to dashboard lock, now reading commands")?;
+            s.spawn(move || {
+                let result = dash_write(to_dash_ref, writer, user_log_ref, state_ref);
+                // nothing more will be written to the dashboard, so stop reading from it too
+                #[allow(unused_must_use)]
+                {
+                    write_stream.shutdown(Shutdown::Both);
+                }
+                result
+            });
 
-            #[allow(unused_must_use)]
-            {
-                // keep the port open even in error cases
-                handle_client(
-                    s,
-                    to_dash_ref,
-                    &mut stream,
-                    config_ref,
-                    driver_lines_ref,
-                    cmd_file_ref,
-                    user_log_ref,
-                    state_ref,
-                );
-            }
+            s.spawn(move || {
+                #[allow(unused_must_use)]
+                {
+                    // keep the port open even in error cases
+                    handle_client(
+                        s,
+                        client,
+                        to_dash_ref,
+                        &mut Framer::new(BufReader::new(stream)),
+                        || timeout_stream.set_read_timeout(None),
+                        config_ref,
+                        driver_lines_ref,
+                        arm_key_ref,
+                        readings_ref,
+                        cmd_file_ref,
+                        user_log_ref,
+                        state_ref,
+                        deadman_ref,
+                    );
+                }
+
+                if to_dash_ref.remove_client(client)? {
+                    user_log_ref.warn(&format!("The commander ({client}) disconnected."))?;
+                    deadman_ref.disconnect()?;
+                }
+                Ok::<(), ControllerError>(())
+            });
         }
 
         Ok::<(), ControllerError>(())
@@ -434,71 +551,465 @@ fn file_create_new(p: impl AsRef<Path>) -> io::Result<File> {
         .open(p)
 }
 
+/// Let a newly connected dashboard know about every alarm which was raised before it connected.
+///
+/// # Errors
+///
+/// This function will return an error if a lock is poisoned or if a message cannot be queued for
+/// the dashboard.
+fn send_alarms(
+    client: ClientId,
+    to_dash: &DashChannel<impl Write, impl Write>,
+    config: &Configuration,
+    readings: &Readings,
+) -> Result<(), ControllerError> {
+    for (group_id, group) in config.sensor_groups.iter().enumerate() {
+        for (sensor_id, channel) in group.channels().enumerate() {
+            let (Some(level), Some(sample)) = (
+                readings.alarm(channel.label)?,
+                readings.sample(channel.label)?,
+            ) else {
+                continue;
+            };
+            if level != AlarmLevel::Nominal {
+                #[allow(clippy::cast_possible_truncation)]
+                to_dash.send_to(
+                    client,
+                    &Message::Alarm {
+                        group_id: group_id as u8,
+                        sensor_id: sensor_id as u8,
+                        level,
+                        value: sample.filtered,
+                    },
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
 #[allow(clippy::too_many_arguments)]
-/// Handle a single dashboard client.
+/// Handle a single dashboard client, whose ID is `client`.
+/// `end_handshake` is called once the handshake succeeds, and should clear any read timeout which
+/// was set on `from_dash` for the handshake.
+/// This function will return once the dashboard disconnects or is refused.
 fn handle_client<'a>(
     thread_scope: &'a Scope<'a, '_>,
-    to_dash: &DashChannel<impl Write + Send, impl Write + Send>,
-    from_dash: &mut impl Read,
+    client: ClientId,
+    to_dash: &'a DashChannel<impl Write + Send + Sync, impl Write + Send>,
+    from_dash: &mut Framer<impl BufRead>,
+    end_handshake: impl FnOnce() -> io::Result<()>,
     config: &'a Configuration,
     driver_lines: &'a Mutex<Vec<impl GpioPin + Send>>,
+    arm_key: &'a Mutex<Option<impl GpioPin + Send>>,
+    readings: &'a Readings,
     cmd_log_file: &'a Mutex<impl Write + Send>,
     user_log: &'a UserLog<impl Write + Send>,
     state: &'a Guard,
+    deadman: &Deadman,
 ) -> Result<(), ControllerError> {
-    to_dash.send(&Message::Config { config })?;
+    if !handshake(client, to_dash, from_dash, user_log)? {
+        return Ok(());
+    }
+    end_handshake()?;
+
+    to_dash.send_to(
+        client,
+        &Message::Config {
+            config,
+            state: state.status()?,
+        },
+    )?;
     user_log.debug("Successfully sent configuration to dashboard.")?;
+
+    send_alarms(client, to_dash, config, readings)?;
+
     loop {
-        let cmd = match Command::parse(from_dash) {
-            Ok(cmd) => cmd,
+        let request = match from_dash.next_request() {
+            Ok(request) => request,
             Err(e) => {
-                match e {
-                    incoming::Error::Malformed(s) => {
-                        user_log.critical(&format!(
-                            "Received malformed command: {}. Future commands will likely also be invalid",
-                            String::from_utf8_lossy(&s)
-                        ))?;
-                    }
-                    incoming::Error::Io(e) => {
-                        if matches!(
-                            e.kind(),
-                            std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::ConnectionReset
-                        ) {
-                            // EOF means the dashboard closed the connection
-                            user_log.info("Dashboard disconnected.")?;
-                            return Ok(());
-                        }
-                        user_log.warn(&format!(
-                            "Encountered I/O error while parsing message: {e:?}"
-                        ))?;
-                    }
+                if report_parse_error(e, user_log)? {
+                    return Ok(());
                 }
                 continue;
             }
         };
 
+        // any message at all from the commander means that it is still alive
+        if to_dash.commander()? == Some(client) {
+            deadman.touch()?;
+        }
+        if request.command == Command::Ping {
+            continue;
+        }
+        if let Command::Hello { .. } = request.command {
+            user_log.warn("Dashboard sent a second Hello. Ignoring it.")?;
+            continue;
+        }
+
         if matches!(
-            cmd,
-            Command::Actuate {
-                driver_id: _,
-                value: _,
-            }
+            request.command,
+            Command::Actuate { .. } | Command::TakeControl | Command::ReleaseControl
         ) {
-            if let Err(e) =
-                handle_command(&cmd, cmd_log_file, user_log, config, driver_lines, state)
-            {
-                user_log.critical(&format!("Encountered error while executing command: {e:?}"))?;
+            if let Err(e) = handle_command(
+                &request,
+                client,
+                cmd_log_file,
+                user_log,
+                config,
+                driver_lines,
+                arm_key,
+                readings,
+                state,
+                to_dash,
+            ) {
+                report_failure(&request.command, &e, user_log)?;
                 continue;
             }
+
+            // the deadman only watches over the commander
+            match request.command {
+                Command::TakeControl => {
+                    user_log.info(&format!("{client} is now the commander."))?;
+                    deadman.connect()?;
+                }
+                Command::ReleaseControl => {
+                    user_log.info(&format!("{client} is no longer the commander."))?;
+                    deadman.release()?;
+                }
+                _ => (),
+            }
+            user_log.debug("Finished executing command.")?;
         } else {
             // spawn thread to handle command
             #[allow(unused_must_use)]
             thread_scope.spawn(move || {
-                handle_command(&cmd, cmd_log_file, user_log, config, driver_lines, state);
-                user_log.debug("Finished executing command.");
+                if let Err(e) = handle_command(
+                    &request,
+                    client,
+                    cmd_log_file,
+                    user_log,
+                    config,
+                    driver_lines,
+                    arm_key,
+                    readings,
+                    state,
+                    to_dash,
+                ) {
+                    report_failure(&request.command, &e, user_log);
+                } else {
+                    user_log.debug("Finished executing command.");
+                }
             });
         }
+    }
+}
+
+/// Greet a newly-connected dashboard, and agree upon a protocol version and set of optional
+/// features.
+///
+/// The controller introduces itself with a `Hello` message, and then expects the dashboard to
+/// respond with a `Hello` of its own.
+/// If the dashboard speaks a different protocol version, if its first message is not a `Hello`, or
+/// if reading its first message times out, the dashboard is sent a `Refused` message explaining
+/// why.
+///
+/// Returns `true` if the dashboard is compatible, and `false` if it was refused or disconnected.
+///
+/// # Errors
+///
+/// This function will return an error if we are unable to write to the user log, or if a lock is
+/// poisoned.
+fn handshake(
+    client: ClientId,
+    to_dash: &DashChannel<impl Write, impl Write>,
+    from_dash: &mut Framer<impl BufRead>,
+    user_log: &UserLog<impl Write>,
+) -> Result<bool, ControllerError> {
+    to_dash.send_to(
+        client,
+        &Message::Hello {
+            version: PROTOCOL_VERSION,
+            features: FEATURES,
+        },
+    )?;
+
+    let reason = match from_dash.next_request() {
+        Ok(Request {
+            command: Command::Hello { version, features },
+            ..
+        }) => {
+            if version == PROTOCOL_VERSION {
+                let agreed: Vec<_> = features
+                    .into_iter()
+                    .filter(|feature| FEATURES.contains(feature))
+                    .collect();
+                user_log.info(&format!(
+                    "Dashboard speaks protocol version {version} with features {agreed:?}"
+                ))?;
+                to_dash.set_features(client, agreed)?;
+                return Ok(true);
+            }
+
+            format!(
+                "dashboard speaks protocol version {version}, but the controller speaks version {PROTOCOL_VERSION}"
+            )
+        }
+        Ok(Request { command, .. }) => {
+            format!("expected a Hello message from the dashboard, but received {command}")
+        }
+        Err(incoming::Error::Malformed(_) | incoming::Error::Oversized(_)) => String::from(
+            "expected a Hello message from the dashboard, but received a malformed message",
+        ),
+        Err(incoming::Error::Io(e))
+            if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+        {
+            String::from("no Hello message was received from the dashboard")
+        }
+        Err(incoming::Error::Io(e)) => {
+            user_log.info(&format!("Dashboard disconnected during handshake: {e}"))?;
+            return Ok(false);
+        }
+    };
+
+    user_log.warn(&format!("Refusing dashboard: {reason}"))?;
+    to_dash.send_to(client, &Message::Refused { reason: &reason })?;
+    Ok(false)
+}
+
+/// Notify the user that a message from the dashboard could not be parsed.
+///
+/// Returns `true` if the error means that the dashboard has disconnected.
+///
+/// # Errors
+///
+/// This function will return an error if we are unable to write to the user log.
+fn report_parse_error(
+    err: incoming::Error,
+    user_log: &UserLog<impl Write>,
+) -> Result<bool, ControllerError> {
+    match err {
+        incoming::Error::Malformed(s) => {
+            user_log.warn(&format!(
+                "Discarded malformed input from dashboard: {}",
+                String::from_utf8_lossy(&s)
+            ))?;
+        }
+        incoming::Error::Oversized(len) => {
+            user_log.warn(&format!(
+                "Discarded {len} bytes of input from dashboard which were too long to be a message"
+            ))?;
+        }
+        incoming::Error::Io(e) => {
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::ConnectionReset
+            ) {
+                // EOF means the dashboard closed the connection
+                user_log.info("Dashboard disconnected.")?;
+                return Ok(true);
+            }
+            user_log.warn(&format!(
+                "Encountered I/O error while parsing message: {e:?}"
+            ))?;
+        }
+    }
+
+    Ok(false)
+}
+
+/// Notify the user that a command failed to execute.
+/// The dashboard will already have been notified by `handle_command`.
+///
+/// # Errors
+///
+/// This function will return an error if we are unable to write to the user log.
+fn report_failure(
+    cmd: &Command,
+    err: &execution::Error,
+    user_log: &UserLog<impl Write>,
+) -> Result<(), ControllerError> {
+    match err {
+        execution::Error::Preempted => {
+            user_log.warn(&format!("Command {cmd} was preempted before finishing."))?;
+        }
+        execution::Error::Forbidden(_)
+        | execution::Error::ProtectedDriver(_)
+        | execution::Error::DriverOutOfBounds
+        | execution::Error::NotCommander
+        | execution::Error::ControlHeld
+        | execution::Error::NoSuchSequence(_)
+        | execution::Error::ArmKeyOff
+        | execution::Error::ArmingExpired
+        | execution::Error::NoGo
+        | execution::Error::Interlocked(_) => {
+            user_log.warn(&format!("Command {cmd} was rejected: {err}"))?;
+        }
+        _ => user_log.critical(&format!(
+            "Encountered error while executing command {cmd}: {err}"
+        ))?,
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use serde_json::Value;
+
+    use crate::outgoing::Feature;
+
+    use super::*;
+
+    /// Perform a handshake with a dashboard which sends `incoming`.
+    /// Returns the result of the handshake, every message sent to the dashboard, and whether the
+    /// `Logs` feature was agreed upon.
+    fn handshake_helper(incoming: &str) -> (bool, Vec<Value>, bool) {
+        let to_dash = DashChannel::<Vec<u8>, Vec<u8>>::new(Vec::new());
+        let client = to_dash.add_client(Vec::new()).unwrap();
+
+        let compatible = handshake(
+            client,
+            &to_dash,
+            &mut Framer::new(Cursor::new(incoming)),
+            &UserLog::new(Vec::new()),
+        )
+        .unwrap();
+
+        let logs = to_dash.has_feature(client, Feature::Logs);
+
+        (compatible, to_dash.sent(client), logs)
+    }
+
+    #[test]
+    /// Test that a compatible dashboard is accepted, and only gets features that both sides
+    /// support.
+    fn handshake_compatible() {
+        let (compatible, messages, logs) = handshake_helper(
+            r#"{
+                "type": "Hello",
+                "version": 1,
+                "features": ["Logs", "BinaryEncoding"]
+            }"#,
+        );
+
+        assert!(compatible);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["type"], "Hello");
+        assert_eq!(messages[0]["version"], PROTOCOL_VERSION);
+        assert!(logs);
+    }
+
+    #[test]
+    /// Test that a dashboard speaking a different version is refused.
+    fn handshake_wrong_version() {
+        let (compatible, messages, logs) = handshake_helper(
+            r#"{
+                "type": "Hello",
+                "version": 9000,
+                "features": ["Logs"]
+            }"#,
+        );
+
+        assert!(!compatible);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1]["type"], "Refused");
+        assert!(!logs);
+    }
+
+    #[test]
+    /// Test that a dashboard which stays silent until the handshake times out is refused.
+    fn handshake_timeout() {
+        /// A stream which always times out.
+        struct Silent;
+
+        impl io::Read for Silent {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(ErrorKind::WouldBlock.into())
+            }
+        }
+
+        let to_dash = DashChannel::<Vec<u8>, Vec<u8>>::new(Vec::new());
+        let client = to_dash.add_client(Vec::new()).unwrap();
+
+        let compatible = handshake(
+            client,
+            &to_dash,
+            &mut Framer::new(BufReader::new(Silent)),
+            &UserLog::new(Vec::new()),
+        )
+        .unwrap();
+
+        assert!(!compatible);
+        let messages = to_dash.sent(client);
+        assert_eq!(messages[1]["type"], "Refused");
+        assert_eq!(
+            messages[1]["reason"],
+            "no Hello message was received from the dashboard"
+        );
+    }
+
+    #[test]
+    /// Test that a dashboard which does not say hello is refused.
+    fn handshake_no_hello() {
+        let (compatible, messages, _) = handshake_helper(
+            r#"{
+                "type": "Ignition"
+            }"#,
+        );
+
+        assert!(!compatible);
+        assert_eq!(messages[1]["type"], "Refused");
+        assert_eq!(
+            messages[1]["reason"],
+            "expected a Hello message from the dashboard, but received ignition"
+        );
+    }
 
-        user_log.debug("Finished executing command.")?;
+    #[test]
+    /// Test that dashboards receive nothing before their `Hello`, and that only dashboards which
+    /// complete the handshake receive broadcasts.
+    fn no_broadcasts_before_hello() {
+        let to_dash = DashChannel::<Vec<u8>, Vec<u8>>::new(Vec::new());
+        let accepted = to_dash.add_client(Vec::new()).unwrap();
+        let refused = to_dash.add_client(Vec::new()).unwrap();
+        to_dash
+            .send(&Message::DriverValue { values: &[true] })
+            .unwrap();
+
+        let user_log = UserLog::new(Vec::new());
+        assert!(handshake(
+            accepted,
+            &to_dash,
+            &mut Framer::new(Cursor::new(
+                r#"{"type": "Hello", "version": 1, "features": []}"#
+            )),
+            &user_log,
+        )
+        .unwrap());
+        assert!(!handshake(
+            refused,
+            &to_dash,
+            &mut Framer::new(Cursor::new(r#"{"type": "Ignition"}"#)),
+            &user_log,
+        )
+        .unwrap());
+        to_dash
+            .send(&Message::DriverValue { values: &[false] })
+            .unwrap();
+
+        let messages = to_dash.sent(accepted);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["type"], "Hello");
+        assert_eq!(messages[1]["type"], "DriverValue");
+        assert_eq!(messages[1]["values"], serde_json::json!([false]));
+
+        let messages = to_dash.sent(refused);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["type"], "Hello");
+        assert_eq!(messages[1]["type"], "Refused");
     }
 }
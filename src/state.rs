@@ -1,6 +1,14 @@
-use std::sync::RwLock;
+use std::{
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Condvar, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 /// The set of errors that can be caused from working with a `Guard`.
 pub enum Error {
     /// The guard's lock was poisoned. This implies a panicked thread owned a lock.
@@ -18,17 +26,47 @@ pub enum Error {
 /// state.
 pub struct Guard {
     /// The current state.
-    state: RwLock<State>,
+    state: Mutex<State>,
+    /// A condition variable which is notified every time the state changes.
+    /// Threads which are sleeping through a procedure wait on this so that they can be woken up
+    /// early if they are preempted.
+    changed: Condvar,
+    /// The channels of every observer which has subscribed to this guard.
+    /// Each time the state changes, a `Transition` is sent down each of these channels.
+    observers: Mutex<Vec<Sender<Transition>>>,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// A record of a single change in state.
+pub struct Transition {
+    /// The state before the transition.
+    pub from: State,
+    /// The state after the transition.
+    pub to: State,
+    /// The time at which the transition occurred.
+    pub time: SystemTime,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 /// The set of all states the engine controller can be in.
 pub enum State {
     /// The engine is in standby - passively logging and awating commands.
-    /// This state can only be reached from the `PostIgnite` and `EStopping` states.
+    /// This state can only be reached from the `Armed`, `PostIgnite`, `Sequencing`, `Countdown`,
+    /// `Hold` and `EStopping` states.
     Standby,
-    /// The engine is preparing to ignite in the upcoming seconds.
+    /// The controller has been armed, and will accept an ignition command.
     /// This state can only be reached from the `Standby` state.
+    /// If no ignition begins before the arming times out, the controller returns to `Standby`.
+    Armed,
+    /// The controller is counting down to an ignition.
+    /// This state can only be reached from the `Armed` and `Hold` states.
+    /// Data logging should be fast here, since configured countdown events may actuate drivers.
+    Countdown,
+    /// The countdown is frozen, with the drivers left as they were when the hold began.
+    /// This state can only be reached from the `Countdown` state.
+    Hold,
+    /// The engine is preparing to ignite in the upcoming seconds.
+    /// This state can only be reached from the `Armed` and `Countdown` states.
     /// During this phase, data logging threads should increase their logging speed.
     PreIgnite,
     /// The engine is currently ignited.
@@ -40,6 +78,11 @@ pub enum State {
     /// Since interesting things might still happen in the post-ignition phase, data logging should
     /// still be fast here.
     PostIgnite,
+    /// The controller is running one of the named auxiliary sequences from the configuration,
+    /// such as a purge or a fill.
+    /// This state can only be reached from the `Standby` state.
+    /// Data logging should be fast here, since the sequence is probably being watched closely.
+    Sequencing,
     /// An emergency stop command has just been called.
     /// This state is reachable from any other state except the `Quit` state.
     /// Data logging should be fast, since anything that is worth e-stopping over is probably very
@@ -57,10 +100,25 @@ impl Guard {
     /// Initializes its state to the value of `state`.
     pub fn new(state: State) -> Guard {
         Guard {
-            state: RwLock::new(state),
+            state: Mutex::new(state),
+            changed: Condvar::new(),
+            observers: Mutex::new(Vec::new()),
         }
     }
 
+    /// Subscribe to changes in the state of this guard.
+    /// Every transition made after subscribing will be sent on the returned channel, in the order
+    /// in which the transitions occurred.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error in the case that an internal lock of this guard is poisoned.
+    pub fn subscribe(&self) -> Result<Receiver<Transition>, Error> {
+        let (tx, rx) = channel();
+        self.observers.lock().map_err(|_| Error::Poison)?.push(tx);
+        Ok(rx)
+    }
+
     /// Get the status of this guard.
     /// This operation is blocking.
     ///
@@ -68,7 +126,7 @@ impl Guard {
     ///
     /// Will return an error in the case that the internal lock of this guard is poisoned.
     pub fn status(&self) -> Result<State, Error> {
-        match self.state.read() {
+        match self.state.lock() {
             Ok(s) => Ok(*s),
             Err(_) => Err(Error::Poison),
         }
@@ -83,26 +141,169 @@ impl Guard {
     /// If `new_state` is not reachable from the current state, an
     /// `Err(ControllerError::IllegalTransition)` will be returned.
     pub fn move_to(&self, new_state: State) -> Result<(), Error> {
-        let mut write_guard = self.state.write().map_err(|_| Error::Poison)?;
-        let old_state = *write_guard;
-
-        // determine whether the transition is valid
-        let valid_transition = match new_state {
-            State::Standby => old_state == State::EStopping || old_state == State::PostIgnite,
-            State::PreIgnite | State::Quit => old_state == State::Standby,
-            State::Ignite => old_state == State::PreIgnite,
-            State::PostIgnite => old_state == State::Ignite,
-            State::EStopping => old_state != State::Quit,
-        };
+        let mut lock_guard = self.state.lock().map_err(|_| Error::Poison)?;
+        let old_state = *lock_guard;
 
-        if !valid_transition {
+        if !old_state.can_move_to(new_state) {
             return Err(Error::IllegalTransition {
                 from: old_state,
                 to: new_state,
             });
         }
 
-        *write_guard = new_state;
+        self.transition(&mut lock_guard, new_state)
+    }
+
+    /// Move this guard from the state `from` into the state `to`, but only if the guard is still in
+    /// `from`.
+    ///
+    /// This is used by procedures which advance through their own sequence of states, so that they
+    /// cannot clobber a state which was set by another thread in the meantime (for instance, if an
+    /// emergency stop happened partway through an ignition).
+    ///
+    /// Returns `true` if the transition was performed, and `false` if the guard was no longer in
+    /// `from`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an `Err(ControllerError::Poison)` in the case that an internal
+    /// lock is poisoned.
+    /// If `to` is not reachable from `from`, an `Err(ControllerError::IllegalTransition)` will be
+    /// returned.
+    pub fn advance(&self, from: State, to: State) -> Result<bool, Error> {
+        let mut lock_guard = self.state.lock().map_err(|_| Error::Poison)?;
+        if *lock_guard != from {
+            return Ok(false);
+        }
+
+        if !from.can_move_to(to) {
+            return Err(Error::IllegalTransition { from, to });
+        }
+
+        self.transition(&mut lock_guard, to)?;
+        Ok(true)
+    }
+
+    /// Sleep for `duration`, waking up early if this guard leaves the state `state`.
+    /// This operation is blocking.
+    ///
+    /// Returns `true` if the guard was still in `state` after sleeping, and `false` if the sleep
+    /// was interrupted by a state change.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error in the case that the internal lock of this guard is poisoned.
+    pub fn sleep_in(&self, state: State, duration: Duration) -> Result<bool, Error> {
+        let lock_guard = self.state.lock().map_err(|_| Error::Poison)?;
+        let (lock_guard, _) = self
+            .changed
+            .wait_timeout_while(lock_guard, duration, |s| *s == state)
+            .map_err(|_| Error::Poison)?;
+
+        Ok(*lock_guard == state)
+    }
+
+    /// Overwrite the state held by `lock_guard` with `new_state`, waking up any sleepers and
+    /// notifying all observers.
+    /// The caller must already have checked that the transition is legal.
+    ///
+    /// Observers are notified while the state lock is still held, so that they always receive
+    /// transitions in the order that they happened.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error in the case that the observer lock of this guard is poisoned.
+    fn transition(&self, lock_guard: &mut State, new_state: State) -> Result<(), Error> {
+        let transition = Transition {
+            from: *lock_guard,
+            to: new_state,
+            time: SystemTime::now(),
+        };
+        *lock_guard = new_state;
+        self.changed.notify_all();
+
+        // forget about any observers who have hung up
+        self.observers
+            .lock()
+            .map_err(|_| Error::Poison)?
+            .retain(|tx| tx.send(transition).is_ok());
+
         Ok(())
     }
 }
+
+impl State {
+    /// Determine whether a transition from this state into `new_state` is legal.
+    fn can_move_to(self, new_state: State) -> bool {
+        match new_state {
+            State::Standby => matches!(
+                self,
+                State::Armed
+                    | State::EStopping
+                    | State::PostIgnite
+                    | State::Sequencing
+                    | State::Countdown
+                    | State::Hold
+            ),
+            State::Countdown => matches!(self, State::Armed | State::Hold),
+            State::Hold => self == State::Countdown,
+            State::PreIgnite => matches!(self, State::Armed | State::Countdown),
+            State::Armed | State::Sequencing | State::Quit => self == State::Standby,
+            State::Ignite => self == State::PreIgnite,
+            State::PostIgnite => self == State::Ignite,
+            State::EStopping => self != State::Quit,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test that observers are told about every transition, in order.
+    fn observe_transitions() {
+        let guard = Guard::new(State::Armed);
+        let rx = guard.subscribe().unwrap();
+
+        guard.move_to(State::PreIgnite).unwrap();
+        assert!(guard.advance(State::PreIgnite, State::Ignite).unwrap());
+        // a failed advance is not a transition
+        assert!(!guard.advance(State::PreIgnite, State::Ignite).unwrap());
+        // neither is an illegal move
+        assert!(guard.move_to(State::Quit).is_err());
+        guard.move_to(State::EStopping).unwrap();
+
+        let transitions: Vec<(State, State)> = rx.try_iter().map(|t| (t.from, t.to)).collect();
+        assert_eq!(
+            transitions,
+            [
+                (State::Armed, State::PreIgnite),
+                (State::PreIgnite, State::Ignite),
+                (State::Ignite, State::EStopping),
+            ]
+        );
+    }
+
+    #[test]
+    /// Test that an observer hanging up does not break the guard.
+    fn observer_hangs_up() {
+        let guard = Guard::new(State::Standby);
+        drop(guard.subscribe().unwrap());
+        let rx = guard.subscribe().unwrap();
+
+        guard.move_to(State::Quit).unwrap();
+        assert_eq!(rx.try_recv().unwrap().to, State::Quit);
+    }
+
+    #[test]
+    /// Test that an ignition can only begin once the controller is armed.
+    fn ignition_requires_arming() {
+        let guard = Guard::new(State::Standby);
+        assert!(guard.move_to(State::PreIgnite).is_err());
+        assert!(guard.move_to(State::Countdown).is_err());
+
+        guard.move_to(State::Armed).unwrap();
+        guard.move_to(State::Countdown).unwrap();
+    }
+}